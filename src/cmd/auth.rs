@@ -0,0 +1,61 @@
+use anyhow::{Context, Result};
+use clap::Args;
+use log::info;
+use std::borrow::Cow;
+use twitch_gift_farm::{Account, Config};
+
+#[derive(Debug, Args)]
+pub struct Opts {
+    /// Login name of the account
+    #[arg(short, long)]
+    username: String,
+
+    /// OAuth token with the `chat:read` scope (and `chat:edit` to send thanks,
+    /// `user:manage:whispers` to whisper gifters and `user:read:follows` for
+    /// `discover follows`), with or without the `oauth:` prefix
+    #[arg(short, long)]
+    token: String,
+}
+
+pub fn run(opts: Opts) -> Result<()> {
+    let mut config = if Config::path().exists() {
+        Config::load()?
+    } else {
+        Config::default()
+    };
+
+    let token = if opts.token.starts_with("oauth:") {
+        opts.token
+    } else {
+        format!("oauth:{}", opts.token)
+    };
+
+    let username = opts.username.to_lowercase();
+    let account = match config
+        .accounts
+        .iter_mut()
+        .position(|account| account.username == username)
+    {
+        Some(index) => &mut config.accounts[index],
+        None => {
+            config.accounts.push(Account {
+                username: Cow::Owned(username.clone()),
+                ..Account::default()
+            });
+            config.accounts.last_mut().unwrap()
+        }
+    };
+
+    account.token = Cow::Owned(token);
+    account.user_config()?;
+
+    config.save().context("Could not save credentials")?;
+
+    info!(
+        "Saved credentials for {} to {}",
+        username,
+        Config::path().display()
+    );
+
+    Ok(())
+}
@@ -0,0 +1,115 @@
+use anyhow::Result;
+use chrono::Local;
+use clap::{Args, Subcommand};
+use log::{info, warn};
+use twitch_gift_farm::{
+    registry::{Registry, Source},
+    Config,
+};
+
+#[derive(Debug, Args)]
+pub struct Opts {
+    /// Manage the channels of this account instead of the shared channels
+    #[arg(short, long)]
+    account: Option<String>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Print all configured channels
+    List,
+    /// Add channels to the config
+    Add { channels: Vec<String> },
+    /// Remove channels from the config
+    Remove { channels: Vec<String> },
+    /// Print all channels that were removed by pruning
+    Pruned,
+    /// Put pruned channels back where they were removed from
+    Restore { channels: Vec<String> },
+    /// Move the channels of a config written before the channel registry existed into the
+    /// registry
+    Migrate,
+}
+
+pub fn run(opts: Opts) -> Result<()> {
+    let mut config = Config::load()?;
+    let account = opts.account.as_deref();
+    if let Some(account) = account {
+        // fail early on typos instead of creating channels for an unknown account
+        config.account_mut(account)?;
+    }
+    let mut registry = Registry::open()?;
+
+    match opts.command {
+        Command::List => {
+            for channel in registry.list(account)? {
+                println!("{}", channel);
+            }
+        }
+
+        Command::Add { channels } => {
+            let channels: Vec<String> = channels.iter().map(|c| normalize(c)).collect();
+            let added = registry.add(account, &channels, Source::Manual)?;
+
+            info!(
+                "Added {} channels for a total of {}",
+                added,
+                registry.list(account)?.len()
+            );
+        }
+
+        Command::Remove { channels } => {
+            let channels: Vec<String> = channels.iter().map(|c| normalize(c)).collect();
+            let removed = registry.remove(account, &channels)?;
+
+            info!(
+                "Removed {} channels for a total of {}",
+                removed,
+                registry.list(account)?.len()
+            );
+        }
+
+        Command::Pruned => {
+            for pruned in registry.pruned()? {
+                println!(
+                    "{} (from {}, {}): {}",
+                    pruned.channel,
+                    pruned.account.as_deref().unwrap_or("shared"),
+                    pruned.time.with_timezone(&Local).format("%Y-%m-%d %H:%M"),
+                    pruned.reason
+                );
+            }
+        }
+
+        Command::Restore { channels } => {
+            for channel in channels.iter().map(|c| normalize(c)) {
+                let restored = registry.restore(&channel)?;
+                if restored.is_empty() {
+                    warn!("{} was not pruned", channel);
+                }
+
+                for pruned in restored {
+                    info!(
+                        "Restored {} to the {} channels",
+                        channel,
+                        pruned.account.as_deref().unwrap_or("shared")
+                    );
+                }
+            }
+        }
+
+        Command::Migrate => {
+            let moved = config.move_channels(&mut registry)?;
+            info!("Moved {} channels from the config to the registry", moved);
+        }
+    }
+
+    Ok(())
+}
+
+fn normalize(channel: &str) -> String {
+    channel.trim_start_matches('#').to_lowercase()
+}
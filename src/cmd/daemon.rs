@@ -0,0 +1,49 @@
+use anyhow::{Context, Result};
+use daemonize::Daemonize;
+use flexi_logger::{LogTarget, Logger};
+use std::{
+    env,
+    fs::{self, OpenOptions},
+};
+use twitch_gift_farm::{
+    config::{project_dirs, runtime_dir},
+    plain_format,
+};
+
+/// Fork into the background and write the PID file, then return `logger` writing to a file.
+///
+/// Has to be called before any threads are started, only the calling thread survives the fork.
+pub fn start(logger: Logger) -> Result<Logger> {
+    let pid_file = runtime_dir().join("tgf.pid");
+    let logs = project_dirs().data_dir().join("logs");
+    fs::create_dir_all(runtime_dir()).context("Could not create runtime directory")?;
+    fs::create_dir_all(&logs).context("Could not create log directory")?;
+
+    // panics do not go through the logger
+    let stderr = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(logs.join("stderr.log"))
+        .context("Could not open the log file for stderr")?;
+
+    eprintln!("Farming in the background, logging to {}", logs.display());
+    Daemonize::new()
+        .pid_file(&pid_file)
+        // relative paths in the arguments keep working
+        .working_directory(env::current_dir()?)
+        .stderr(stderr)
+        .start()
+        .with_context(|| {
+            format!(
+                "Could not start in the background, is {} locked by a running farm?",
+                pid_file.display()
+            )
+        })?;
+
+    Ok(logger
+        .log_target(LogTarget::File)
+        .directory(logs)
+        .suppress_timestamp()
+        .append()
+        .format_for_files(plain_format))
+}
@@ -0,0 +1,245 @@
+use anyhow::{Context, Result};
+use clap::{Args, ValueEnum};
+use log::info;
+use std::collections::HashSet;
+use twitch_gift_farm::{
+    discovery::{get_streams, get_team_members, select, Selection},
+    helix::Helix,
+    registry::{Registry, Source},
+    runtime, Config,
+};
+
+#[derive(Debug, Args)]
+pub struct Opts {
+    /// Add the channels to this account instead of the shared channels
+    #[arg(short, long)]
+    account: Option<String>,
+
+    /// Where to look for channels
+    #[arg(long, value_enum, default_value = "streams")]
+    from: Origin,
+
+    /// Print the channels that would be added instead of adding them
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Add at most this many new channels
+    #[arg(long)]
+    max_new: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Origin {
+    /// Channels streaming the top games right now
+    Streams,
+    /// Members of the teams in the discovery section of the config
+    Teams,
+    /// Channels the account follows, or every account without `--account`. The tokens need the
+    /// `user:read:follows` scope.
+    Follows,
+}
+
+pub fn run(opts: Opts) -> Result<()> {
+    // loading the config moves channels of old configs into the registry
+    let mut config = Config::load()?;
+    if let Some(account) = &opts.account {
+        config.account_mut(account)?;
+    }
+
+    match opts.from {
+        Origin::Streams => streams(&opts, &config),
+        Origin::Teams => teams(&opts, &config),
+        Origin::Follows => follows(&opts, &config),
+    }
+}
+
+fn streams(opts: &Opts, config: &Config) -> Result<()> {
+    let account = opts.account.as_deref();
+
+    let discovered = runtime::block_on(get_streams(&config.discovery))?;
+    let channels = &discovered.channels;
+
+    info!("Found {} channels currently streaming", channels.len());
+
+    let mut registry = Registry::open()?;
+    let selection = select(
+        &registry,
+        account,
+        opts.max_new,
+        &config.discovery,
+        channels,
+    )?;
+
+    let found = [
+        ("games scanned", discovered.games),
+        ("pages fetched", discovered.pages),
+        ("streams found", discovered.streams),
+        ("channels found", channels.len()),
+    ];
+
+    if opts.dry_run {
+        for channel in &selection.new {
+            println!("{}", channel);
+        }
+
+        summary(&found, selection.new.len(), &selection);
+        return Ok(());
+    }
+
+    let added = registry.add(account, &selection.new, Source::Discovery)?;
+    registry.live(channels)?;
+
+    info!(
+        "Saving {} new channels for a total of {}",
+        added,
+        registry.list(account)?.len()
+    );
+    summary(&found, added, &selection);
+
+    Ok(())
+}
+
+fn teams(opts: &Opts, config: &Config) -> Result<()> {
+    let account = opts.account.as_deref();
+
+    if config.discovery.teams.is_empty() {
+        info!("No teams in the discovery section of the config");
+        return Ok(());
+    }
+
+    let teams = runtime::block_on(get_team_members(&config.discovery))?;
+
+    // channels can be in several teams, they are credited to the first one
+    let mut seen = HashSet::new();
+    let mut members = Vec::new();
+    for (team, channels) in &teams {
+        for channel in channels {
+            if seen.insert(channel.as_str()) {
+                members.push((team.as_str(), channel.clone()));
+            }
+        }
+    }
+    let channels: Vec<String> = members.iter().map(|(_, channel)| channel.clone()).collect();
+
+    info!("Found {} channels in {} teams", channels.len(), teams.len());
+
+    let mut registry = Registry::open()?;
+    let selection = select(
+        &registry,
+        account,
+        opts.max_new,
+        &config.discovery,
+        &channels,
+    )?;
+
+    let found = [
+        ("teams scanned", teams.len()),
+        ("channels found", channels.len()),
+    ];
+
+    if opts.dry_run {
+        for channel in &selection.new {
+            println!("{}", channel);
+        }
+
+        summary(&found, selection.new.len(), &selection);
+        return Ok(());
+    }
+
+    let new: HashSet<&str> = selection.new.iter().map(String::as_str).collect();
+    let mut added = 0;
+    for (team, _) in &teams {
+        let channels: Vec<String> = members
+            .iter()
+            .filter(|(name, channel)| name == team && new.contains(channel.as_str()))
+            .map(|(_, channel)| channel.clone())
+            .collect();
+        added += registry.add(account, &channels, Source::Team(team.clone()))?;
+    }
+
+    info!(
+        "Saving {} new channels for a total of {}",
+        added,
+        registry.list(account)?.len()
+    );
+    summary(&found, added, &selection);
+
+    Ok(())
+}
+
+fn follows(opts: &Opts, config: &Config) -> Result<()> {
+    let account = opts.account.as_deref();
+
+    let accounts = config
+        .accounts
+        .iter()
+        .filter(|a| account.map_or(true, |username| a.username == username));
+
+    let mut seen = HashSet::new();
+    let mut channels = Vec::new();
+    let mut scanned = 0;
+    for a in accounts {
+        let followed = runtime::block_on(async {
+            Helix::with_user_token(&a.token)
+                .await?
+                .followed_channels()
+                .await
+        })
+        .with_context(|| format!("Could not get the channels {} follows", a.username))?;
+
+        info!("{} follows {} channels", a.username, followed.len());
+        scanned += 1;
+        channels.extend(
+            followed
+                .into_iter()
+                .filter(|channel| seen.insert(channel.clone())),
+        );
+    }
+
+    let mut registry = Registry::open()?;
+    let selection = select(
+        &registry,
+        account,
+        opts.max_new,
+        &config.discovery,
+        &channels,
+    )?;
+
+    let found = [
+        ("accounts scanned", scanned),
+        ("channels found", channels.len()),
+    ];
+
+    if opts.dry_run {
+        for channel in &selection.new {
+            println!("{}", channel);
+        }
+
+        summary(&found, selection.new.len(), &selection);
+        return Ok(());
+    }
+
+    let added = registry.add(account, &selection.new, Source::Follows)?;
+
+    info!(
+        "Saving {} new channels for a total of {}",
+        added,
+        registry.list(account)?.len()
+    );
+    summary(&found, added, &selection);
+
+    Ok(())
+}
+
+/// Print what the discovery run found on stderr, stdout only gets channels
+fn summary(found: &[(&str, usize)], new: usize, selection: &Selection) {
+    eprintln!();
+    for (label, count) in found {
+        eprintln!("  {:<20} {:>8}", label, count);
+    }
+    eprintln!("  {:<20} {:>8}", "new channels", new);
+    eprintln!("  {:<20} {:>8}", "duplicates skipped", selection.duplicates);
+    if selection.trimmed > 0 {
+        eprintln!("  {:<20} {:>8}", "over the cap", selection.trimmed);
+    }
+}
@@ -0,0 +1,81 @@
+use anyhow::{anyhow, Result};
+use log::{error, info, warn};
+use smol::future::FutureExt;
+use std::time::Duration;
+use twitch_gift_farm::{
+    connector::{connect, Endpoint},
+    registry::Registry,
+    runtime::{self, sleep},
+    Account, Config,
+};
+
+pub fn run() -> Result<()> {
+    let path = Config::path();
+    if !Config::is_from_env() && !path.exists() {
+        error!("No config file at {}", path.display());
+        return Err(anyhow!("Run `auth` to create a config"));
+    }
+
+    let config = Config::load()?;
+    info!("Config from {} is valid", Config::origin());
+
+    if config.accounts.is_empty() {
+        error!("No accounts configured");
+        return Err(anyhow!("Run `auth` to add an account"));
+    }
+
+    if config.replicas == 0 {
+        warn!("replicas is 0, no account will join the shared channels");
+    }
+
+    let registry = Registry::open()?;
+    info!("Found channel registry at {}", Registry::path().display());
+
+    let mut failed = 0;
+    for (index, account) in config.accounts.iter().enumerate() {
+        if let Err(err) = check_account(
+            &config.irc,
+            account,
+            registry.channels_for(&config, index)?.len(),
+        ) {
+            error!("{}: {:#}", account.username, err);
+            failed += 1;
+        }
+    }
+
+    if failed > 0 {
+        return Err(anyhow!("{} accounts have problems", failed));
+    }
+
+    Ok(())
+}
+
+fn check_account(endpoint: &Endpoint, account: &Account, joined: usize) -> Result<()> {
+    let user_config = account
+        .user_config()
+        .map_err(|err| anyhow!("{:#}, run `auth` to fix the credentials", err))?;
+
+    if joined == 0 {
+        warn!(
+            "{}: No channels configured, run `discover` or `channels add`",
+            account.username
+        );
+    } else {
+        info!("{}: {} channels to join", account.username, joined);
+    }
+
+    info!("{}: Connecting to Twitch", account.username);
+    runtime::block_on(
+        async {
+            connect(endpoint, &user_config).await?;
+            Ok(())
+        }
+        .or(async {
+            sleep(Duration::from_secs(30)).await;
+            Err(anyhow!("Timed out connecting to Twitch"))
+        }),
+    )?;
+    info!("{}: Connected to Twitch", account.username);
+
+    Ok(())
+}
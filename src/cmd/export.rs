@@ -0,0 +1,204 @@
+use anyhow::{Context, Result};
+use chrono::{Local, NaiveDate, TimeZone};
+use clap::{Args, ValueEnum};
+use log::info;
+use serde::Serialize;
+use std::{
+    fs::File,
+    io::{self, Write},
+    path::PathBuf,
+};
+use twitch_gift_farm::history::{Gift, GiftKind, History, Tier};
+
+#[derive(Debug, Args)]
+pub struct Opts {
+    #[arg(short, long, value_enum, default_value_t = Format::Csv)]
+    format: Format,
+
+    /// Only export gifts received on or after this day
+    #[arg(long)]
+    since: Option<NaiveDate>,
+
+    /// Only export gifts received before this day
+    #[arg(long)]
+    until: Option<NaiveDate>,
+
+    /// Write to this file instead of stdout
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Format {
+    Csv,
+    /// Needs the `parquet` feature
+    Parquet,
+}
+
+/// One line of the exported table
+#[derive(Debug, Serialize)]
+struct Row<'a> {
+    time: String,
+    account: &'a str,
+    channel: &'a str,
+    gifter: &'a str,
+    kind: GiftKind,
+    tier: Tier,
+    plan_name: &'a str,
+    months: u64,
+    community_gift: Option<u64>,
+    prior_gifter: Option<&'a str>,
+}
+
+impl<'a> From<&'a Gift> for Row<'a> {
+    fn from(gift: &'a Gift) -> Self {
+        Self {
+            time: gift.time.to_rfc3339(),
+            account: &gift.account,
+            channel: &gift.channel,
+            gifter: &gift.gifter,
+            kind: gift.kind,
+            tier: gift.tier,
+            plan_name: &gift.plan_name,
+            months: gift.months,
+            community_gift: gift.community_gift,
+            prior_gifter: gift.prior_gifter.as_deref(),
+        }
+    }
+}
+
+pub fn run(opts: Opts) -> Result<()> {
+    let since = opts.since.map(local_midnight);
+    let until = opts.until.map(local_midnight);
+
+    let gifts: Vec<Gift> = History::load()?
+        .into_iter()
+        .filter(|gift| since.map_or(true, |since| gift.time >= since))
+        .filter(|gift| until.map_or(true, |until| gift.time < until))
+        .collect();
+
+    let writer: Box<dyn Write + Send> = match &opts.output {
+        Some(path) => Box::new(
+            File::create(path).with_context(|| format!("Could not create {}", path.display()))?,
+        ),
+        None => Box::new(io::stdout()),
+    };
+
+    match opts.format {
+        Format::Csv => write_csv(writer, &gifts)?,
+        #[cfg(feature = "parquet")]
+        Format::Parquet => write_parquet(writer, &gifts)?,
+        #[cfg(not(feature = "parquet"))]
+        Format::Parquet => {
+            return Err(anyhow::anyhow!(
+                "tgf was built without parquet support, rebuild it with `--features parquet`"
+            ))
+        }
+    }
+
+    if let Some(path) = &opts.output {
+        info!("Exported {} gifts to {}", gifts.len(), path.display());
+    }
+
+    Ok(())
+}
+
+fn write_csv(writer: impl Write, gifts: &[Gift]) -> Result<()> {
+    let mut writer = csv::Writer::from_writer(writer);
+    for gift in gifts {
+        writer.serialize(Row::from(gift))?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+#[cfg(feature = "parquet")]
+fn write_parquet(writer: impl Write + Send, gifts: &[Gift]) -> Result<()> {
+    use arrow_array::{ArrayRef, RecordBatch, StringArray, TimestampMillisecondArray, UInt64Array};
+    use arrow_schema::{DataType, Field, Schema, TimeUnit};
+    use parquet::{arrow::ArrowWriter, basic::Compression, file::properties::WriterProperties};
+    use std::sync::Arc;
+
+    let strings = |field: fn(&Gift) -> &str| -> ArrayRef {
+        Arc::new(gifts.iter().map(field).map(Some).collect::<StringArray>())
+    };
+
+    let schema = Schema::new(vec![
+        Field::new(
+            "time",
+            DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())),
+            false,
+        ),
+        Field::new("account", DataType::Utf8, false),
+        Field::new("channel", DataType::Utf8, false),
+        Field::new("gifter", DataType::Utf8, false),
+        Field::new("kind", DataType::Utf8, false),
+        Field::new("tier", DataType::Utf8, false),
+        Field::new("plan_name", DataType::Utf8, false),
+        Field::new("months", DataType::UInt64, false),
+        Field::new("recipient_months", DataType::UInt64, true),
+        Field::new("community_gift", DataType::UInt64, true),
+        Field::new("prior_gifter", DataType::Utf8, true),
+    ]);
+
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(
+            TimestampMillisecondArray::from(
+                gifts
+                    .iter()
+                    .map(|gift| gift.time.timestamp_millis())
+                    .collect::<Vec<_>>(),
+            )
+            .with_timezone("UTC"),
+        ),
+        strings(|gift| &gift.account),
+        strings(|gift| &gift.channel),
+        strings(|gift| &gift.gifter),
+        strings(|gift| gift.kind.as_str()),
+        strings(|gift| gift.tier.as_str()),
+        strings(|gift| &gift.plan_name),
+        Arc::new(
+            gifts
+                .iter()
+                .map(|gift| gift.months)
+                .collect::<UInt64Array>(),
+        ),
+        Arc::new(
+            gifts
+                .iter()
+                .map(|gift| gift.recipient_months)
+                .collect::<UInt64Array>(),
+        ),
+        Arc::new(
+            gifts
+                .iter()
+                .map(|gift| gift.community_gift)
+                .collect::<UInt64Array>(),
+        ),
+        Arc::new(
+            gifts
+                .iter()
+                .map(|gift| gift.prior_gifter.as_deref())
+                .collect::<StringArray>(),
+        ),
+    ];
+
+    let batch = RecordBatch::try_new(Arc::new(schema), columns)?;
+    let properties = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .build();
+
+    let mut writer = ArrowWriter::try_new(writer, batch.schema(), Some(properties))?;
+    writer.write(&batch)?;
+    writer.close()?;
+
+    Ok(())
+}
+
+fn local_midnight(date: NaiveDate) -> chrono::DateTime<Local> {
+    Local
+        .from_local_datetime(&date.and_hms_opt(0, 0, 0).unwrap())
+        .earliest()
+        .unwrap()
+}
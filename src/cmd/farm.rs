@@ -0,0 +1,142 @@
+use anyhow::{Context, Result};
+use clap::{Args, ValueEnum};
+use log::error;
+use serde::Serialize;
+use std::{
+    future::Future,
+    io::{self, Write},
+    path::PathBuf,
+    process,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use twitch_gift_farm::{
+    connector::Simulation,
+    farm::{Farm, FarmBuilder},
+    runtime, Config,
+};
+
+#[derive(Debug, Args)]
+pub struct Opts {
+    /// Farm even if another process is already farming with the same account
+    #[arg(long)]
+    force: bool,
+
+    /// What to print on stdout, logs always go to stderr
+    #[arg(short, long, value_enum, default_value_t = Output::Log)]
+    output: Output,
+
+    /// Feed raw IRC lines recorded from Twitch to the first account instead of connecting.
+    /// Nothing is sent, stored or notified, only logged.
+    #[arg(long, value_name = "FILE")]
+    replay: Option<PathBuf>,
+
+    /// Append every raw IRC line Twitch sends to FILE, prefixed with a timestamp.
+    /// The file can be fed back with --replay.
+    #[arg(long, value_name = "FILE")]
+    capture: Option<PathBuf>,
+
+    /// Feed made up gifts to the first account instead of connecting, to try out notifications
+    /// and scripts. The gifts are stored like real ones, only chat messages are not sent.
+    #[arg(long, conflicts_with_all = ["replay", "capture"])]
+    simulate: bool,
+
+    /// Simulated gifts per minute
+    #[arg(long, default_value_t = 6.0, requires = "simulate", value_parser = parse_rate)]
+    rate: f64,
+
+    /// Number of made up channels the simulated gifts come from
+    #[arg(long, default_value_t = 3, requires = "simulate")]
+    fake_channels: usize,
+
+    /// Run as a Windows service that logs to the event log. The service has to be created with
+    /// `sc create tgf binPath= "C:\path\to\tgf.exe farm --service"`.
+    #[cfg(windows)]
+    #[arg(long, conflicts_with_all = ["replay", "simulate"])]
+    pub service: bool,
+
+    /// Fork into the background, write the process id to tgf.pid in the runtime directory and
+    /// log to logs/tgf.log in the data directory, panics go to logs/stderr.log next to it
+    #[cfg(unix)]
+    #[arg(long, conflicts_with_all = ["replay", "simulate"])]
+    pub daemon: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Output {
+    /// Only log
+    Log,
+    /// Print every gift event as one JSON object per line
+    Ndjson,
+}
+
+pub fn run(opts: Opts) -> Result<()> {
+    #[cfg(windows)]
+    if opts.service {
+        return super::service::run(opts);
+    }
+
+    let farm = builder(opts)?.stop_on(stop_signal()?).build()?;
+    runtime::block_on(farm.run())
+}
+
+/// Completes on the first Ctrl-C or SIGTERM, the second one exits right away
+fn stop_signal() -> Result<impl Future<Output = ()>> {
+    static STOPPING: AtomicBool = AtomicBool::new(false);
+
+    let (sender, receiver) = smol::channel::bounded(1);
+    ctrlc::set_handler(move || {
+        if STOPPING.swap(true, Ordering::SeqCst) {
+            process::exit(130);
+        }
+        sender.try_send(()).ok();
+    })
+    .context("Could not handle Ctrl-C")?;
+
+    Ok(async move {
+        receiver.recv().await.ok();
+    })
+}
+
+/// The farm configured by `opts`
+pub fn builder(opts: Opts) -> Result<FarmBuilder> {
+    let mut builder = Farm::builder(Config::load()?).force(opts.force);
+    if opts.output == Output::Ndjson {
+        builder = builder.on_gift(print_json);
+    }
+    if let Some(path) = opts.replay {
+        builder = builder.replay(path);
+    }
+    if let Some(path) = opts.capture {
+        builder = builder.capture(path);
+    }
+    if opts.simulate {
+        builder = builder.simulate(Simulation {
+            rate: opts.rate,
+            channels: opts.fake_channels,
+        });
+    }
+
+    Ok(builder)
+}
+
+/// A rate of gifts that is above 0, so there is a time between them
+fn parse_rate(rate: &str) -> Result<f64, String> {
+    match rate.parse::<f64>() {
+        Ok(rate) if rate.is_finite() && rate > 0.0 => Ok(rate),
+        Ok(_) => Err("must be a number above 0".to_string()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+/// Print `event` as a single line of JSON on stdout
+fn print_json(event: &impl Serialize) {
+    match serde_json::to_string(event) {
+        Ok(json) => {
+            let mut stdout = io::stdout().lock();
+            if let Err(err) = writeln!(stdout, "{}", json).and_then(|_| stdout.flush()) {
+                error!("Could not print event: {}", err);
+            }
+        }
+        Err(err) => error!("Could not serialize event: {}", err),
+    }
+}
@@ -0,0 +1,15 @@
+pub mod auth;
+pub mod channels;
+#[cfg(unix)]
+pub mod daemon;
+pub mod discover;
+pub mod doctor;
+pub mod export;
+pub mod farm;
+pub mod prune;
+pub mod report;
+pub mod scout;
+#[cfg(windows)]
+pub mod service;
+pub mod simulate_event;
+pub mod stats;
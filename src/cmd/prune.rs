@@ -0,0 +1,57 @@
+use anyhow::Result;
+use clap::Args;
+use log::info;
+use twitch_gift_farm::{discovery::check_channels, registry::Registry, runtime, Config};
+
+#[derive(Debug, Args)]
+pub struct Opts {
+    /// Remove channels that were offline or deleted for this many days
+    #[arg(long, default_value_t = 30)]
+    days: i64,
+
+    /// Print the channels that would be removed instead of removing them
+    #[arg(long)]
+    dry_run: bool,
+}
+
+pub fn run(opts: Opts) -> Result<()> {
+    let config = Config::load()?;
+    let mut registry = Registry::open()?;
+
+    let channels = registry.active()?;
+    info!("Checking {} channels", channels.len());
+
+    let checked = runtime::block_on(check_channels(&config.discovery, &channels))?;
+    let live: Vec<&String> = checked.live.iter().collect();
+    let missing: Vec<&String> = channels
+        .iter()
+        .filter(|channel| !checked.existing.contains(*channel))
+        .collect();
+    info!(
+        "{} channels are live, {} do not exist",
+        live.len(),
+        missing.len()
+    );
+
+    registry.checked(&channels)?;
+    registry.live(&live)?;
+    registry.missing(&missing)?;
+
+    let stale = registry.stale(opts.days)?;
+    for (channel, reason) in &stale {
+        if opts.dry_run {
+            println!("{} ({})", channel, reason);
+        } else {
+            registry.prune(channel, reason)?;
+            info!("Pruned {}, {}", channel, reason);
+        }
+    }
+
+    if opts.dry_run {
+        info!("Would prune {} channels", stale.len());
+    } else {
+        info!("Pruned {} channels", stale.len());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,107 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Datelike, Local, NaiveDate, TimeZone};
+use clap::Args;
+use std::collections::BTreeMap;
+use twitch_gift_farm::{
+    history::{Gift, GiftKind, History},
+    Config,
+};
+
+#[derive(Debug, Args)]
+pub struct Opts {
+    /// The year to look back on, defaults to the current year
+    #[arg(short, long)]
+    year: Option<i32>,
+}
+
+pub fn run(opts: Opts) -> Result<()> {
+    let config = Config::load()?;
+    let prices = config.prices()?;
+    let year = opts.year.unwrap_or_else(|| Local::now().year());
+
+    let start = local_midnight(year, 1, 1)?;
+    let end = local_midnight(year + 1, 1, 1)?.min(Local::now());
+    if end <= start {
+        return Err(anyhow!("{} has not started yet", year));
+    }
+
+    let mut gifts: Vec<Gift> = History::load()?
+        .into_iter()
+        .filter(|gift| gift.kind.is_gift())
+        .filter(|gift| {
+            let time = gift.time.with_timezone(&Local);
+            time >= start && time < end
+        })
+        .collect();
+    gifts.sort_by_key(|gift| gift.time);
+
+    println!("Your {} in gifts", year);
+    println!();
+
+    if gifts.is_empty() {
+        println!("No gifts received in {}.", year);
+        return Ok(());
+    }
+
+    let months: u64 = gifts.iter().map(|gift| gift.months).sum();
+    println!("Gifts received:    {}", gifts.len());
+    println!("Months received:   {}", months);
+    println!("Estimated value:   {}", prices.total(&gifts));
+
+    let mut by_month: BTreeMap<u32, Vec<&Gift>> = BTreeMap::new();
+    let mut by_gifter: BTreeMap<&str, Vec<&Gift>> = BTreeMap::new();
+    for gift in &gifts {
+        by_month
+            .entry(gift.time.with_timezone(&Local).month())
+            .or_default()
+            .push(gift);
+        if gift.kind != GiftKind::AnonSubGift {
+            by_gifter.entry(&gift.gifter).or_default().push(gift);
+        }
+    }
+
+    // ties go to the earlier month and the alphabetically first gifter
+    if let Some((month, month_gifts)) = by_month.iter().rev().max_by_key(|(_, gifts)| gifts.len()) {
+        println!(
+            "Best month:        {} with {} gifts worth {}",
+            NaiveDate::from_ymd_opt(year, *month, 1)
+                .unwrap()
+                .format("%B"),
+            month_gifts.len(),
+            prices.total(month_gifts.iter().copied())
+        );
+    }
+
+    if let Some((gifter, gifter_gifts)) =
+        by_gifter.iter().rev().max_by_key(|(_, gifts)| gifts.len())
+    {
+        println!(
+            "Most generous:     {} with {} gifts worth {}",
+            gifter,
+            gifter_gifts.len(),
+            prices.total(gifter_gifts.iter().copied())
+        );
+    }
+
+    let times = std::iter::once(start)
+        .chain(gifts.iter().map(|gift| gift.time.with_timezone(&Local)))
+        .chain(std::iter::once(end))
+        .collect::<Vec<_>>();
+    if let Some(drought) = times.windows(2).max_by_key(|pair| pair[1] - pair[0]) {
+        println!(
+            "Longest drought:   {} days from {} to {}",
+            (drought[1] - drought[0]).num_days(),
+            drought[0].format("%Y-%m-%d"),
+            drought[1].format("%Y-%m-%d")
+        );
+    }
+
+    Ok(())
+}
+
+fn local_midnight(year: i32, month: u32, day: u32) -> Result<DateTime<Local>> {
+    NaiveDate::from_ymd_opt(year, month, day)
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .and_then(|time| Local.from_local_datetime(&time).earliest())
+        .ok_or_else(|| anyhow!("{} is not a valid year", year))
+}
@@ -0,0 +1,241 @@
+use anyhow::{Context, Result};
+use clap::Args;
+use log::{debug, info, warn};
+use smol::future::FutureExt;
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+use twitch_gift_farm::{
+    connector::{connect_monitored, Endpoint},
+    discovery::{get_streams, select},
+    farm::JOIN_INTERVAL,
+    registry::{Registry, Source},
+    runtime::{self, sleep_until},
+    Config,
+};
+use twitchchat::{
+    messages::{Commands, NoticeType},
+    twitch::Capability,
+    Status, UserConfig,
+};
+
+#[derive(Debug, Args)]
+pub struct Opts {
+    /// Channels to scout instead of the new channels currently streaming the top games
+    channels: Vec<String>,
+
+    /// Scout the channels that were demoted for a lack of gifts and make the ones that get gifts
+    /// active again. Subs alone do not count.
+    #[arg(long, conflicts_with_all = ["channels", "account"])]
+    pool: bool,
+
+    /// Add the active channels to this account instead of the shared channels
+    #[arg(short, long)]
+    account: Option<String>,
+
+    /// Minutes to watch the channels for
+    #[arg(long, default_value_t = 30)]
+    minutes: u64,
+
+    /// Subs and gift events a channel needs during that time to be added
+    #[arg(long, default_value_t = 1)]
+    min_events: u64,
+
+    /// Scout at most this many discovered channels
+    #[arg(long, default_value_t = 200)]
+    max: usize,
+
+    /// Print the active channels instead of adding them
+    #[arg(long)]
+    dry_run: bool,
+}
+
+/// What happened in a channel while it was watched
+#[derive(Debug, Default)]
+struct Activity {
+    subs: u64,
+    gifts: u64,
+}
+
+impl Activity {
+    fn events(&self) -> u64 {
+        self.subs + self.gifts
+    }
+}
+
+pub fn run(opts: Opts) -> Result<()> {
+    let account = opts.account.as_deref();
+    // loading the config moves channels of old configs into the registry
+    let mut config = Config::load()?;
+    if let Some(account) = account {
+        config.account_mut(account)?;
+    }
+
+    let mut registry = Registry::open()?;
+
+    let candidates = if opts.pool {
+        registry.pool()?
+    } else if opts.channels.is_empty() {
+        let discovered = runtime::block_on(get_streams(&config.discovery))?;
+        select(
+            &registry,
+            account,
+            Some(opts.max),
+            &config.discovery,
+            &discovered.channels,
+        )?
+        .new
+    } else {
+        let known: HashSet<String> = registry.known(account)?.into_iter().collect();
+        opts.channels
+            .iter()
+            .map(|channel| channel.trim_start_matches('#').to_lowercase())
+            .filter(|channel| !known.contains(channel))
+            .collect()
+    };
+
+    if candidates.is_empty() {
+        info!("No channels to scout");
+        return Ok(());
+    }
+
+    let window = Duration::from_secs(opts.minutes * 60);
+    let activity = runtime::block_on(scout(&config.irc, &candidates, window))?;
+
+    // channels in the pool already had their chance to be gifted to
+    let events = |activity: &Activity| {
+        if opts.pool {
+            activity.gifts
+        } else {
+            activity.events()
+        }
+    };
+    let mut active: Vec<(&String, &Activity)> = activity
+        .iter()
+        .filter(|(_, activity)| events(activity) >= opts.min_events.max(1))
+        .collect();
+    // the busiest channels come first
+    active.sort_by(|a, b| events(b.1).cmp(&events(a.1)).then(a.0.cmp(b.0)));
+
+    info!(
+        "{} of {} channels had at least {} {}",
+        active.len(),
+        candidates.len(),
+        opts.min_events.max(1),
+        if opts.pool {
+            "gift events"
+        } else {
+            "subs or gift events"
+        }
+    );
+
+    let channels: Vec<&String> = active.iter().map(|(channel, _)| *channel).collect();
+    if opts.dry_run {
+        for (channel, activity) in &active {
+            println!("{}\t{}\t{}", channel, activity.subs, activity.gifts);
+        }
+
+        return Ok(());
+    }
+
+    if opts.pool {
+        let mut promoted = 0;
+        for channel in &channels {
+            if registry.promote(channel)? > 0 {
+                promoted += 1;
+            }
+        }
+        info!("Moved {} channels out of the scout pool", promoted);
+        return Ok(());
+    }
+
+    let added = registry.add(account, &channels, Source::Scout)?;
+    info!(
+        "Saving {} new channels for a total of {}",
+        added,
+        registry.list(account)?.len()
+    );
+
+    Ok(())
+}
+
+/// Watch `channels` anonymously for `window` after joining them and count their subs and gifts
+async fn scout(
+    endpoint: &Endpoint,
+    channels: &[String],
+    window: Duration,
+) -> Result<HashMap<String, Activity>> {
+    let user_config = UserConfig::builder()
+        .anonymous()
+        .capabilities(&[Capability::Tags, Capability::Commands])
+        .build()
+        .context("Could not configure the anonymous connection")?;
+    let (mut runner, monitor) = connect_monitored(endpoint, &user_config).await?;
+
+    let mut activity: HashMap<String, Activity> = HashMap::new();
+    let mut pending = channels.iter();
+    let mut next_join = Instant::now();
+    // the window starts once every channel was joined
+    let mut end = None;
+
+    info!(
+        "Scouting {} channels for {} minutes",
+        channels.len(),
+        window.as_secs() / 60
+    );
+
+    loop {
+        let wake = match end {
+            Some(end) => end,
+            None => next_join,
+        };
+        let status = async { runner.next_message().await.map(Some) }
+            .or(async {
+                sleep_until(wake).await;
+                Ok(None)
+            })
+            .await?;
+
+        match status {
+            Some(Status::Message(Commands::UserNotice(msg))) => {
+                let channel = msg.channel().trim_start_matches('#').to_string();
+                match msg.msg_id() {
+                    Some(NoticeType::Sub) | Some(NoticeType::Resub) => {
+                        activity.entry(channel).or_default().subs += 1;
+                    }
+                    Some(NoticeType::SubGift)
+                    | Some(NoticeType::AnonSubGift)
+                    | Some(NoticeType::SubMysteryGift)
+                    | Some(NoticeType::Unknown("anonsubmysterygift")) => {
+                        activity.entry(channel).or_default().gifts += 1;
+                    }
+                    _ => {}
+                }
+            }
+            Some(Status::Eof) => {
+                warn!("Lost the anonymous connection, stopping early");
+                break;
+            }
+            Some(Status::Quit) => break,
+            Some(Status::Message(..)) | None => {}
+        }
+
+        if let Some(end) = end {
+            if Instant::now() >= end {
+                break;
+            }
+        } else if Instant::now() >= next_join {
+            match pending.next() {
+                Some(channel) => {
+                    debug!("Scouting: {}", channel);
+                    monitor.send_raw(&format!("JOIN #{}", channel)).await?;
+                    next_join = Instant::now() + JOIN_INTERVAL;
+                }
+                None => end = Some(Instant::now() + window),
+            }
+        }
+    }
+
+    Ok(activity)
+}
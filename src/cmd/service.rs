@@ -0,0 +1,137 @@
+use super::farm::{self, Opts};
+use anyhow::{Context, Result};
+use eventlog::EventLog;
+use flexi_logger::{writers::LogWriter, DeferredNow, Record};
+use log::{error, Level, LevelFilter, Log};
+use std::{ffi::OsString, io, sync::Mutex, time::Duration};
+use twitch_gift_farm::runtime;
+use windows_service::{
+    define_windows_service,
+    service::{
+        ServiceControl, ServiceControlAccept, ServiceExitCode, ServiceState, ServiceStatus,
+        ServiceType,
+    },
+    service_control_handler::{self, ServiceControlHandlerResult, ServiceStatusHandle},
+    service_dispatcher,
+};
+
+/// Name of the service and of the event source it logs as
+const SERVICE_NAME: &str = "tgf";
+
+/// The options of `tgf farm --service`, the service dispatcher does not pass them on
+static OPTS: Mutex<Option<Opts>> = Mutex::new(None);
+
+/// Hand the process over to the service control manager, which farms in [`service_main`] until
+/// the service is stopped
+pub fn run(opts: Opts) -> Result<()> {
+    *OPTS.lock().unwrap() = Some(opts);
+
+    service_dispatcher::start(SERVICE_NAME, ffi_service_main)
+        .context("Could not start the service, --service only works when Windows starts tgf")
+}
+
+define_windows_service!(ffi_service_main, service_main);
+
+fn service_main(_arguments: Vec<OsString>) {
+    if let Err(err) = run_service() {
+        error!("The service stopped: {:#}", err);
+    }
+}
+
+fn run_service() -> Result<()> {
+    let (stop, stopped) = smol::channel::bounded(1);
+    let status = service_control_handler::register(SERVICE_NAME, move |control| match control {
+        ServiceControl::Stop | ServiceControl::Shutdown => {
+            stop.try_send(()).ok();
+            ServiceControlHandlerResult::NoError
+        }
+        ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+        _ => ServiceControlHandlerResult::NotImplemented,
+    })
+    .context("Could not register the service control handler")?;
+    set_state(
+        &status,
+        ServiceState::Running,
+        ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN,
+        ServiceExitCode::NO_ERROR,
+    )?;
+
+    let opts = OPTS
+        .lock()
+        .unwrap()
+        .take()
+        .expect("the options are set before the service starts");
+    let result = farm::builder(opts)
+        .and_then(|builder| {
+            builder
+                .stop_on(async move {
+                    stopped.recv().await.ok();
+                })
+                .build()
+        })
+        .and_then(|farm| runtime::block_on(farm.run()));
+
+    // a service specific code always counts as a failure, even 0
+    let exit_code = if result.is_ok() {
+        ServiceExitCode::NO_ERROR
+    } else {
+        ServiceExitCode::ServiceSpecific(1)
+    };
+    set_state(
+        &status,
+        ServiceState::Stopped,
+        ServiceControlAccept::empty(),
+        exit_code,
+    )?;
+
+    result
+}
+
+fn set_state(
+    status: &ServiceStatusHandle,
+    state: ServiceState,
+    accept: ServiceControlAccept,
+    exit_code: ServiceExitCode,
+) -> Result<()> {
+    status
+        .set_service_status(ServiceStatus {
+            service_type: ServiceType::OWN_PROCESS,
+            current_state: state,
+            controls_accepted: accept,
+            exit_code,
+            checkpoint: 0,
+            wait_hint: Duration::default(),
+            process_id: None,
+        })
+        .context("Could not update the service status")
+}
+
+/// Writes the log to the Windows event log, a service has no console
+pub struct EventLogWriter(EventLog);
+
+impl EventLogWriter {
+    pub fn new() -> Result<Self> {
+        // registering the event source needs administrator rights, which the service has but
+        // the source may be registered already
+        eventlog::register(SERVICE_NAME).ok();
+
+        Ok(Self(
+            EventLog::new(SERVICE_NAME, Level::Trace).context("Could not open the event log")?,
+        ))
+    }
+}
+
+impl LogWriter for EventLogWriter {
+    fn write(&self, _now: &mut DeferredNow, record: &Record) -> io::Result<()> {
+        self.0.log(record);
+        Ok(())
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn max_log_level(&self) -> LevelFilter {
+        LevelFilter::Trace
+    }
+}
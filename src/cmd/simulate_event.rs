@@ -0,0 +1,62 @@
+use anyhow::{anyhow, Result};
+use clap::Args;
+use twitch_gift_farm::{connector::FakeGift, farm::Farm, runtime, Config};
+
+#[derive(Debug, Args)]
+pub struct Opts {
+    /// A raw USERNOTICE as Twitch sends it, with tags. Made up from the other options if missing.
+    line: Option<String>,
+
+    /// The account that receives the gift, the first configured account by default
+    #[arg(short, long)]
+    account: Option<String>,
+
+    /// Channel the gift is sent in
+    #[arg(long, default_value = "simulated", conflicts_with = "line")]
+    channel: String,
+
+    /// Who gifted the sub, anonymous if missing
+    #[arg(long, conflicts_with = "line")]
+    gifter: Option<String>,
+
+    /// Tier of the gifted sub
+    #[arg(
+        long,
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u32).range(1..=3),
+        conflicts_with = "line"
+    )]
+    tier: u32,
+
+    /// Months gifted at once
+    #[arg(long, default_value_t = 1, conflicts_with = "line")]
+    months: u32,
+}
+
+pub fn run(opts: Opts) -> Result<()> {
+    let mut config = Config::load()?;
+    if let Some(account) = &opts.account {
+        config.account_mut(account)?;
+        config.accounts.retain(|other| &other.username == account);
+    }
+    let username = config
+        .accounts
+        .first()
+        .map(|account| account.username.to_string())
+        .ok_or_else(|| anyhow!("No accounts configured, run `auth` first"))?;
+
+    let line = match opts.line {
+        Some(line) => line,
+        None => FakeGift {
+            gifter: opts.gifter.as_deref(),
+            recipient: &username,
+            channel: &opts.channel,
+            plan: &format!("{}000", opts.tier),
+            months: opts.months,
+            id: &format!("simulated-{}", chrono::Utc::now().timestamp_millis()),
+        }
+        .line(),
+    };
+
+    runtime::block_on(Farm::builder(config).inject(line).build()?.run())
+}
@@ -0,0 +1,308 @@
+use anyhow::Result;
+use chrono::{Datelike, Duration, Local, Timelike, Utc, Weekday};
+use clap::{Args, Subcommand, ValueEnum};
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap, HashSet},
+};
+use twitch_gift_farm::{
+    history::{GiftKind, History},
+    registry::Registry,
+    state::{ChannelState, WINDOW_DAYS},
+    Config,
+};
+
+#[derive(Debug, Args)]
+pub struct Opts {
+    /// Same as `stats channels`
+    #[arg(long, hide = true)]
+    channels: bool,
+
+    #[command(subcommand)]
+    view: Option<View>,
+}
+
+#[derive(Debug, Subcommand)]
+enum View {
+    /// Rank the channels joined recently to see which ones are worth keeping
+    Channels(ChannelsOpts),
+    /// Rank the most generous gifters across all channels
+    Gifters(GiftersOpts),
+}
+
+#[derive(Debug, Args)]
+struct GiftersOpts {
+    /// Only count gifts of the last days instead of the whole history
+    #[arg(long)]
+    days: Option<u32>,
+
+    /// How many gifters to show
+    #[arg(long, default_value_t = 10)]
+    limit: usize,
+}
+
+#[derive(Debug, Args)]
+struct ChannelsOpts {
+    /// What to rank the channels by
+    #[arg(long, value_enum, default_value_t = Order::Rate)]
+    by: Order,
+
+    /// How many channels to show, all by default
+    #[arg(long)]
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Order {
+    /// Gifts received
+    Gifts,
+    /// Gifts per day joined
+    Rate,
+    /// The most recent gift
+    Last,
+}
+
+pub fn run(opts: Opts) -> Result<()> {
+    match opts.view {
+        Some(View::Channels(opts)) => return ranking(opts),
+        Some(View::Gifters(opts)) => return gifters(opts),
+        None if opts.channels => {
+            return ranking(ChannelsOpts {
+                by: Order::Rate,
+                limit: None,
+            })
+        }
+        None => {}
+    }
+
+    let config = Config::load()?;
+    let gifts = History::load()?;
+    let prices = config.prices()?;
+    let registry = Registry::open()?;
+
+    println!("Shared channels:   {}", registry.list(None)?.len());
+    println!("Replicas:          {}", config.replicas);
+    println!();
+
+    for (index, account) in config.accounts.iter().enumerate() {
+        let count = |filter: fn(GiftKind) -> bool| {
+            gifts
+                .iter()
+                .filter(|gift| gift.account == account.username && filter(gift.kind))
+                .count()
+        };
+
+        println!("Account:           {}", account.username);
+        println!(
+            "Own channels:      {}",
+            registry.list(Some(&account.username))?.len()
+        );
+        println!(
+            "Assigned channels: {}",
+            registry.channels_for(&config, index)?.len()
+        );
+        let months: u64 = gifts
+            .iter()
+            .filter(|gift| gift.account == account.username && gift.kind.is_gift())
+            .map(|gift| gift.months)
+            .sum();
+
+        println!("Gifts received:    {}", count(GiftKind::is_gift));
+        println!("Months received:   {}", months);
+        println!(
+            "Estimated value:   {}",
+            prices.total(gifts.iter().filter(|gift| gift.account == account.username))
+        );
+        println!("Upgrades:          {}", count(GiftKind::is_upgrade));
+        println!("Pay forwards:      {}", count(GiftKind::is_pay_forward));
+        println!();
+    }
+
+    let mut by_kind = BTreeMap::new();
+    let mut by_tier = BTreeMap::new();
+    for gift in &gifts {
+        *by_kind.entry(gift.kind.as_str()).or_insert(0) += 1;
+        if gift.kind.is_gift() {
+            *by_tier.entry(gift.tier).or_insert(0) += 1;
+        }
+    }
+    let community = gifts
+        .iter()
+        .filter(|gift| gift.community_gift.is_some())
+        .count();
+
+    println!("Recorded events:   {}", gifts.len());
+    for (kind, count) in by_kind {
+        println!("  {:<16} {}", kind, count);
+    }
+    for (tier, count) in by_tier {
+        println!("  {:<16} {}", tier.as_str(), count);
+    }
+    println!("  {:<16} {}", "from community", community);
+    println!("  {:<16} {}", "estimated value", prices.total(&gifts));
+
+    let mut lineage = BTreeMap::new();
+    for gift in gifts.iter().filter(|gift| gift.kind.is_pay_forward()) {
+        if let Some(prior_gifter) = &gift.prior_gifter {
+            lineage
+                .entry(gift.channel.as_str())
+                .or_insert_with(Vec::new)
+                .push(format!("{} -> {}", prior_gifter, gift.gifter));
+        }
+    }
+
+    if !lineage.is_empty() {
+        println!();
+        println!("Pay forwards:");
+        for (channel, chain) in lineage {
+            println!("  {}: {}", channel, chain.join(", "));
+        }
+    }
+
+    let mut by_hour = [0; 24];
+    let mut by_weekday = [0; 7];
+    for gift in gifts.iter().filter(|gift| gift.kind.is_gift()) {
+        let time = gift.time.with_timezone(&Local);
+        by_hour[time.hour() as usize] += 1;
+        by_weekday[time.weekday().num_days_from_monday() as usize] += 1;
+    }
+
+    if by_hour.iter().any(|&count| count > 0) {
+        println!();
+        println!("Gifts by hour:");
+        for (hour, count) in by_hour.iter().enumerate() {
+            println!("  {:02}:00 {}", hour, bar(*count, &by_hour));
+        }
+
+        println!();
+        println!("Gifts by weekday:");
+        let mut weekday = Weekday::Mon;
+        for count in &by_weekday {
+            println!("  {}   {}", weekday, bar(*count, &by_weekday));
+            weekday = weekday.succ();
+        }
+    }
+
+    Ok(())
+}
+
+/// Print the channels joined during the scoring window, best first
+fn ranking(opts: ChannelsOpts) -> Result<()> {
+    let mut scores = ChannelState::load()?.scores(&History::load()?);
+
+    if scores.is_empty() {
+        println!("No channels joined in the last {} days", WINDOW_DAYS);
+        return Ok(());
+    }
+
+    // the scores come sorted by rate, the other orders fall back to it for ties
+    match opts.by {
+        Order::Gifts => scores.sort_by_key(|score| Reverse(score.gifts)),
+        Order::Rate => {}
+        Order::Last => scores.sort_by_key(|score| Reverse(score.last_gift)),
+    }
+    scores.truncate(opts.limit.unwrap_or(usize::MAX));
+
+    let heading = match opts.by {
+        Order::Gifts => "Gifts received",
+        Order::Rate => "Gifts per day joined",
+        Order::Last => "Latest gifts",
+    };
+    println!("{} in the last {} days:", heading, WINDOW_DAYS);
+    println!(
+        "  {:<25} {:>6} {:>6} {:>8} {:>10} {:>11}",
+        "channel", "days", "gifts", "score", "msgs/day", "last gift"
+    );
+    for score in scores {
+        let last_gift = score.last_gift.map_or_else(
+            || "never".to_string(),
+            |time| time.with_timezone(&Local).format("%Y-%m-%d").to_string(),
+        );
+        println!(
+            "  {:<25} {:>6.1} {:>6} {:>8.3} {:>10.0} {:>11}",
+            score.channel, score.days_joined, score.gifts, score.score, score.messages, last_gift
+        );
+    }
+
+    Ok(())
+}
+
+/// Print the gifters that gave the accounts the most gifts, most generous first
+fn gifters(opts: GiftersOpts) -> Result<()> {
+    let config = Config::load()?;
+    let gifts = History::load()?;
+    let prices = config.prices()?;
+    let since = opts
+        .days
+        .map(|days| Utc::now() - Duration::days(days.into()));
+
+    let mut by_gifter = HashMap::new();
+    for gift in gifts.iter().filter(|gift| {
+        // anonymous gifts all share one made up gifter
+        gift.kind.is_gift()
+            && gift.kind != GiftKind::AnonSubGift
+            && since.map_or(true, |since| gift.time >= since)
+    }) {
+        by_gifter
+            .entry(gift.gifter.as_str())
+            .or_insert_with(Vec::new)
+            .push(gift);
+    }
+
+    let period = match opts.days {
+        Some(days) => format!("in the last {} days", days),
+        None => "in the whole history".to_string(),
+    };
+    if by_gifter.is_empty() {
+        println!("No gifts {}", period);
+        return Ok(());
+    }
+
+    let mut ranking: Vec<_> = by_gifter
+        .into_iter()
+        .map(|(gifter, gifts)| {
+            let channels = gifts
+                .iter()
+                .map(|gift| gift.channel.as_str())
+                .collect::<HashSet<_>>()
+                .len();
+            let value = prices.total(gifts.iter().copied());
+            (gifter, gifts.len(), channels, value)
+        })
+        .collect();
+    ranking.sort_by(|a, b| {
+        b.1.cmp(&a.1)
+            .then(b.3.amount.total_cmp(&a.3.amount))
+            .then(a.0.cmp(b.0))
+    });
+
+    println!("Most generous gifters {}:", period);
+    println!(
+        "  {:<25} {:>6} {:>9} {:>16}",
+        "gifter", "gifts", "channels", "estimated value"
+    );
+    for (gifter, count, channels, value) in ranking.into_iter().take(opts.limit) {
+        println!(
+            "  {:<25} {:>6} {:>9} {:>16}",
+            gifter,
+            count,
+            channels,
+            value.to_string()
+        );
+    }
+
+    Ok(())
+}
+
+/// A bar proportional to `count` among `counts` followed by the count
+fn bar(count: usize, counts: &[usize]) -> String {
+    const WIDTH: usize = 40;
+
+    let max = counts.iter().copied().max().unwrap_or(0).max(1);
+    format!(
+        "{:<width$} {}",
+        "#".repeat(count * WIDTH / max),
+        count,
+        width = WIDTH
+    )
+}
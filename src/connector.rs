@@ -0,0 +1,834 @@
+use crate::proxy::Route;
+use anyhow::{Context, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+use futures::{
+    future,
+    io::{AsyncRead, AsyncWrite},
+};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::{File, OpenOptions},
+    future::Future,
+    io::{self, Write},
+    path::PathBuf,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context as TaskContext, Poll, Waker},
+    time::{Duration, Instant},
+};
+use twitchchat::{connector::Connector, AsyncRunner, BoxedFuture, UserConfig};
+
+#[cfg(not(any(feature = "rustls", feature = "native-tls")))]
+compile_error!("enable the `rustls` or the `native-tls` feature");
+
+/// A connection to the chat server, with or without TLS
+pub trait Stream: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<S> Stream for S where S: AsyncRead + AsyncWrite + Send + Unpin {}
+
+const LOGIN_FAILED: &[&str] = &["Login authentication failed", "Improperly formatted auth"];
+
+/// The chat server to connect to, Twitch unless a test server or another edge is configured
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Endpoint {
+    #[serde(default = "default_host")]
+    pub host: String,
+    /// Defaults to 6697 with TLS and to 6667 without
+    #[serde(default)]
+    pub port: Option<u16>,
+    #[serde(default = "default_tls")]
+    pub tls: bool,
+}
+
+fn default_host() -> String {
+    twitchchat::TWITCH_TLS_DOMAIN.to_string()
+}
+
+fn default_tls() -> bool {
+    true
+}
+
+impl Default for Endpoint {
+    fn default() -> Self {
+        Self {
+            host: default_host(),
+            port: None,
+            tls: default_tls(),
+        }
+    }
+}
+
+impl Endpoint {
+    pub fn port(&self) -> u16 {
+        self.port.unwrap_or(if self.tls { 6697 } else { 6667 })
+    }
+}
+
+/// Returned when Twitch rejected the username or token. Retrying will not help.
+#[derive(Debug)]
+pub struct LoginFailed {
+    pub username: String,
+}
+
+impl fmt::Display for LoginFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Login authentication failed for {}, run `auth` to update the token",
+            self.username
+        )
+    }
+}
+
+impl std::error::Error for LoginFailed {}
+
+/// Why Twitch refused to let us join a channel
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rejection {
+    Suspended,
+    /// The account is banned from the channel
+    Banned,
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Suspended => write!(f, "channel is suspended"),
+            Self::Banned => write!(f, "banned from channel"),
+        }
+    }
+}
+
+impl std::error::Error for Rejection {}
+
+/// Connect to `endpoint` and wait until the connection is ready.
+///
+/// If Twitch rejects the credentials the error can be downcast to [`LoginFailed`].
+pub async fn connect(endpoint: &Endpoint, user_config: &UserConfig) -> Result<AsyncRunner> {
+    Ok(connect_monitored(endpoint, user_config).await?.0)
+}
+
+/// Like [`connect`] but also returns the monitor that inspects the connection
+pub async fn connect_monitored(
+    endpoint: &Endpoint,
+    user_config: &UserConfig,
+) -> Result<(AsyncRunner, Arc<Monitor>)> {
+    connect_capturing(endpoint, user_config, None).await
+}
+
+/// Like [`connect_monitored`] but also writes every line Twitch sends to `capture`
+pub async fn connect_capturing(
+    endpoint: &Endpoint,
+    user_config: &UserConfig,
+    capture: Option<Arc<Capture>>,
+) -> Result<(AsyncRunner, Arc<Monitor>)> {
+    let connector = EndpointConnector::new(endpoint).with_context(|| {
+        format!(
+            "Could not resolve the IRC address {}:{}",
+            endpoint.host,
+            endpoint.port()
+        )
+    })?;
+
+    connect_with(connector, user_config, capture).await
+}
+
+/// Like [`connect_monitored`] but reads the raw IRC `lines` instead of connecting anywhere
+pub async fn replay(lines: &str, user_config: &UserConfig) -> Result<(AsyncRunner, Arc<Monitor>)> {
+    connect_with(
+        ReplayConnector::new(&user_config.name, lines),
+        user_config,
+        None,
+    )
+    .await
+}
+
+/// Like [`connect_monitored`] but reads gifts made up by `simulation` instead of connecting anywhere
+pub async fn simulate(
+    simulation: &Simulation,
+    user_config: &UserConfig,
+) -> Result<(AsyncRunner, Arc<Monitor>)> {
+    connect_with(
+        SimulationConnector {
+            username: user_config.name.clone(),
+            simulation: simulation.clone(),
+        },
+        user_config,
+        None,
+    )
+    .await
+}
+
+async fn connect_with<C>(
+    inner: C,
+    user_config: &UserConfig,
+    capture: Option<Arc<Capture>>,
+) -> Result<(AsyncRunner, Arc<Monitor>)>
+where
+    C: Connector,
+{
+    let monitor = Arc::new(Monitor {
+        capture,
+        ..Monitor::default()
+    });
+    let connector = MonitoredConnector {
+        inner,
+        monitor: monitor.clone(),
+    };
+
+    match AsyncRunner::connect(connector, user_config).await {
+        Ok(runner) => Ok((runner, monitor)),
+        Err(_) if monitor.login_failed() => Err(LoginFailed {
+            username: user_config.name.clone(),
+        }
+        .into()),
+        Err(err) => Err(err).context("Could not connect to Twitch"),
+    }
+}
+
+/// Inspects every line Twitch sends, including those read while the runner is still waiting for
+/// the connection to become ready.
+#[derive(Default)]
+pub struct Monitor {
+    login_failed: AtomicBool,
+    rejections: Mutex<HashMap<String, Rejection>>,
+    /// The connection, only written to by [`poll_send`](Monitor::poll_send)
+    writer: Mutex<Option<Box<dyn AsyncWrite + Send + Sync + Unpin>>>,
+    /// Bytes of the runner and of [`send_raw`](Monitor::send_raw) waiting to be written, always
+    /// whole lines so they can't be mixed up on the connection
+    outgoing: Mutex<Vec<u8>>,
+    capture: Option<Arc<Capture>>,
+    /// Token and time of the last [`ping`](Monitor::ping) that was not answered yet
+    ping: Mutex<Option<(String, Instant)>>,
+    /// Round trip of the last answered ping that was not taken yet
+    latency: Mutex<Option<Duration>>,
+}
+
+impl fmt::Debug for Monitor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Monitor")
+            .field("login_failed", &self.login_failed)
+            .field("rejections", &self.rejections)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Monitor {
+    /// Send a raw line to Twitch, queued behind the lines of the runner.
+    ///
+    /// The runner only lets chat messages through its writer, this is for commands it has no
+    /// support for, like joining many channels at once.
+    pub async fn send_raw(&self, line: &str) -> io::Result<()> {
+        if self.writer.lock().unwrap().is_none() {
+            return Err(io::Error::new(io::ErrorKind::NotConnected, "not connected"));
+        }
+
+        self.outgoing
+            .lock()
+            .unwrap()
+            .extend_from_slice(format!("{}\r\n", line).as_bytes());
+        future::poll_fn(|cx| self.poll_send(cx)).await
+    }
+
+    /// Write the outgoing bytes until none are left. Whoever polls writes for everyone, and the
+    /// bytes are only taken from the queue once they are written, so cancelling is safe.
+    fn poll_send(&self, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        let mut writer = self.writer.lock().unwrap();
+        let writer = match writer.as_mut() {
+            Some(writer) => writer,
+            None => return Poll::Ready(Ok(())),
+        };
+
+        loop {
+            let mut outgoing = self.outgoing.lock().unwrap();
+            if outgoing.is_empty() {
+                return Pin::new(&mut *writer).poll_flush(cx);
+            }
+
+            match Pin::new(&mut *writer).poll_write(cx, &outgoing) {
+                Poll::Ready(Ok(0)) => return Poll::Ready(Err(io::ErrorKind::WriteZero.into())),
+                Poll::Ready(Ok(written)) => {
+                    outgoing.drain(..written);
+                }
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+
+    /// Send a PING to measure the round trip, see [`take_latency`](Self::take_latency)
+    pub async fn ping(&self) -> io::Result<()> {
+        let token = format!("tgf-{}", Utc::now().timestamp_millis());
+        *self.ping.lock().unwrap() = Some((token.clone(), Instant::now()));
+
+        self.send_raw(&format!("PING :{}", token)).await
+    }
+
+    /// How long the last ping has been waiting for its PONG, if it is still waiting
+    pub fn unanswered(&self) -> Option<Duration> {
+        self.ping
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|(_, sent)| sent.elapsed())
+    }
+
+    /// The round trip of the last ping, once after its PONG arrived
+    pub fn take_latency(&self) -> Option<Duration> {
+        self.latency.lock().unwrap().take()
+    }
+
+    pub fn login_failed(&self) -> bool {
+        self.login_failed.load(Ordering::Relaxed)
+    }
+
+    /// Take the reason Twitch refused to let us join `channel`, if it did
+    pub fn rejection(&self, channel: &str) -> Option<Rejection> {
+        self.rejections
+            .lock()
+            .unwrap()
+            .remove(channel.trim_start_matches('#'))
+    }
+
+    fn inspect(&self, line: &str) {
+        if let Some(capture) = &self.capture {
+            capture.write(line);
+        }
+
+        if is_login_failure(line) {
+            self.login_failed.store(true, Ordering::Relaxed);
+        }
+
+        if let Some((channel, rejection)) = parse_rejection(line) {
+            self.rejections.lock().unwrap().insert(channel, rejection);
+        }
+
+        if line.contains(" PONG ") {
+            let mut ping = self.ping.lock().unwrap();
+            if let Some((token, sent)) = ping.as_ref() {
+                if line.ends_with(token.as_str()) {
+                    *self.latency.lock().unwrap() = Some(sent.elapsed());
+                    *ping = None;
+                }
+            }
+        }
+    }
+}
+
+/// Appends every line Twitch sends to a file, each prefixed with the time it arrived and a tab
+#[derive(Debug)]
+pub struct Capture {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl Capture {
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("Could not open {}", path.display()))?;
+
+        Ok(Self {
+            path,
+            file: Mutex::new(file),
+        })
+    }
+
+    fn write(&self, line: &str) {
+        let time = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+        if let Err(err) = writeln!(self.file.lock().unwrap(), "{}\t{}", time, line) {
+            warn!("Could not capture to {}: {}", self.path.display(), err);
+        }
+    }
+
+    /// Remove the time [`Capture`] puts in front of a line, if it has one
+    pub fn strip_time(line: &str) -> &str {
+        match line.split_once('\t') {
+            Some((time, rest)) if DateTime::parse_from_rfc3339(time).is_ok() => rest,
+            _ => line,
+        }
+    }
+}
+
+/// Parse a NOTICE like `@msg-id=msg_channel_suspended :tmi.twitch.tv NOTICE #channel :...`
+fn parse_rejection(line: &str) -> Option<(String, Rejection)> {
+    let (tags, rest) = line.strip_prefix('@')?.split_once(' ')?;
+    let rejection = match tags
+        .split(';')
+        .find_map(|tag| tag.strip_prefix("msg-id="))?
+    {
+        "msg_channel_suspended" => Rejection::Suspended,
+        "msg_banned" => Rejection::Banned,
+        _ => return None,
+    };
+
+    let mut words = rest.split(' ').skip_while(|word| *word != "NOTICE");
+    let channel = words.nth(1)?.trim_start_matches('#');
+
+    Some((channel.to_string(), rejection))
+}
+
+/// Check whether a raw line or a NOTICE message tells us that the login was rejected.
+pub fn is_login_failure(message: &str) -> bool {
+    LOGIN_FAILED.iter().any(|needle| message.contains(needle))
+}
+
+#[derive(Debug, Clone)]
+pub struct MonitoredConnector<C> {
+    inner: C,
+    monitor: Arc<Monitor>,
+}
+
+impl<C> Connector for MonitoredConnector<C>
+where
+    C: Connector,
+{
+    type Output = Monitored<C::Output>;
+
+    fn connect(&mut self) -> BoxedFuture<io::Result<Self::Output>> {
+        let connect = self.inner.connect();
+        let monitor = self.monitor.clone();
+
+        Box::pin(async move {
+            let inner = async_dup::Arc::new(async_dup::Mutex::new(connect.await?));
+            // whatever was not written belongs to the old connection
+            monitor.outgoing.lock().unwrap().clear();
+            *monitor.writer.lock().unwrap() = Some(Box::new(inner.clone()));
+
+            Ok(Monitored {
+                inner,
+                monitor,
+                partial: Mutex::new(Vec::new()),
+            })
+        })
+    }
+}
+
+pub struct Monitored<S> {
+    inner: async_dup::Arc<async_dup::Mutex<S>>,
+    monitor: Arc<Monitor>,
+    partial: Mutex<Vec<u8>>,
+}
+
+impl<S> Monitored<S> {
+    fn feed(&self, data: &[u8]) {
+        let mut partial = self.partial.lock().unwrap();
+        partial.extend_from_slice(data);
+
+        while let Some(end) = partial.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = partial.drain(..=end).collect();
+            self.monitor
+                .inspect(String::from_utf8_lossy(&line).trim_end());
+        }
+    }
+}
+
+impl<S> AsyncRead for &Monitored<S>
+where
+    S: AsyncRead + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = *self;
+        let poll = Pin::new(&mut &*this.inner).poll_read(cx, buf);
+
+        if let Poll::Ready(Ok(n)) = poll {
+            this.feed(&buf[..n]);
+        }
+
+        poll
+    }
+}
+
+impl<S> AsyncWrite for &Monitored<S>
+where
+    S: AsyncWrite + Unpin,
+{
+    /// Queues `buf` behind the raw lines of the monitor. The runner writes whole messages at
+    /// once, so taking all of `buf` keeps the lines apart.
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.monitor.outgoing.lock().unwrap().extend_from_slice(buf);
+        match self.monitor.poll_send(cx) {
+            Poll::Ready(Err(err)) => Poll::Ready(Err(err)),
+            _ => Poll::Ready(Ok(buf.len())),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        self.monitor.poll_send(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut &*self.inner).poll_close(cx)
+    }
+}
+
+impl<S> AsyncRead for Monitored<S>
+where
+    S: AsyncRead + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut &*self).poll_read(cx, buf)
+    }
+}
+
+impl<S> AsyncWrite for Monitored<S>
+where
+    S: AsyncWrite + Unpin,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut &*self).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut &*self).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut &*self).poll_close(cx)
+    }
+}
+
+/// Connects to an [`Endpoint`], through the proxy if one is set
+#[derive(Debug, Clone)]
+pub struct EndpointConnector {
+    route: Route,
+    tls: bool,
+}
+
+impl EndpointConnector {
+    pub fn new(endpoint: &Endpoint) -> io::Result<Self> {
+        Ok(Self {
+            route: Route::new(&endpoint.host, endpoint.port())?,
+            tls: endpoint.tls,
+        })
+    }
+}
+
+impl Connector for EndpointConnector {
+    type Output = async_dup::Mutex<Box<dyn Stream>>;
+
+    fn connect(&mut self) -> BoxedFuture<io::Result<Self::Output>> {
+        let route = self.route.clone();
+        let tls = self.tls;
+
+        Box::pin(async move {
+            let stream = route.connect().await?;
+            if !tls {
+                return Ok(async_dup::Mutex::new(Box::new(stream) as Box<dyn Stream>));
+            }
+
+            #[cfg(feature = "native-tls")]
+            let stream = async_native_tls::connect(route.host(), stream)
+                .await
+                .map_err(io::Error::other)?;
+            #[cfg(not(feature = "native-tls"))]
+            let stream = async_tls::TlsConnector::new()
+                .connect(route.host(), stream)
+                .await?;
+
+            Ok(async_dup::Mutex::new(Box::new(stream) as Box<dyn Stream>))
+        })
+    }
+}
+
+/// Plays back recorded IRC lines after a successful login. Everything written to it is dropped.
+#[derive(Debug, Clone)]
+pub struct ReplayConnector {
+    data: Arc<Vec<u8>>,
+}
+
+/// What Twitch sends after a successful login as `username`
+fn welcome(username: &str) -> String {
+    format!(
+        ":tmi.twitch.tv CAP * ACK :twitch.tv/tags\r\n\
+         :tmi.twitch.tv CAP * ACK :twitch.tv/commands\r\n\
+         :tmi.twitch.tv 001 {name} :Welcome, GLHF!\r\n\
+         :tmi.twitch.tv 376 {name} :>\r\n\
+         @display-name={name};user-id=0 :tmi.twitch.tv GLOBALUSERSTATE\r\n",
+        name = username
+    )
+}
+
+impl ReplayConnector {
+    pub fn new(username: &str, lines: &str) -> Self {
+        let mut data = welcome(username);
+        for line in lines
+            .lines()
+            .map(|line| Capture::strip_time(line.trim_end()))
+            .filter(|line| !line.is_empty())
+        {
+            data.push_str(line);
+            data.push_str("\r\n");
+        }
+
+        Self {
+            data: Arc::new(data.into_bytes()),
+        }
+    }
+}
+
+impl Connector for ReplayConnector {
+    type Output = Replay;
+
+    fn connect(&mut self) -> BoxedFuture<io::Result<Self::Output>> {
+        let data = self.data.clone();
+        Box::pin(async move { Ok(Replay { data, position: 0 }) })
+    }
+}
+
+/// The connection of a [`ReplayConnector`], it ends after the last line
+pub struct Replay {
+    data: Arc<Vec<u8>>,
+    position: usize,
+}
+
+impl AsyncRead for Replay {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        _cx: &mut TaskContext<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let rest = &self.data[self.position..];
+        let n = rest.len().min(buf.len());
+        buf[..n].copy_from_slice(&rest[..n]);
+        self.position += n;
+
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl AsyncWrite for Replay {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut TaskContext<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// A USERNOTICE of a sub gift as Twitch would send it
+#[derive(Debug, Clone)]
+pub struct FakeGift<'a> {
+    /// Who gifted the sub, `None` for an anonymous gift
+    pub gifter: Option<&'a str>,
+    pub recipient: &'a str,
+    pub channel: &'a str,
+    /// The tier as Twitch writes it, 1000, 2000 or 3000
+    pub plan: &'a str,
+    pub months: u32,
+    /// The `id` tag, unique per message
+    pub id: &'a str,
+}
+
+impl FakeGift<'_> {
+    /// The raw IRC line without the line break
+    pub fn line(&self) -> String {
+        let (kind, gifter, user_id) = match self.gifter {
+            Some(gifter) => ("subgift", gifter, 1),
+            None => ("anonsubgift", "AnAnonymousGifter", 274_598_607),
+        };
+
+        format!(
+            "@badge-info=;badges=;color=;display-name={gifter};emotes=;flags=;id={id};\
+             login={login};mod=0;msg-id={kind};msg-param-gift-months={months};msg-param-months=1;\
+             msg-param-origin-id={id};msg-param-recipient-display-name={name};\
+             msg-param-recipient-id=0;msg-param-recipient-user-name={name};\
+             msg-param-sub-plan={plan};msg-param-sub-plan-name=Simulated\\sSub;\
+             room-id=0;subscriber=0;system-msg={gifter}\\sgifted\\sa\\ssub\\sto\\s{name}!;\
+             tmi-sent-ts={time};user-id={user_id};user-type= \
+             :tmi.twitch.tv USERNOTICE #{channel}",
+            gifter = gifter,
+            login = gifter.to_lowercase(),
+            id = self.id,
+            kind = kind,
+            months = self.months,
+            name = self.recipient.to_lowercase(),
+            plan = self.plan,
+            channel = self.channel.trim_start_matches('#').to_lowercase(),
+            time = Utc::now().timestamp_millis(),
+            user_id = user_id,
+        )
+    }
+}
+
+/// Made up gifts to an account, spread over made up channels
+#[derive(Debug, Clone)]
+pub struct Simulation {
+    /// Gifts per minute
+    pub rate: f64,
+    /// Number of channels the gifts come from
+    pub channels: usize,
+}
+
+impl Simulation {
+    /// The timer sending the gifts, `None` if the rate is so low that the gap does not fit, which
+    /// means never
+    fn timer(&self) -> Option<smol::Timer> {
+        let period = Duration::try_from_secs_f64(60.0 / self.rate).ok()?;
+        Some(smol::Timer::interval_at(
+            Instant::now().checked_add(period)?,
+            period,
+        ))
+    }
+
+    /// The `n`th gift to `username`, the kind, tier, months and gifter vary with `n`
+    pub fn gift(&self, username: &str, n: u64) -> String {
+        const GIFTERS: &[&str] = &["SimGifter", "GenerousViewer", "SubTrain", "LurkingWhale"];
+        const PLANS: &[&str] = &["1000", "1000", "2000", "1000", "3000"];
+        const MONTHS: &[u32] = &[1, 1, 3, 1, 6, 1, 12];
+
+        let n = n as usize;
+        let gifter = if n % 4 == 3 {
+            None
+        } else {
+            Some(GIFTERS[n % GIFTERS.len()])
+        };
+
+        FakeGift {
+            gifter,
+            recipient: username,
+            channel: &format!("simulated{}", n % self.channels.max(1) + 1),
+            plan: PLANS[n % PLANS.len()],
+            months: MONTHS[n % MONTHS.len()],
+            id: &format!("simulated-{}", n),
+        }
+        .line()
+    }
+}
+
+/// Logs in instantly and then sends a gift made up by a [`Simulation`] every interval
+#[derive(Debug, Clone)]
+pub struct SimulationConnector {
+    username: String,
+    simulation: Simulation,
+}
+
+impl Connector for SimulationConnector {
+    type Output = Simulated;
+
+    fn connect(&mut self) -> BoxedFuture<io::Result<Self::Output>> {
+        let simulated = Simulated {
+            data: welcome(&self.username).into_bytes(),
+            written: Vec::new(),
+            timer: self.simulation.timer(),
+            gifts: 0,
+            username: self.username.clone(),
+            simulation: self.simulation.clone(),
+            reader: None,
+        };
+        Box::pin(async move { Ok(simulated) })
+    }
+}
+
+/// The connection of a [`SimulationConnector`], it answers PINGs and drops everything else
+pub struct Simulated {
+    /// Lines not read yet
+    data: Vec<u8>,
+    /// Written bytes after the last complete line
+    written: Vec<u8>,
+    /// Never fires if `None`
+    timer: Option<smol::Timer>,
+    gifts: u64,
+    username: String,
+    simulation: Simulation,
+    /// Wakes the reader when a PONG is waiting
+    reader: Option<Waker>,
+}
+
+impl AsyncRead for Simulated {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        if this.data.is_empty() {
+            let fired = match &mut this.timer {
+                Some(timer) => Pin::new(timer).poll(cx).is_ready(),
+                None => false,
+            };
+            if !fired {
+                this.reader = Some(cx.waker().clone());
+                return Poll::Pending;
+            }
+
+            let gift = this.simulation.gift(&this.username, this.gifts);
+            this.gifts += 1;
+            this.data.extend_from_slice(gift.as_bytes());
+            this.data.extend_from_slice(b"\r\n");
+        }
+
+        let n = this.data.len().min(buf.len());
+        buf[..n].copy_from_slice(&this.data[..n]);
+        this.data.drain(..n);
+
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl AsyncWrite for Simulated {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _cx: &mut TaskContext<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        this.written.extend_from_slice(buf);
+
+        while let Some(end) = this.written.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = this.written.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            if let Some(token) = line.trim_end().strip_prefix("PING ") {
+                let pong = format!(":tmi.twitch.tv PONG tmi.twitch.tv {}\r\n", token);
+                this.data.extend_from_slice(pong.as_bytes());
+                if let Some(reader) = this.reader.take() {
+                    reader.wake();
+                }
+            }
+        }
+
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
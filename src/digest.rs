@@ -0,0 +1,157 @@
+use crate::{history::Gift, runtime, value::Prices};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Local, NaiveTime, Utc, Weekday};
+use lettre::{
+    message::header::ContentType, transport::smtp::authentication::Credentials, Message,
+    SmtpTransport, Transport,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt::Write};
+
+/// Where and when to send the weekly digest
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Digest {
+    /// SMTP server, connected to with STARTTLS
+    pub server: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    pub username: String,
+    pub password: String,
+    pub from: String,
+    pub to: String,
+    /// Day of the week the digest is sent on
+    #[serde(default = "default_day")]
+    pub day: Weekday,
+    /// Local time the digest is sent at
+    #[serde(default = "default_at")]
+    pub at: NaiveTime,
+}
+
+fn default_port() -> u16 {
+    587
+}
+
+fn default_day() -> Weekday {
+    Weekday::Mon
+}
+
+fn default_at() -> NaiveTime {
+    NaiveTime::from_hms_opt(9, 0, 0).unwrap()
+}
+
+impl Digest {
+    /// Render the digest of the week before `until` and mail it
+    pub async fn send(
+        &self,
+        history: &[Gift],
+        prices: &Prices,
+        until: DateTime<Utc>,
+    ) -> Result<()> {
+        let email = Message::builder()
+            .from(self.from.parse().context("Invalid from address")?)
+            .to(self.to.parse().context("Invalid to address")?)
+            .subject(format!(
+                "Gift farm digest for the week until {}",
+                until.with_timezone(&Local).format("%Y-%m-%d")
+            ))
+            .header(ContentType::TEXT_HTML)
+            .body(render(history, prices, until))?;
+
+        let mailer = SmtpTransport::starttls_relay(&self.server)?
+            .port(self.port)
+            .credentials(Credentials::new(
+                self.username.clone(),
+                self.password.clone(),
+            ))
+            .build();
+
+        runtime::unblock(move || mailer.send(&email))
+            .await
+            .context("Could not send the digest")?;
+
+        Ok(())
+    }
+}
+
+/// Render an HTML digest of the gifts received in the week before `until`
+pub fn render(history: &[Gift], prices: &Prices, until: DateTime<Utc>) -> String {
+    let since = until - Duration::weeks(1);
+    let gifts: Vec<&Gift> = history
+        .iter()
+        .filter(|gift| gift.time >= since && gift.time < until && gift.kind.is_gift())
+        .collect();
+
+    let mut per_day = BTreeMap::new();
+    let mut day = since.with_timezone(&Local).date_naive();
+    while day <= until.with_timezone(&Local).date_naive() {
+        per_day.insert(day, 0);
+        day = day.succ_opt().unwrap();
+    }
+
+    let mut per_channel: BTreeMap<&str, Vec<&Gift>> = BTreeMap::new();
+    for gift in &gifts {
+        *per_day
+            .entry(gift.time.with_timezone(&Local).date_naive())
+            .or_insert(0) += 1;
+        per_channel.entry(&gift.channel).or_default().push(gift);
+    }
+
+    let mut leaderboard: Vec<_> = per_channel.into_iter().collect();
+    leaderboard.sort_by(|a, b| b.1.len().cmp(&a.1.len()).then_with(|| a.0.cmp(b.0)));
+    leaderboard.truncate(10);
+
+    let months: u64 = gifts.iter().map(|gift| gift.months).sum();
+    let busiest = per_day.values().copied().max().unwrap_or(0).max(1);
+
+    // writing to a String never fails
+    let mut html = String::new();
+    html.push_str("<html><body style=\"font-family: sans-serif\">");
+    html.push_str("<h2>Totals</h2><ul>");
+    let _ = write!(html, "<li>{} gifts</li>", gifts.len());
+    let _ = write!(html, "<li>{} months</li>", months);
+    let _ = write!(
+        html,
+        "<li>{} estimated value</li>",
+        prices.total(gifts.iter().copied())
+    );
+    html.push_str("</ul>");
+
+    html.push_str("<h2>Gifts per day</h2><table>");
+    for (day, count) in per_day {
+        let _ = write!(
+            html,
+            "<tr><td>{}</td><td style=\"width: 300px\"><div style=\"background: #9146ff; \
+             width: {}%\">&nbsp;</div></td><td>{}</td></tr>",
+            day.format("%a %d.%m."),
+            count * 100 / busiest,
+            count
+        );
+    }
+    html.push_str("</table>");
+
+    html.push_str("<h2>Channels</h2>");
+    if leaderboard.is_empty() {
+        html.push_str("<p>No gifts this week.</p>");
+    } else {
+        html.push_str("<table><tr><th>Channel</th><th>Gifts</th><th>Value</th></tr>");
+        for (channel, gifts) in leaderboard {
+            let _ = write!(
+                html,
+                "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+                escape(channel),
+                gifts.len(),
+                prices.total(gifts)
+            );
+        }
+        html.push_str("</table>");
+    }
+
+    html.push_str("</body></html>");
+    html
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
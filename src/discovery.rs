@@ -0,0 +1,698 @@
+use crate::{
+    helix::{check, AppToken},
+    proxy,
+    registry::Registry,
+    runtime::{compat, sleep},
+};
+use anyhow::{anyhow, Context, Result};
+use chrono::Utc;
+use futures::{stream, StreamExt, TryStreamExt};
+use log::{debug, info, warn};
+use regex::{Regex, RegexBuilder};
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION},
+    Client, StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp::Reverse,
+    collections::HashSet,
+    fmt,
+    future::Future,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+const HELIX_STREAMS: &str = "https://api.twitch.tv/helix/streams";
+const HELIX_TOP_GAMES: &str = "https://api.twitch.tv/helix/games/top";
+const HELIX_GAMES: &str = "https://api.twitch.tv/helix/games";
+const HELIX_USERS: &str = "https://api.twitch.tv/helix/users";
+const HELIX_TEAMS: &str = "https://api.twitch.tv/helix/teams";
+const APP_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+const CLIENT_ID: &str = "34afn666979w6kmmr6b1bcnagfv6s3";
+/// Attempts per request before giving up
+const ATTEMPTS: u32 = 4;
+
+/// How channels are discovered
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Discovery {
+    /// Client-ID of the Twitch application used to look up streams
+    #[serde(default = "default_client_id")]
+    pub client_id: String,
+    /// Secret of that application, needed to get an app access token
+    #[serde(default)]
+    pub client_secret: Option<String>,
+    /// Skip streams with fewer viewers
+    #[serde(default)]
+    pub min_viewers: Option<u64>,
+    /// Skip streams with more viewers
+    #[serde(default)]
+    pub max_viewers: Option<u64>,
+    /// Only collect streams in these languages, like `"en"` or `"de"`. All languages if empty.
+    #[serde(default)]
+    pub languages: Vec<String>,
+    /// Also collect channels that are neither affiliate nor partner, they can't receive gifts
+    #[serde(default)]
+    pub include_unmonetized: bool,
+    /// Only look for streams in these games instead of the top 100
+    #[serde(default)]
+    pub games: Vec<String>,
+    /// Never look for streams in these games
+    #[serde(default)]
+    pub exclude_games: Vec<String>,
+    /// Regular expressions matched against stream titles, like `"subathon"` or `"sub goal"`.
+    /// Streams with a matching title come first.
+    #[serde(default)]
+    pub titles: Vec<String>,
+    /// Only collect streams with a title matching `titles`
+    #[serde(default)]
+    pub titles_only: bool,
+    /// Pages of 100 streams fetched for every game
+    #[serde(default = "default_pages_per_game")]
+    pub pages_per_game: usize,
+    /// Stop looking at a game after this many streams
+    #[serde(default)]
+    pub max_per_game: Option<usize>,
+    /// Maximum number of requests at the same time
+    #[serde(default = "default_concurrency")]
+    pub concurrency: usize,
+    /// Stop adding channels once this many are active
+    #[serde(default)]
+    pub max_channels: Option<usize>,
+    /// Twitch teams whose members are added by `discover --from teams`
+    #[serde(default)]
+    pub teams: Vec<String>,
+    /// Discover streams this often while farming and join the new channels right away
+    #[serde(default)]
+    pub every_minutes: Option<u64>,
+}
+
+fn default_pages_per_game() -> usize {
+    10
+}
+
+fn default_concurrency() -> usize {
+    8
+}
+
+fn default_client_id() -> String {
+    CLIENT_ID.to_string()
+}
+
+impl Discovery {
+    fn title_patterns(&self) -> Result<Vec<Regex>> {
+        self.titles
+            .iter()
+            .map(|title| {
+                RegexBuilder::new(title)
+                    .case_insensitive(true)
+                    .build()
+                    .with_context(|| format!("Invalid title pattern: {}", title))
+            })
+            .collect()
+    }
+
+    fn accepts(&self, stream: &Stream) -> bool {
+        self.min_viewers
+            .map_or(true, |min| stream.viewer_count >= min)
+            && self
+                .max_viewers
+                .map_or(true, |max| stream.viewer_count <= max)
+            && (self.languages.is_empty()
+                || self
+                    .languages
+                    .iter()
+                    .any(|language| language.eq_ignore_ascii_case(&stream.language)))
+    }
+}
+
+impl Default for Discovery {
+    fn default() -> Self {
+        Self {
+            client_id: default_client_id(),
+            client_secret: None,
+            min_viewers: None,
+            max_viewers: None,
+            languages: Vec::new(),
+            include_unmonetized: false,
+            games: Vec::new(),
+            exclude_games: Vec::new(),
+            titles: Vec::new(),
+            titles_only: false,
+            pages_per_game: default_pages_per_game(),
+            max_per_game: None,
+            concurrency: default_concurrency(),
+            max_channels: None,
+            teams: Vec::new(),
+            every_minutes: None,
+        }
+    }
+}
+
+/// The outcome of a discovery run
+#[derive(Debug, Default)]
+pub struct Discovered {
+    /// Channels that are live right now, best candidates first
+    pub channels: Vec<String>,
+    pub games: usize,
+    pub pages: usize,
+    /// Streams that passed the filters, before dropping duplicates and unmonetized channels
+    pub streams: usize,
+}
+
+/// What Twitch knows about a list of channels
+#[derive(Debug, Default)]
+pub struct Checked {
+    /// Channels whose Twitch account exists
+    pub existing: HashSet<String>,
+    /// Channels that are live right now
+    pub live: HashSet<String>,
+}
+
+/// The channels of a discovery run that are not known yet
+#[derive(Debug)]
+pub struct Selection {
+    pub new: Vec<String>,
+    pub duplicates: usize,
+    pub trimmed: usize,
+}
+
+/// Counts shared by the concurrent requests for the streams of each game
+struct Progress {
+    games: usize,
+    done: AtomicUsize,
+    pages: AtomicUsize,
+}
+
+#[derive(Debug, Deserialize)]
+struct Page<T> {
+    data: Vec<T>,
+    #[serde(default)]
+    pagination: Pagination,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Pagination {
+    cursor: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Stream {
+    user_login: String,
+    viewer_count: u64,
+    language: String,
+    title: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct User {
+    login: String,
+    /// `partner`, `affiliate` or empty
+    broadcaster_type: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Team {
+    #[serde(default)]
+    users: Vec<TeamMember>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TeamMember {
+    user_login: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Game {
+    id: String,
+    name: String,
+}
+
+/// Twitch did not accept the app access token
+#[derive(Debug)]
+struct TokenRejected;
+
+impl fmt::Display for TokenRejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "The app access token was rejected")
+    }
+}
+
+impl std::error::Error for TokenRejected {}
+
+async fn get<T>(client: &Client, url: &str, query: &[(&str, &str)], context: &str) -> Result<T>
+where
+    T: serde::de::DeserializeOwned,
+{
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+
+        let result = compat(client.get(url).query(query).send()).await;
+        let delay = match &result {
+            Ok(resp) if resp.status() == StatusCode::TOO_MANY_REQUESTS => {
+                Some(rate_limit_reset(resp).unwrap_or_else(|| backoff(attempt)))
+            }
+            Ok(resp) if resp.status().is_server_error() => Some(backoff(attempt)),
+            Ok(_) => None,
+            Err(_) => Some(backoff(attempt)),
+        };
+
+        match delay {
+            Some(delay) if attempt < ATTEMPTS => {
+                debug!("{}, trying again in {:?}", context, delay);
+                sleep(delay).await;
+            }
+            _ => {
+                let resp = result.with_context(|| context.to_string())?;
+                if resp.status() == StatusCode::UNAUTHORIZED {
+                    return Err(TokenRejected.into());
+                }
+
+                return compat(async { Ok(check(resp, context).await?.json::<T>().await?) }).await;
+            }
+        }
+    }
+}
+
+/// 1, 2, 4, ... seconds
+fn backoff(attempt: u32) -> Duration {
+    Duration::from_secs(1 << (attempt - 1).min(6))
+}
+
+/// Time until the rate limit bucket is refilled
+fn rate_limit_reset(resp: &reqwest::Response) -> Option<Duration> {
+    let reset: i64 = resp
+        .headers()
+        .get("ratelimit-reset")?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()?;
+
+    Some(Duration::from_secs(
+        (reset - Utc::now().timestamp()).clamp(1, 60) as u64,
+    ))
+}
+
+/// The configured games or the top games, without the excluded ones
+async fn get_games(client: &Client, discovery: &Discovery) -> Result<Vec<Game>> {
+    let mut games = if discovery.games.is_empty() {
+        get_top_games(client).await?
+    } else {
+        let query: Vec<(&str, &str)> = discovery
+            .games
+            .iter()
+            .map(|game| ("name", game.as_str()))
+            .collect();
+        let page: Page<Game> = get(client, HELIX_GAMES, &query, "Could not get games").await?;
+
+        for name in &discovery.games {
+            if !page
+                .data
+                .iter()
+                .any(|game| game.name.eq_ignore_ascii_case(name))
+            {
+                warn!("Unknown game: {}", name);
+            }
+        }
+
+        page.data
+    };
+
+    games.retain(|game| {
+        !discovery
+            .exclude_games
+            .iter()
+            .any(|name| game.name.eq_ignore_ascii_case(name))
+    });
+
+    Ok(games)
+}
+
+async fn get_top_games(client: &Client) -> Result<Vec<Game>> {
+    let page: Page<Game> = get(
+        client,
+        HELIX_TOP_GAMES,
+        &[("first", "100")],
+        "Could not get top games",
+    )
+    .await?;
+
+    Ok(page.data)
+}
+
+async fn get_all_streams_for_game(
+    client: &Client,
+    discovery: &Discovery,
+    progress: &Progress,
+    game: Game,
+) -> Vec<Stream> {
+    let mut streams = Vec::new();
+    let mut cursor: Option<String> = None;
+
+    for _ in 0..discovery.pages_per_game {
+        let mut query = vec![("game_id", game.id.as_str()), ("first", "100")];
+        for language in &discovery.languages {
+            query.push(("language", language.as_str()));
+        }
+        if let Some(cursor) = &cursor {
+            query.push(("after", cursor.as_str()));
+        }
+
+        let page: Page<Stream> =
+            match get(client, HELIX_STREAMS, &query, "Could not get streams").await {
+                Ok(page) => page,
+                Err(err) => {
+                    warn!("Skipping the remaining streams of {}: {:#}", game.name, err);
+                    break;
+                }
+            };
+        progress.pages.fetch_add(1, Ordering::Relaxed);
+
+        // streams are sorted by viewers, the next pages only have smaller ones
+        let exhausted = page
+            .data
+            .last()
+            .zip(discovery.min_viewers)
+            .is_some_and(|(stream, min)| stream.viewer_count < min);
+
+        streams.extend(
+            page.data
+                .into_iter()
+                .filter(|stream| discovery.accepts(stream)),
+        );
+
+        if let Some(max) = discovery.max_per_game {
+            if streams.len() >= max {
+                streams.truncate(max);
+                break;
+            }
+        }
+
+        if exhausted {
+            break;
+        }
+
+        cursor = match page.pagination.cursor {
+            Some(next) => Some(next),
+            None => break,
+        };
+    }
+
+    info!(
+        "[{}/{}] Found {} channels streaming {}",
+        progress.done.fetch_add(1, Ordering::Relaxed) + 1,
+        progress.games,
+        streams.len(),
+        game.name
+    );
+
+    streams
+}
+
+/// Logins of the affiliates and partners among `channels`
+async fn get_monetized(
+    client: &Client,
+    discovery: &Discovery,
+    channels: &[String],
+) -> Result<HashSet<String>> {
+    // collected so the future stays `Send`, see rust-lang/rust#102211
+    let pages: Vec<_> = channels
+        .chunks(100)
+        .map(|chunk| async move {
+            let query: Vec<(&str, &str)> = chunk
+                .iter()
+                .map(|channel| ("login", channel.as_str()))
+                .collect();
+
+            let page: Page<User> = get(client, HELIX_USERS, &query, "Could not get users").await?;
+            Ok::<_, anyhow::Error>(
+                page.data
+                    .into_iter()
+                    .filter(|user| !user.broadcaster_type.is_empty())
+                    .map(|user| user.login)
+                    .collect::<Vec<_>>(),
+            )
+        })
+        .collect();
+
+    let pages: Vec<Vec<String>> = stream::iter(pages)
+        .buffer_unordered(discovery.concurrency.max(1))
+        .try_collect()
+        .await?;
+
+    Ok(pages.into_iter().flatten().collect())
+}
+
+/// Logins among `channels` that are returned by `url`, which is queried with `key=channel`
+async fn lookup<T, F>(
+    client: &Client,
+    discovery: &Discovery,
+    channels: &[String],
+    url: &str,
+    key: &str,
+    login: F,
+) -> Result<HashSet<String>>
+where
+    T: serde::de::DeserializeOwned,
+    F: Fn(T) -> String + Copy,
+{
+    let pages: Vec<Vec<String>> = stream::iter(channels.chunks(100))
+        .map(|chunk| async move {
+            let mut query: Vec<(&str, &str)> = chunk
+                .iter()
+                .map(|channel| (key, channel.as_str()))
+                .collect();
+            query.push(("first", "100"));
+
+            let page: Page<T> = get(client, url, &query, "Could not look up channels").await?;
+            Ok::<_, anyhow::Error>(page.data.into_iter().map(login).collect())
+        })
+        .buffer_unordered(discovery.concurrency.max(1))
+        .try_collect()
+        .await?;
+
+    Ok(pages.into_iter().flatten().collect())
+}
+
+async fn check_all(client: &Client, discovery: &Discovery, channels: &[String]) -> Result<Checked> {
+    Ok(Checked {
+        existing: lookup(
+            client,
+            discovery,
+            channels,
+            HELIX_USERS,
+            "login",
+            |user: User| user.login,
+        )
+        .await?,
+        live: lookup(
+            client,
+            discovery,
+            channels,
+            HELIX_STREAMS,
+            "user_login",
+            |stream: Stream| stream.user_login,
+        )
+        .await?,
+    })
+}
+
+/// Look up which of `channels` exist and which are live right now
+pub async fn check_channels(discovery: &Discovery, channels: &[String]) -> Result<Checked> {
+    with_app_token(discovery, |client| async move {
+        check_all(&client, discovery, channels).await
+    })
+    .await
+}
+
+/// Members of the Twitch teams in the config by team name
+pub async fn get_team_members(discovery: &Discovery) -> Result<Vec<(String, Vec<String>)>> {
+    with_app_token(discovery, |client| async move {
+        let mut teams = Vec::with_capacity(discovery.teams.len());
+        for name in &discovery.teams {
+            let page: Page<Team> = get(
+                &client,
+                HELIX_TEAMS,
+                &[("name", name.as_str())],
+                "Could not get team",
+            )
+            .await?;
+            let members: Vec<String> = page
+                .data
+                .into_iter()
+                .flat_map(|team| team.users)
+                .map(|user| user.user_login)
+                .collect();
+
+            info!("Found {} members of team {}", members.len(), name);
+            teams.push((name.clone(), members));
+        }
+
+        Ok(teams)
+    })
+    .await
+}
+
+/// Run `f` with a client using the app access token, and once more with a new token if Twitch
+/// rejects the cached one
+async fn with_app_token<T, F, Fut>(discovery: &Discovery, f: F) -> Result<T>
+where
+    F: Fn(Client) -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let secret = discovery.client_secret.as_deref().ok_or_else(|| {
+        anyhow!("Set client_secret in the discovery section of the config to use the Twitch API")
+    })?;
+    let token = AppToken::get(&discovery.client_id, secret).await?;
+
+    match f(helix_client(&discovery.client_id, &token)?).await {
+        // the token was revoked before it expired
+        Err(err) if err.is::<TokenRejected>() => {
+            warn!("{}, getting a new one", err);
+            let token = AppToken::fetch(&discovery.client_id, secret).await?;
+            f(helix_client(&discovery.client_id, &token)?).await
+        }
+        result => result,
+    }
+}
+
+fn helix_client(client_id: &str, token: &AppToken) -> Result<Client> {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        AUTHORIZATION,
+        HeaderValue::from_str(&format!("Bearer {}", token.access_token))?,
+    );
+    headers.insert(
+        HeaderName::from_static("client-id"),
+        HeaderValue::from_str(client_id)?,
+    );
+
+    Ok(proxy::client_builder()
+        .default_headers(headers)
+        .user_agent(APP_USER_AGENT)
+        .build()?)
+}
+
+/// Look for channels that are live right now
+pub async fn get_streams(discovery: &Discovery) -> Result<Discovered> {
+    let patterns = discovery.title_patterns()?;
+
+    let (client, games) = with_app_token(discovery, |client| async move {
+        let games = get_games(&client, discovery).await?;
+        Ok((client, games))
+    })
+    .await?;
+
+    info!("Found {} games", games.len());
+    let per_game = discovery.pages_per_game * 100;
+    info!(
+        "Getting up to {} streams",
+        discovery
+            .max_per_game
+            .map_or(per_game, |max| max.min(per_game))
+            * games.len()
+    );
+
+    let client = &client;
+    let progress = Progress {
+        games: games.len(),
+        done: AtomicUsize::new(0),
+        pages: AtomicUsize::new(0),
+    };
+    let progress = &progress;
+    let pages: Vec<Vec<Stream>> = stream::iter(games)
+        .map(|game| get_all_streams_for_game(client, discovery, progress, game))
+        .buffered(discovery.concurrency.max(1))
+        .collect()
+        .await;
+    let mut streams: Vec<Stream> = pages.into_iter().flatten().collect();
+
+    // streams running events like subathons come first, then the biggest ones
+    let matches = |stream: &Stream| {
+        patterns
+            .iter()
+            .any(|pattern| pattern.is_match(&stream.title))
+    };
+    if discovery.titles_only {
+        streams.retain(matches);
+    }
+    streams.sort_by_cached_key(|stream| (!matches(stream), Reverse(stream.viewer_count)));
+    if !patterns.is_empty() {
+        info!(
+            "Found {} streams with a matching title",
+            streams.iter().filter(|stream| matches(stream)).count()
+        );
+    }
+
+    let mut discovered = Discovered {
+        games: progress.games,
+        pages: progress.pages.load(Ordering::Relaxed),
+        streams: streams.len(),
+        ..Discovered::default()
+    };
+
+    let mut seen = HashSet::new();
+    let mut channels: Vec<String> = streams
+        .into_iter()
+        .map(|stream| stream.user_login)
+        .filter(|channel| seen.insert(channel.clone()))
+        .collect();
+
+    if discovery.include_unmonetized {
+        discovered.channels = channels;
+        return Ok(discovered);
+    }
+
+    let monetized = get_monetized(client, discovery, &channels).await?;
+    info!(
+        "Dropping {} channels that are neither affiliate nor partner",
+        channels.len().saturating_sub(monetized.len())
+    );
+    channels.retain(|channel| monetized.contains(channel));
+
+    discovered.channels = channels;
+    Ok(discovered)
+}
+
+/// Drop channels that are already known and keep to `max_new` and the cap from the config
+pub fn select(
+    registry: &Registry,
+    account: Option<&str>,
+    max_new: Option<usize>,
+    discovery: &Discovery,
+    channels: &[String],
+) -> Result<Selection> {
+    let known: HashSet<String> = registry.known(account)?.into_iter().collect();
+    let mut new: Vec<String> = channels
+        .iter()
+        .filter(|channel| !known.contains(*channel))
+        .cloned()
+        .collect();
+    let duplicates = channels.len() - new.len();
+    let mut trimmed = 0;
+
+    let room = match discovery.max_channels {
+        Some(max) => Some(max.saturating_sub(registry.list(account)?.len())),
+        None => None,
+    };
+    if let Some(limit) = max_new.into_iter().chain(room).min() {
+        if new.len() > limit {
+            // the channels are sorted best first
+            info!(
+                "Only adding the best {} of {} new channels",
+                limit,
+                new.len()
+            );
+            trimmed = new.len() - limit;
+            new.truncate(limit);
+        }
+    }
+
+    Ok(Selection {
+        new,
+        duplicates,
+        trimmed,
+    })
+}
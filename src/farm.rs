@@ -0,0 +1,2894 @@
+#[cfg(feature = "smtp")]
+use crate::digest::Digest;
+#[cfg(feature = "plugins")]
+use crate::plugin::Plugin;
+#[cfg(feature = "scripting")]
+use crate::script::Scripts;
+use crate::{
+    config::{self, Friends, Healthcheck, Prune, Rotation, Thanks, Whisper},
+    connector::{
+        self, connect_capturing, is_login_failure, Capture, Endpoint, LoginFailed, Monitor,
+        Rejection, Simulation,
+    },
+    discovery::{get_streams, select, Discovery},
+    health::{self, AccountHealth, Health},
+    helix::Helix,
+    history::{ChannelLogs, Gift, GiftKind, History, Tier},
+    lock::InstanceLock,
+    logger,
+    milestone::MilestoneTracker,
+    mqtt,
+    notify::{Notification, Notifier},
+    proxy, redis,
+    registry::{Registry, Source},
+    runtime::{self, compat, sleep, sleep_until},
+    state::{ChannelState, WINDOW_DAYS},
+    stream,
+    summary::Summary,
+    systemd,
+    template::render,
+    value::Prices,
+    Account, Config,
+};
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Datelike, Local, NaiveDate, NaiveTime, Utc, Weekday};
+use futures::{future::join_all, Stream, StreamExt, TryFutureExt};
+use log::{debug, error, info, warn};
+use messages::{UserNotice, UserState};
+use serde::Serialize;
+use smol::{
+    channel::{Receiver, Sender, TrySendError},
+    future::FutureExt,
+    net::TcpListener,
+};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    fmt, fs,
+    future::Future,
+    io,
+    path::PathBuf,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use twitchchat::{
+    commands,
+    messages::{self, Commands, NoticeType},
+    twitch::BadgeKind,
+    AsyncRunner, RunnerError, Status, UserConfig,
+};
+
+/// See [`FarmBuilder::on_gift`]
+type Callback = Box<dyn Fn(&Notification) + Send + Sync>;
+
+/// The name of a channel, shared by everything that refers to the channel instead of copied
+type Channel = Arc<str>;
+
+/// Hands out the same [`Channel`] for the same name
+#[derive(Default)]
+struct Interner(Mutex<HashSet<Channel>>);
+
+impl Interner {
+    fn intern(&self, name: &str) -> Channel {
+        let mut names = self.0.lock().unwrap();
+        match names.get(name) {
+            Some(channel) => channel.clone(),
+            None => {
+                let channel = Channel::from(name);
+                names.insert(channel.clone());
+                channel
+            }
+        }
+    }
+
+    /// Forget the names nothing refers to anymore
+    fn shrink(&self) {
+        self.0
+            .lock()
+            .unwrap()
+            .retain(|channel| Arc::strong_count(channel) > 1);
+    }
+}
+
+/// Farms gifted subs with all accounts of a config
+pub struct Farm {
+    config: Config<'static>,
+    force: bool,
+    on_gift: Vec<Callback>,
+    handlers: Vec<Box<dyn EventHandler>>,
+    replay: Option<PathBuf>,
+    inject: Option<String>,
+    capture: Option<PathBuf>,
+    simulation: Option<Simulation>,
+    stop: Option<Pin<Box<dyn Future<Output = ()> + Send>>>,
+}
+
+/// A gift, upgrade or pay forward that landed on one of the accounts
+#[derive(Debug, Clone, Serialize)]
+pub struct GiftEvent {
+    pub kind: GiftKind,
+    pub channel: String,
+    pub gifter: String,
+    /// The account that received the gift, `None` for community pay forwards
+    pub recipient: Option<String>,
+    pub tier: Tier,
+    pub months: u64,
+    /// All tags of the USERNOTICE the gift was announced with
+    pub tags: HashMap<String, String>,
+}
+
+/// What happened on the connections of all accounts since the farm started
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionStats {
+    pub started: DateTime<Utc>,
+    pub uptime_secs: u64,
+    /// Bytes of the IRC lines the bots received
+    pub bytes_received: u64,
+    /// IRC lines the bots received
+    pub messages: u64,
+    pub user_notices: u64,
+    pub reconnects: u64,
+    /// Channels at least one bot is in right now
+    pub joined: usize,
+    /// Gifts, upgrades and pay forwards that landed on the accounts
+    pub gifts: u64,
+    pub gifts_by_tier: BTreeMap<Tier, u64>,
+    /// Channels with at least one USERNOTICE, like a sub or a gift
+    pub active_channels: usize,
+}
+
+impl SessionStats {
+    pub fn messages_per_second(&self) -> f64 {
+        self.messages as f64 / self.uptime_secs.max(1) as f64
+    }
+}
+
+impl fmt::Display for SessionStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "up {}h {}m, {} channels joined, {} messages ({:.1}/s), {:.1} MB received, \
+             {} USERNOTICEs, {} reconnects, {} gifts",
+            self.uptime_secs / 3600,
+            self.uptime_secs / 60 % 60,
+            self.joined,
+            self.messages,
+            self.messages_per_second(),
+            self.bytes_received as f64 / 1_000_000.0,
+            self.user_notices,
+            self.reconnects,
+            self.gifts
+        )
+    }
+}
+
+/// Reacts to what the bots do while farming. Every method does nothing by default.
+///
+/// The methods are called from the bots, so they should return quickly.
+pub trait EventHandler: Send + Sync {
+    fn on_gift(&self, _event: &GiftEvent) {}
+
+    /// `account` joined `channel`
+    fn on_join(&self, _account: &str, _channel: &str) {}
+
+    /// `account` left `channel`
+    fn on_part(&self, _account: &str, _channel: &str) {}
+
+    /// `account` lost the connection to Twitch and connected again
+    fn on_reconnect(&self, _account: &str) {}
+
+    /// Something went wrong for `account`. Errors that stop the account are reported as well.
+    fn on_error(&self, _account: &str, _error: &anyhow::Error) {}
+
+    /// Twitch answered a PING of `account` after `latency`
+    fn on_latency(&self, _account: &str, _latency: Duration) {}
+
+    /// The session statistics, every [`STATS_INTERVAL`] and when the farm stops
+    fn on_stats(&self, _stats: &SessionStats) {}
+}
+
+impl<H: EventHandler + ?Sized> EventHandler for Arc<H> {
+    fn on_gift(&self, event: &GiftEvent) {
+        (**self).on_gift(event)
+    }
+
+    fn on_join(&self, account: &str, channel: &str) {
+        (**self).on_join(account, channel)
+    }
+
+    fn on_part(&self, account: &str, channel: &str) {
+        (**self).on_part(account, channel)
+    }
+
+    fn on_reconnect(&self, account: &str) {
+        (**self).on_reconnect(account)
+    }
+
+    fn on_error(&self, account: &str, error: &anyhow::Error) {
+        (**self).on_error(account, error)
+    }
+
+    fn on_latency(&self, account: &str, latency: Duration) {
+        (**self).on_latency(account, latency)
+    }
+
+    fn on_stats(&self, stats: &SessionStats) {
+        (**self).on_stats(stats)
+    }
+}
+
+/// Feeds the stream of [`FarmBuilder::gift_events`]
+struct GiftEvents(Sender<GiftEvent>);
+
+impl EventHandler for GiftEvents {
+    fn on_gift(&self, event: &GiftEvent) {
+        // the stream was dropped if this fails
+        self.0.try_send(event.clone()).ok();
+    }
+}
+
+/// Builds a [`Farm`]
+pub struct FarmBuilder {
+    farm: Farm,
+}
+
+impl FarmBuilder {
+    /// Farm even if another process is already farming with the same account
+    pub fn force(mut self, force: bool) -> Self {
+        self.farm.force = force;
+        self
+    }
+
+    /// Call `callback` for every gift, upgrade and pay forward that lands on one of the accounts
+    pub fn on_gift(mut self, callback: impl Fn(&Notification) + Send + Sync + 'static) -> Self {
+        self.farm.on_gift.push(Box::new(callback));
+        self
+    }
+
+    /// Receive every gift, upgrade and pay forward that lands on one of the accounts. The
+    /// stream ends when the farm stops.
+    pub fn gift_events(&mut self) -> impl Stream<Item = GiftEvent> {
+        let (sender, receiver) = smol::channel::unbounded();
+        self.farm.handlers.push(Box::new(GiftEvents(sender)));
+        receiver
+    }
+
+    /// Let `handler` know what the bots do
+    pub fn handler(mut self, handler: impl EventHandler + 'static) -> Self {
+        self.farm.handlers.push(Box::new(handler));
+        self
+    }
+
+    /// Feed the raw IRC lines recorded in `path` to the first account instead of connecting and
+    /// stop at the end of the file. Nothing is sent, stored or notified, only logged.
+    pub fn replay(mut self, path: impl Into<PathBuf>) -> Self {
+        self.farm.replay = Some(path.into());
+        self
+    }
+
+    /// Feed the raw IRC `lines` to the first account like [`replay`](Self::replay), but notify
+    /// and publish the gifts as if they were real. Nothing is sent to chat or stored.
+    pub fn inject(mut self, lines: impl Into<String>) -> Self {
+        self.farm.inject = Some(lines.into());
+        self
+    }
+
+    /// Append every line Twitch sends to the file at `path`, see [`Capture`]
+    pub fn capture(mut self, path: impl Into<PathBuf>) -> Self {
+        self.farm.capture = Some(path.into());
+        self
+    }
+
+    /// Feed gifts made up by `simulation` to the first account instead of connecting. They are
+    /// stored and notified like real gifts, only chat messages are not sent.
+    pub fn simulate(mut self, simulation: Simulation) -> Self {
+        self.farm.simulation = Some(simulation);
+        self
+    }
+
+    /// Stop farming once `signal` completes. The queued gifts are still stored and the session
+    /// is summed up.
+    pub fn stop_on(mut self, signal: impl Future<Output = ()> + Send + 'static) -> Self {
+        self.farm.stop = Some(Box::pin(signal));
+        self
+    }
+
+    pub fn build(self) -> Result<Farm> {
+        if self.farm.config.accounts.is_empty() {
+            return Err(anyhow!("No accounts configured, run `auth` first"));
+        }
+        if let Some(simulation) = &self.farm.simulation {
+            if !(simulation.rate > 0.0 && simulation.rate.is_finite()) {
+                return Err(anyhow!(
+                    "The simulated gifts per minute have to be positive"
+                ));
+            }
+        }
+
+        Ok(self.farm)
+    }
+}
+
+impl Farm {
+    pub fn builder(config: Config<'static>) -> FarmBuilder {
+        FarmBuilder {
+            farm: Self {
+                config,
+                force: false,
+                on_gift: Vec::new(),
+                handlers: Vec::new(),
+                replay: None,
+                inject: None,
+                capture: None,
+                simulation: None,
+                stop: None,
+            },
+        }
+    }
+
+    /// Join the channels of all accounts and farm until every account stopped
+    pub async fn run(self) -> Result<()> {
+        let config = self.config;
+        let mut handlers = self.handlers;
+        #[cfg(feature = "plugins")]
+        for plugin in Plugin::load_all(&config.plugins)? {
+            handlers.push(Box::new(plugin));
+        }
+
+        // the config does not have to come from `Config::load`
+        proxy::set(config.proxy()?);
+        logger::set(&config.logging)?;
+
+        // injected lines are notified like real gifts, replayed ones only logged
+        let dry_run = self.replay.is_some();
+        let lines = match &self.replay {
+            Some(path) => Some(
+                fs::read_to_string(path)
+                    .with_context(|| format!("Could not read {}", path.display()))?,
+            ),
+            None => self.inject.clone(),
+        };
+
+        let stream = config
+            .stream
+            .clone()
+            .map(|stream| Arc::new(stream::Producer::new(stream)));
+        if let (Some(stream), false) = (&stream, dry_run) {
+            handlers.push(Box::new(stream.clone()));
+        }
+
+        let _locks = if self.force || lines.is_some() || self.simulation.is_some() {
+            Vec::new()
+        } else {
+            config
+                .accounts
+                .iter()
+                .map(|account| InstanceLock::acquire(&account.username))
+                .collect::<Result<Vec<_>>>()?
+        };
+
+        let history = History::load()?;
+        let since = Utc::now() - chrono::Duration::days(WINDOW_DAYS);
+        let (gifts, queue) = smol::channel::bounded(GIFT_QUEUE);
+        let shared = Arc::new(Shared {
+            history: History::open()?,
+            recent_gifts: Mutex::new(
+                history
+                    .iter()
+                    .filter(|gift| gift.time >= since)
+                    .cloned()
+                    .collect(),
+            ),
+            registry: Mutex::new(Registry::open()?),
+            channel_logs: config.channel_logs.then(ChannelLogs::open).transpose()?,
+            notifier: Notifier::new(config.notifications.clone())?.dry_run(dry_run),
+            friend_notifier: Notifier::new(
+                config
+                    .friends
+                    .as_ref()
+                    .map_or_else(Vec::new, |friends| friends.notifications.clone()),
+            )?
+            .dry_run(dry_run),
+            friends: config.friends.clone(),
+            mqtt: config.mqtt.clone().map(mqtt::Publisher::new),
+            redis: config.redis.clone().map(redis::Publisher::new),
+            stream,
+            batch: config
+                .notification_batch
+                .map(|seconds| (Duration::from_secs(seconds.max(1)), Mutex::default())),
+            muted: config
+                .muted
+                .iter()
+                .map(|channel| channel.trim_start_matches('#').to_lowercase())
+                .collect(),
+            prices: config.prices()?,
+            thanks: config.thanks.clone(),
+            milestones: Mutex::new(MilestoneTracker::new(config.milestones.clone(), &history)),
+            counters: Mutex::default(),
+            on_gift: self.on_gift,
+            handlers,
+            #[cfg(feature = "scripting")]
+            scripts: Scripts::load()?,
+            state: Mutex::new(ChannelState::load()?),
+            bots: Mutex::default(),
+            notify_bans: config.notify_bans,
+            irc: config.irc.clone(),
+            replay: lines,
+            dry_run,
+            capture: self.capture.map(Capture::open).transpose()?.map(Arc::new),
+            simulation: self.simulation,
+            gifts,
+            names: Interner::default(),
+        });
+
+        let (done, processed) = smol::channel::bounded(1);
+        let _processor = runtime::spawn(process_gifts(queue, shared.clone(), done));
+
+        let farming = farm_accounts(&config, shared.clone());
+        let result = match self.stop {
+            Some(stop) => {
+                farming
+                    .or(async {
+                        stop.await;
+                        info!("Stopping");
+                        Ok(())
+                    })
+                    .await
+            }
+            None => farming.await,
+        };
+        // let the queued gifts be stored and notified before returning
+        shared.gifts.close();
+        processed.recv().await.ok();
+        send_batch(&shared).await;
+        shared.notifier.flush().await;
+        shared.friend_notifier.flush().await;
+        if !shared.dry_run() {
+            publish_queued(&shared).await;
+        }
+        // the state is otherwise only saved every few minutes by `track_channels`
+        if !shared.fed() {
+            if let Err(err) = shared.state.lock().unwrap().save() {
+                error!("Could not save the channel state: {:#}", err);
+            }
+        }
+
+        let stats = shared.counters.lock().unwrap().stats();
+        info!("Session ended: {}", stats);
+        if !stats.gifts_by_tier.is_empty() {
+            let tiers: Vec<String> = stats
+                .gifts_by_tier
+                .iter()
+                .map(|(tier, count)| format!("{} {}", count, tier.as_str()))
+                .collect();
+            info!("Gifts by tier: {}", tiers.join(", "));
+        }
+        info!(
+            "{} channels had subs or gifts this session",
+            stats.active_channels
+        );
+        for handler in &shared.handlers {
+            handler.on_stats(&stats);
+        }
+
+        result
+    }
+}
+
+/// Run the bots of all accounts of `config`, or the one replaying or simulating
+async fn farm_accounts(config: &Config<'static>, shared: Arc<Shared>) -> Result<()> {
+    if shared.replay.is_some() {
+        return replay(&config.accounts[0], shared).await;
+    }
+    if shared.simulation.is_some() {
+        return simulate(&config.accounts[0], shared).await;
+    }
+
+    // the tasks are cancelled when they are dropped at the end of `farm_accounts`
+    let _stats = runtime::spawn(log_stats(shared.clone()));
+    let _healthcheck = config
+        .healthcheck
+        .clone()
+        .map(|healthcheck| runtime::spawn(ping_healthcheck(healthcheck, shared.clone())));
+    let _health = match &config.health_endpoint {
+        Some(address) => {
+            let listener = TcpListener::bind(address.as_str())
+                .await
+                .with_context(|| format!("Could not serve the health endpoint on {}", address))?;
+            info!("Serving the health endpoint on {}", address);
+            let shared = shared.clone();
+            Some(runtime::spawn(health::serve(listener, move || {
+                shared.counters.lock().unwrap().health()
+            })))
+        }
+        None => None,
+    };
+    let _systemd = if systemd::enabled() {
+        Some(runtime::spawn(notify_systemd(shared.clone())))
+    } else {
+        None
+    };
+    let _batches = shared
+        .batch
+        .as_ref()
+        .map(|(window, _)| runtime::spawn(send_batches(*window, shared.clone())));
+    let _mqtt = shared
+        .mqtt
+        .is_some()
+        .then(|| runtime::spawn(publish_mqtt(shared.clone())));
+    let _redis = shared.redis.is_some().then(|| {
+        let shared = shared.clone();
+        runtime::spawn(async move {
+            if let Some(redis) = &shared.redis {
+                redis.run().await;
+            }
+        })
+    });
+    let _stream = shared.stream.is_some().then(|| {
+        let shared = shared.clone();
+        runtime::spawn(async move {
+            if let Some(stream) = &shared.stream {
+                stream.run().await;
+            }
+        })
+    });
+    let _tracker = runtime::spawn(track_channels(config.prune.clone(), shared.clone()));
+    let _summary = config
+        .daily_summary
+        .map(|at| runtime::spawn(daily_summary(at, shared.clone())));
+    #[cfg(feature = "smtp")]
+    let _digest = config
+        .digest
+        .clone()
+        .map(|digest| runtime::spawn(weekly_digest(digest, shared.clone())));
+
+    let _discovery = config.discovery.every_minutes.map(|minutes| {
+        runtime::spawn(discover_channels(
+            Duration::from_secs(minutes.max(1) * 60),
+            config.discovery.clone(),
+        ))
+    });
+
+    let channels = {
+        let registry = shared.registry.lock().unwrap();
+        (0..config.accounts.len())
+            .map(|index| {
+                Ok(registry
+                    .channels_for(config, index)?
+                    .iter()
+                    .map(|channel| shared.names.intern(channel))
+                    .collect())
+            })
+            .collect::<Result<Vec<Vec<Channel>>>>()?
+    };
+
+    log_startup(config, &channels);
+
+    let _watcher = runtime::spawn(watch_channels(
+        config.clone(),
+        channels.clone(),
+        shared.clone(),
+    ));
+
+    let bots = config
+        .accounts
+        .iter()
+        .zip(channels)
+        .map(|(account, channels)| {
+            let shared = shared.clone();
+            async move {
+                let result = farm(account, channels, config, shared.clone()).await;
+
+                if let Err(err) = &result {
+                    error!("Stopped farming as {}: {:#}", account.username, err);
+                    for handler in &shared.handlers {
+                        handler.on_error(&account.username, err);
+                    }
+
+                    if let Some(LoginFailed { username }) = err.downcast_ref() {
+                        shared
+                            .notify(&Notification::LoginFailed {
+                                username: username.clone(),
+                            })
+                            .await;
+                    }
+                }
+
+                result
+            }
+        });
+
+    let results = join_all(bots).await;
+    let failed = results.iter().filter(|result| result.is_err()).count();
+
+    if failed > 0 {
+        return Err(anyhow!(
+            "{} of {} accounts stopped farming",
+            failed,
+            results.len()
+        ));
+    }
+
+    Ok(())
+}
+
+/// Sum up what is about to be farmed and how, before joining takes a while
+fn log_startup(config: &Config<'_>, channels: &[Vec<Channel>]) {
+    let accounts: Vec<String> = config
+        .accounts
+        .iter()
+        .zip(channels)
+        .map(|(account, channels)| format!("{} ({} channels)", account.username, channels.len()))
+        .collect();
+    info!("Farming as {}", accounts.join(", "));
+    info!("Config: {}", Config::origin());
+
+    let mut sinks: Vec<String> = config
+        .notifications
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    if let Some(mqtt) = &config.mqtt {
+        sinks.push(format!("MQTT on {}", mqtt.broker));
+    }
+    if let Some(redis) = &config.redis {
+        sinks.push(format!(
+            "Redis channel {} on {}",
+            redis.channel, redis.address
+        ));
+    }
+    if let Some(stream) = &config.stream {
+        sinks.push(stream.target.to_string());
+    }
+    info!(
+        "Notifications: {}{}",
+        if sinks.is_empty() {
+            "none".to_string()
+        } else {
+            sinks.join(", ")
+        },
+        match config.muted.len() {
+            0 => String::new(),
+            muted => format!(", {} channels muted", muted),
+        }
+    );
+    info!(
+        "Storage: gifts in {}, channels in {}",
+        History::path().display(),
+        Registry::path().display()
+    );
+
+    let mut joins = format!(
+        "Joins: one channel every {} ms, up to {} per JOIN",
+        JOIN_INTERVAL.as_millis(),
+        config.join_batch.max(1)
+    );
+    if let Some(rotation) = &config.rotation {
+        joins.push_str(&format!(
+            ", rotating slices of {} channels every {} minutes",
+            rotation.channels.max(1),
+            rotation.minutes.max(1)
+        ));
+    }
+    info!("{}", joins);
+}
+
+/// Store the gifts of all bots in the history and send notifications with [`GIFT_WORKERS`] at once
+/// until the queue is closed,
+/// then signal `done`
+async fn process_gifts(queue: Receiver<Gift>, shared: Arc<Shared>, done: Sender<()>) {
+    // a slow sink only holds up one worker
+    queue
+        .for_each_concurrent(GIFT_WORKERS, |gift| store(&shared, gift))
+        .await;
+
+    done.send(()).await.ok();
+}
+
+/// Store `gift` in the history and send a notification
+async fn store(shared: &Shared, gift: Gift) {
+    if shared.fed() {
+        debug!("Not recording the gift, it was replayed or injected");
+    } else {
+        if let Err(err) = shared.history.append(&gift) {
+            error!("Could not record gift: {:#}", err);
+        }
+        {
+            let since = Utc::now() - chrono::Duration::days(WINDOW_DAYS);
+            let mut recent = shared.recent_gifts.lock().unwrap();
+            recent.retain(|gift| gift.time >= since);
+            recent.push(gift.clone());
+        }
+        if let Some(logs) = &shared.channel_logs {
+            if let Err(err) = logs.append(&gift) {
+                error!("Could not log gift of the channel: {:#}", err);
+            }
+        }
+    }
+    if gift.kind.is_gift() && !shared.fed() {
+        let channel = gift.channel.trim_start_matches('#');
+        if let Err(err) = shared.registry.lock().unwrap().gift(channel, gift.time) {
+            error!("Could not count gift in the channel registry: {:#}", err);
+        }
+    }
+
+    let muted = shared.muted.contains(gift.channel.trim_start_matches('#'));
+    if muted {
+        debug!("Not notifying, {} is muted", gift.channel);
+    }
+
+    // a made up gift must not use up a milestone of the real ones
+    let milestones = if shared.fed() {
+        Vec::new()
+    } else {
+        shared.milestones.lock().unwrap().record(&gift)
+    };
+    for milestone in milestones {
+        info!("Milestone reached: {}", milestone);
+
+        let notification = Notification::Milestone {
+            milestone,
+            gift: gift.clone(),
+        };
+        if muted {
+            shared.stream(&notification);
+        } else {
+            shared.notify(&notification).await;
+        }
+    }
+
+    let notification = if gift.kind.is_upgrade() {
+        Notification::Upgrade(gift)
+    } else if gift.kind.is_pay_forward() {
+        Notification::PayForward(gift)
+    } else {
+        Notification::Gift {
+            value: shared.prices.value(&gift),
+            friend: gift.kind == GiftKind::SubGift && shared.is_friend(&gift.gifter),
+            gift,
+        }
+    };
+
+    for callback in &shared.on_gift {
+        callback(&notification);
+    }
+    shared.stream(&notification);
+    if !shared.dry_run() {
+        if let (Some(mqtt), Notification::Gift { gift, value, .. }) = (&shared.mqtt, &notification)
+        {
+            mqtt.gift(gift, value);
+        }
+        if let Some(redis) = &shared.redis {
+            redis.publish(&notification);
+        }
+    }
+
+    let friend = matches!(notification, Notification::Gift { friend: true, .. });
+
+    futures::join!(
+        async {
+            match (&shared.batch, &notification) {
+                _ if muted => {}
+                (Some((_, batch)), Notification::Gift { gift, .. }) => {
+                    batch.lock().unwrap().push(gift.clone())
+                }
+                _ => shared.notifier.notify(&notification).await,
+            }
+        },
+        async {
+            if friend {
+                shared.friend_notifier.notify(&notification).await;
+            }
+        }
+    );
+}
+
+/// Publish the events still queued for MQTT, Redis and the stream, unless they cannot be reached
+/// within [`PUBLISH_TIMEOUT`]
+async fn publish_queued(shared: &Shared) {
+    let publishing = async {
+        futures::join!(
+            async {
+                if let Some(mqtt) = &shared.mqtt {
+                    mqtt.finish().await;
+                }
+            },
+            async {
+                if let Some(redis) = &shared.redis {
+                    redis.finish().await;
+                }
+            },
+            async {
+                if let Some(stream) = &shared.stream {
+                    stream.finish().await;
+                }
+            }
+        );
+        true
+    };
+
+    let published = publishing
+        .or(async {
+            sleep(PUBLISH_TIMEOUT).await;
+            false
+        })
+        .await;
+    if !published {
+        warn!("Dropping the events that could not be published in time");
+    }
+}
+
+/// State shared by the bots of all accounts
+struct Shared {
+    history: History,
+    /// The gifts of the history within the scoring window, loaded once and kept up to date by
+    /// [`store`]
+    recent_gifts: Mutex<Vec<Gift>>,
+    /// Opened once, the gifts and bans of all bots are written through it
+    registry: Mutex<Registry>,
+    channel_logs: Option<ChannelLogs>,
+    notifier: Notifier,
+    /// Gets the gifts from `friends` in addition to `notifier`
+    friend_notifier: Notifier,
+    friends: Option<Friends>,
+    mqtt: Option<mqtt::Publisher>,
+    redis: Option<redis::Publisher>,
+    /// Gets the notifications here, and the other events as one of the `handlers`
+    stream: Option<Arc<stream::Producer>>,
+    /// How long gift notifications are collected for and the ones collected so far, see
+    /// [`send_batches`]
+    batch: Option<(Duration, Mutex<Vec<Gift>>)>,
+    /// Channels without `#` whose gifts are not sent to `notifier`
+    muted: HashSet<String>,
+    prices: Prices,
+    thanks: Option<Thanks>,
+    milestones: Mutex<MilestoneTracker>,
+    counters: Mutex<Counters>,
+    on_gift: Vec<Callback>,
+    handlers: Vec<Box<dyn EventHandler>>,
+    #[cfg(feature = "scripting")]
+    scripts: Scripts,
+    state: Mutex<ChannelState>,
+    /// Control channels of all running bots by username
+    bots: Mutex<HashMap<String, Sender<Control>>>,
+    notify_bans: bool,
+    irc: Endpoint,
+    /// Recorded or injected IRC lines the bot reads instead of connecting to `irc`
+    replay: Option<String>,
+    /// The lines are replayed, so nothing is notified or published
+    dry_run: bool,
+    capture: Option<Arc<Capture>>,
+    /// Made up gifts the bot reads instead of connecting to `irc`
+    simulation: Option<Simulation>,
+    /// Gifts waiting to be stored and notified, see [`process_gifts`]
+    gifts: Sender<Gift>,
+    names: Interner,
+}
+
+impl Shared {
+    /// Only log what would be notified or published
+    fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// The gifts come from replayed or injected lines, so they are not stored
+    fn fed(&self) -> bool {
+        self.replay.is_some()
+    }
+
+    /// Stream `notification` and send it to the sinks
+    async fn notify(&self, notification: &Notification) {
+        self.stream(notification);
+        self.notifier.notify(notification).await;
+    }
+
+    /// Stream `notification` to NATS or Kafka, gifts are streamed one by one even if they are
+    /// muted or batched for the sinks
+    fn stream(&self, notification: &Notification) {
+        if let (Some(stream), false) = (&self.stream, self.dry_run()) {
+            stream.publish(notification.kind(), notification);
+        }
+    }
+
+    /// `gifter` is one of the configured friends
+    fn is_friend(&self, gifter: &str) -> bool {
+        self.friends
+            .as_ref()
+            .is_some_and(|friends| friends.contains(gifter))
+    }
+
+    /// Not connected to Twitch, so only log what would be sent to chat
+    fn offline(&self) -> bool {
+        self.replay.is_some() || self.simulation.is_some()
+    }
+}
+
+/// Requests to a running bot
+#[derive(Debug, Clone)]
+enum Control {
+    /// Leave a channel and don't join it again
+    Part(Channel),
+    /// Join the channels that are not in the list of channels yet
+    Add(Vec<Channel>),
+}
+
+/// Events counted while farming
+#[derive(Default)]
+struct Counters {
+    /// Channels joined at least once
+    seen: HashSet<Channel>,
+    /// Number of bots currently in each channel
+    joined: HashMap<Channel, usize>,
+    new_channels: usize,
+    reconnects: u64,
+    /// Gifts, upgrades and pay forwards that landed on the accounts
+    gifts: u64,
+    /// Chat messages per channel since the channel state was last updated
+    messages: HashMap<Channel, u64>,
+    session: Session,
+    /// State of each bot by username
+    links: HashMap<String, Link>,
+}
+
+/// What the health endpoint and the healthcheck learn about a bot
+#[derive(Debug, Default)]
+struct Link {
+    connected: bool,
+    joined: usize,
+    configured: usize,
+    last_message: Option<Instant>,
+    /// Done joining the channels for the first time
+    settled: bool,
+}
+
+/// Totals since the farm started, unlike the [`Counters`] for the daily summary they are never
+/// reset
+struct Session {
+    started: DateTime<Utc>,
+    bytes: u64,
+    messages: u64,
+    user_notices: u64,
+    reconnects: u64,
+    gifts_by_tier: BTreeMap<Tier, u64>,
+    /// Channels with at least one USERNOTICE
+    active: HashSet<Channel>,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self {
+            started: Utc::now(),
+            bytes: 0,
+            messages: 0,
+            user_notices: 0,
+            reconnects: 0,
+            gifts_by_tier: BTreeMap::new(),
+            active: HashSet::new(),
+        }
+    }
+}
+
+impl Counters {
+    /// Count the IRC line `raw`
+    fn received(&mut self, raw: &str) {
+        // the line break is not part of it
+        self.session.bytes += raw.len() as u64 + 2;
+        self.session.messages += 1;
+    }
+
+    fn stats(&self) -> SessionStats {
+        SessionStats {
+            started: self.session.started,
+            uptime_secs: (Utc::now() - self.session.started).num_seconds().max(0) as u64,
+            bytes_received: self.session.bytes,
+            messages: self.session.messages,
+            user_notices: self.session.user_notices,
+            reconnects: self.session.reconnects,
+            joined: self.joined.len(),
+            gifts: self.gifts,
+            gifts_by_tier: self.session.gifts_by_tier.clone(),
+            active_channels: self.session.active.len(),
+        }
+    }
+
+    fn link(&mut self, username: &str) -> &mut Link {
+        if !self.links.contains_key(username) {
+            self.links.insert(username.to_string(), Link::default());
+        }
+        self.links.get_mut(username).unwrap()
+    }
+
+    /// Every bot is done joining its channels for the first time
+    fn settled(&self) -> bool {
+        !self.links.is_empty() && self.links.values().all(|link| link.settled)
+    }
+
+    fn health(&self) -> Health {
+        let secs = |at: Option<Instant>| at.map(|at| at.elapsed().as_secs());
+        let mut accounts: Vec<AccountHealth> = self
+            .links
+            .iter()
+            .map(|(username, link)| AccountHealth {
+                username: username.clone(),
+                connected: link.connected,
+                joined: link.joined,
+                configured: link.configured,
+                last_message_secs: secs(link.last_message),
+            })
+            .collect();
+        accounts.sort_by(|a, b| a.username.cmp(&b.username));
+
+        Health {
+            joined: accounts.iter().map(|account| account.joined).sum(),
+            configured: accounts.iter().map(|account| account.configured).sum(),
+            last_message_secs: secs(
+                self.links
+                    .values()
+                    .filter_map(|link| link.last_message)
+                    .max(),
+            ),
+            accounts,
+        }
+    }
+
+    fn join(&mut self, channel: &Channel) {
+        *self.joined.entry(channel.clone()).or_insert(0) += 1;
+        if self.seen.insert(channel.clone()) {
+            self.new_channels += 1;
+        }
+    }
+
+    fn part(&mut self, channel: &str) {
+        if let Some(count) = self.joined.get_mut(channel) {
+            *count -= 1;
+            if *count == 0 {
+                self.joined.remove(channel);
+            }
+        }
+    }
+}
+
+struct Bot {
+    user_config: UserConfig,
+    runner: AsyncRunner,
+    monitor: Arc<Monitor>,
+    channels: Vec<Channel>,
+    /// Channels the bot is currently in
+    joined: HashSet<Channel>,
+    /// Channels the account is subscribed to and when to join them again
+    parked: HashMap<Channel, DateTime<Utc>>,
+    shared: Arc<Shared>,
+    /// When the account last said something in chat
+    last_message: Option<Instant>,
+    whisperer: Option<Whisperer>,
+    community_gifts: HashMap<String, CommunityGift>,
+    control: Receiver<Control>,
+    rotation: Option<Rotation>,
+    /// Index of the slice of channels that is currently joined
+    slot: usize,
+    next_rotation: Instant,
+    /// Channels waiting to be joined between messages
+    pending: VecDeque<Channel>,
+    next_join: Instant,
+    progress: JoinProgress,
+    /// Channels per JOIN command
+    join_batch: usize,
+    /// Channels of batched JOIN commands that were not confirmed yet and when they were sent
+    in_flight: HashMap<Channel, Instant>,
+    /// The replayed lines ran out
+    finished: bool,
+    /// Ids of recent USERNOTICEs and when they arrived, Twitch repeats some after a reconnect
+    seen: HashMap<String, Instant>,
+    /// Done joining the channels for the first time
+    settled: bool,
+    next_ping: Instant,
+    /// Pings in a row that took at least [`SLOW_LATENCY`]
+    slow_pings: u32,
+}
+
+/// Outcome of the joins since the queue was last empty
+struct JoinProgress {
+    joined: usize,
+    failed: usize,
+    reported: Instant,
+}
+
+impl Default for JoinProgress {
+    fn default() -> Self {
+        Self {
+            joined: 0,
+            failed: 0,
+            reported: Instant::now(),
+        }
+    }
+}
+
+impl Bot {
+    async fn new(
+        user_config: UserConfig,
+        channels: Vec<Channel>,
+        shared: Arc<Shared>,
+        whisperer: Option<Whisperer>,
+        rotation: Option<Rotation>,
+        join_batch: usize,
+    ) -> Result<Self> {
+        let (runner, monitor) = connect(&shared, &user_config).await?;
+
+        let (sender, control) = smol::channel::unbounded();
+        shared
+            .bots
+            .lock()
+            .unwrap()
+            .insert(user_config.name.clone(), sender);
+
+        let bot = Self {
+            user_config,
+            channels,
+            runner,
+            monitor,
+            joined: HashSet::new(),
+            parked: HashMap::new(),
+            shared,
+            last_message: None,
+            whisperer,
+            community_gifts: HashMap::new(),
+            control,
+            next_rotation: Instant::now() + rotation_interval(rotation.as_ref()),
+            rotation,
+            slot: 0,
+            pending: VecDeque::new(),
+            next_join: Instant::now(),
+            progress: JoinProgress::default(),
+            join_batch,
+            in_flight: HashMap::new(),
+            finished: false,
+            seen: HashMap::new(),
+            settled: false,
+            next_ping: Instant::now(),
+            slow_pings: 0,
+        };
+        bot.shared
+            .counters
+            .lock()
+            .unwrap()
+            .link(&bot.user_config.name)
+            .connected = true;
+        bot.report_channels();
+
+        Ok(bot)
+    }
+
+    async fn run(&mut self) -> Result<()> {
+        debug!("Running bot");
+
+        self.join_channels();
+
+        debug!("starting main loop");
+        self.main_loop().await
+    }
+
+    async fn reconnect(&mut self) -> Result<()> {
+        {
+            let mut counters = self.shared.counters.lock().unwrap();
+            counters.reconnects += 1;
+            counters.session.reconnects += 1;
+            for channel in self.joined.drain() {
+                counters.part(&channel);
+            }
+            let link = counters.link(&self.user_config.name);
+            link.connected = false;
+            link.joined = 0;
+        }
+        let (runner, monitor) = connect(&self.shared, &self.user_config).await?;
+        self.shared
+            .counters
+            .lock()
+            .unwrap()
+            .link(&self.user_config.name)
+            .connected = true;
+        for handler in &self.shared.handlers {
+            handler.on_reconnect(&self.user_config.name);
+        }
+        self.runner = runner;
+        self.monitor = monitor;
+        self.next_ping = Instant::now();
+        self.slow_pings = 0;
+
+        self.in_flight.clear();
+        self.join_channels();
+        Ok(())
+    }
+
+    /// Queue the channels of the current slice that are neither joined nor queued yet.
+    ///
+    /// They are queued in front of the channels that are still waiting from an earlier call, so
+    /// after a reconnect the channels that were joined before are back first and the rest
+    /// continues where it left off.
+    fn join_channels(&mut self) {
+        if self.pending.is_empty() && self.in_flight.is_empty() {
+            self.progress = JoinProgress::default();
+        }
+
+        let queued: HashSet<&Channel> = self.pending.iter().collect();
+        let mut channels: Vec<Channel> = self
+            .active()
+            .iter()
+            .filter(|channel| !self.joined.contains(*channel) && !queued.contains(channel))
+            .cloned()
+            .collect();
+
+        // the most valuable channels should be back first after a reconnect
+        self.shared
+            .state
+            .lock()
+            .unwrap()
+            .prioritize(&mut channels, &self.shared.recent_gifts.lock().unwrap());
+
+        for channel in channels.into_iter().rev() {
+            if let Some(rejoin) = self.rejoin_at(&channel) {
+                debug!("Not joining {} until {}, subscribed", channel, rejoin);
+                self.parked.insert(channel, rejoin);
+                continue;
+            }
+
+            self.pending.push_front(channel);
+        }
+
+        info!(
+            "Joining {} of {} channels as {}",
+            self.pending.len(),
+            self.channels.len(),
+            self.user_config.name
+        );
+    }
+
+    /// Join the next queued channels
+    async fn join_next(&mut self) {
+        let mut batch: Vec<Channel> = Vec::new();
+        // IRC lines are limited to 512 bytes including the line break
+        let mut length = "JOIN ".len();
+        while let Some(channel) = self.pending.front().cloned() {
+            if batch.len() >= self.join_batch.max(1) {
+                break;
+            }
+
+            if self.joined.contains(&channel) || self.in_flight.contains_key(&channel) {
+                self.pending.pop_front();
+                continue;
+            }
+
+            // the channel is prefixed with `#` and followed by `,`
+            length += channel.len() + 2;
+            if !batch.is_empty() && length > 510 {
+                break;
+            }
+
+            self.pending.pop_front();
+            batch.push(channel);
+        }
+
+        if batch.is_empty() {
+            return;
+        }
+
+        // every channel counts against the join rate limit
+        self.next_join = Instant::now() + JOIN_INTERVAL * batch.len() as u32;
+
+        if self.join_batch <= 1 {
+            self.try_join(&batch[0]).await;
+        } else {
+            self.send_join(batch).await;
+        }
+    }
+
+    /// Join all channels in `batch` with a single command. The joins are confirmed in
+    /// `handle_message`.
+    async fn send_join(&mut self, batch: Vec<Channel>) {
+        debug!("Joining: {}", batch.join(", "));
+
+        let channels: Vec<String> = batch
+            .iter()
+            .map(|channel| format!("#{}", channel))
+            .collect();
+        match self
+            .monitor
+            .send_raw(&format!("JOIN {}", channels.join(",")))
+            .await
+        {
+            Ok(()) => {
+                let now = Instant::now();
+                for channel in batch {
+                    self.in_flight.insert(channel, now);
+                }
+            }
+            Err(err) => {
+                error!("Error while joining {} channels: {}", batch.len(), err);
+                for channel in batch.into_iter().rev() {
+                    self.pending.push_front(channel);
+                }
+            }
+        }
+    }
+
+    /// Give up on channels of batched joins that Twitch refused or never confirmed
+    async fn check_in_flight(&mut self) {
+        let mut failed = Vec::new();
+        for (channel, sent) in &self.in_flight {
+            if let Some(rejection) = self.monitor.rejection(channel) {
+                failed.push((channel.clone(), anyhow::Error::from(rejection)));
+            } else if sent.elapsed() >= JOIN_TIMEOUT {
+                failed.push((channel.clone(), anyhow!("timed out")));
+            }
+        }
+
+        for (channel, err) in failed {
+            self.in_flight.remove(&channel);
+            self.join_failed(&channel, err).await;
+        }
+    }
+
+    /// Log the progress of joining the queued channels every now and then
+    fn report_progress(&mut self) {
+        if self.pending.is_empty() && self.in_flight.is_empty() {
+            info!(
+                "Joined {} channels as {}, {} failed",
+                self.progress.joined, self.user_config.name, self.progress.failed
+            );
+        } else if self.progress.reported.elapsed() >= PROGRESS_INTERVAL {
+            self.progress.reported = Instant::now();
+            info!(
+                "Joined {} channels as {}, {} failed, {} remaining",
+                self.progress.joined,
+                self.user_config.name,
+                self.progress.failed,
+                self.pending.len() + self.in_flight.len()
+            );
+        }
+    }
+
+    /// Join `channel` and stop joining it if Twitch refuses to let us in
+    async fn try_join(&mut self, channel: &Channel) {
+        debug!("Joining: {}", channel);
+        let monitor = self.monitor.clone();
+        let result = self
+            .join(channel)
+            .or(async {
+                // Twitch only answers with a NOTICE if we can't join
+                loop {
+                    if let Some(rejection) = monitor.rejection(channel) {
+                        return Err(rejection.into());
+                    }
+                    sleep(Duration::from_millis(500)).await;
+                }
+            })
+            .or(async {
+                sleep(JOIN_TIMEOUT).await;
+                Err(anyhow!("timed out"))
+            })
+            .await;
+
+        match result {
+            Ok(()) => self.progress.joined += 1,
+            Err(err) => self.join_failed(channel, err).await,
+        }
+    }
+
+    /// Stop joining `channel` if Twitch refused to let us in, otherwise try again later
+    async fn join_failed(&mut self, channel: &str, err: anyhow::Error) {
+        match err {
+            err if err.downcast_ref() == Some(&Rejection::Banned) => {
+                self.progress.failed += 1;
+                warn!("Not joining '{}' again: {}", channel, err);
+                self.forget(channel);
+                self.ban(channel).await;
+            }
+            err if err.is::<Rejection>() => {
+                self.progress.failed += 1;
+                warn!("Not joining '{}' again: {}", channel, err);
+                self.forget(channel);
+                prune_channels(&[(channel.to_string(), err.to_string())], &self.shared);
+            }
+            err if matches!(err.downcast_ref(), Some(RunnerError::UnexpectedEof)) => {
+                // the connection is gone, join again after reconnecting
+                debug!("Lost the connection while joining '{}'", channel);
+                self.pending.push_front(self.shared.names.intern(channel));
+            }
+            err => {
+                self.progress.failed += 1;
+                error!("Error while joining '{}': {}", channel, err);
+                for handler in &self.shared.handlers {
+                    handler.on_error(&self.user_config.name, &err);
+                }
+            }
+        }
+    }
+
+    /// Remove `channel` from the list of channels and the join queue
+    fn forget(&mut self, channel: &str) {
+        self.channels.retain(|c| &**c != channel);
+        self.pending.retain(|c| &**c != channel);
+        self.report_channels();
+    }
+
+    /// Leave `channel`, it stays in the list of channels
+    async fn leave(&mut self, channel: &str) {
+        if !self.joined.remove(channel) {
+            return;
+        }
+
+        debug!("Leaving: {}", channel);
+        self.shared.counters.lock().unwrap().part(channel);
+        self.report_channels();
+        for handler in &self.shared.handlers {
+            handler.on_part(&self.user_config.name, channel);
+        }
+
+        if let Err(err) = self
+            .runner
+            .part(channel)
+            .map_err(anyhow::Error::from)
+            .or(async {
+                sleep(Duration::from_secs(30)).await;
+                Err(anyhow!("timed out"))
+            })
+            .await
+        {
+            error!("Error while leaving '{}': {}", channel, err);
+        }
+    }
+
+    fn subscribed_until(&self, channel: &str) -> Option<DateTime<Utc>> {
+        self.shared
+            .state
+            .lock()
+            .unwrap()
+            .subscribed_until(&self.user_config.name, channel)
+    }
+
+    /// When to join `channel` again if the account should not be in it because of a sub
+    fn rejoin_at(&self, channel: &str) -> Option<DateTime<Utc>> {
+        self.subscribed_until(channel)
+            .map(|until| until - chrono::Duration::hours(REJOIN_EARLY_HOURS))
+            .filter(|rejoin| *rejoin > Utc::now())
+    }
+
+    /// Leave `channel` because the account has a sub in it
+    async fn park(&mut self, channel: &str) {
+        let rejoin = match self.rejoin_at(channel) {
+            Some(rejoin) => rejoin,
+            None => return,
+        };
+
+        info!(
+            "{} is subscribed to {}, leaving until {}",
+            self.user_config.name,
+            channel,
+            rejoin.with_timezone(&Local).format("%Y-%m-%d %H:%M")
+        );
+        self.leave(channel).await;
+        self.parked
+            .insert(self.shared.names.intern(channel), rejoin);
+    }
+
+    /// The account already has a sub in the channel and can't receive gifts there
+    async fn handle_user_state(&mut self, msg: UserState<'_>) {
+        let subscribed = msg.badges().iter().any(|badge| {
+            matches!(
+                badge.kind,
+                BadgeKind::Subscriber | BadgeKind::Unknown("founder")
+            )
+        });
+        let channel = msg.channel().trim_start_matches('#');
+        if !subscribed || !self.joined.contains(channel) {
+            return;
+        }
+
+        if self.subscribed_until(channel).is_none() {
+            // we don't know when the sub was renewed, assume it just was
+            self.shared.state.lock().unwrap().subscribed(
+                &self.user_config.name,
+                channel,
+                Utc::now() + chrono::Duration::days(30),
+            );
+        }
+
+        self.park(channel).await;
+    }
+
+    /// Join parked channels again once the sub should have ended
+    fn unpark(&mut self) {
+        let now = Utc::now();
+        let due: Vec<Channel> = self
+            .parked
+            .iter()
+            .filter(|(_, rejoin)| **rejoin <= now)
+            .map(|(channel, _)| channel.clone())
+            .collect();
+
+        for channel in due {
+            self.parked.remove(&channel);
+            if self.active().contains(&channel) && !self.pending.contains(&channel) {
+                self.pending.push_front(channel);
+            }
+        }
+    }
+
+    /// Remember that the account is banned from `channel`
+    async fn ban(&self, channel: &str) {
+        let account = &self.user_config.name;
+        match self.shared.registry.lock().unwrap().ban(account, channel) {
+            Ok(true) => {}
+            Ok(false) => warn!(
+                "{} is banned from {}, keeping it as its own channel",
+                account, channel
+            ),
+            Err(err) => error!(
+                "Could not save that {} is banned from {}: {:#}",
+                account, channel, err
+            ),
+        }
+
+        if self.shared.notify_bans {
+            self.shared
+                .notify(&Notification::Banned {
+                    account: account.clone(),
+                    channel: channel.to_string(),
+                })
+                .await;
+        }
+    }
+
+    async fn join(&mut self, channel: &Channel) -> Result<()> {
+        match self.runner.join(channel).await {
+            Ok(()) => {}
+            Err(RunnerError::BannedFromChannel { .. }) => return Err(Rejection::Banned.into()),
+            Err(err) => return Err(err.into()),
+        }
+
+        self.mark_joined(channel.clone());
+        Ok(())
+    }
+
+    fn mark_joined(&mut self, channel: Channel) {
+        self.shared.counters.lock().unwrap().join(&channel);
+        self.joined.insert(channel.clone());
+        self.report_channels();
+        for handler in &self.shared.handlers {
+            handler.on_join(&self.user_config.name, &channel);
+        }
+    }
+
+    /// Let the health endpoint know how many channels are joined
+    fn report_channels(&self) {
+        let mut counters = self.shared.counters.lock().unwrap();
+        let link = counters.link(&self.user_config.name);
+        link.joined = self.joined.len();
+        link.configured = self.channels.len();
+    }
+
+    async fn main_loop(&mut self) -> Result<()> {
+        loop {
+            if self.finished {
+                return Ok(());
+            }
+
+            if Instant::now() >= self.next_ping {
+                if self.shared.offline() {
+                    // nothing to ping, but the loop still wakes up as often
+                    self.next_ping = Instant::now() + PING_INTERVAL;
+                } else {
+                    self.check_connection().await?;
+                }
+            }
+
+            if self.pending.is_empty() && self.in_flight.is_empty() {
+                if !self.settled {
+                    self.settled = true;
+                    self.shared
+                        .counters
+                        .lock()
+                        .unwrap()
+                        .link(&self.user_config.name)
+                        .settled = true;
+                }
+
+                // wake up for the next ping even if nothing is happening
+                let wake = self.next_ping;
+                if let Some(status) = self.next_status(wake).await? {
+                    self.handle_message(status).await?;
+                }
+            } else {
+                // keep handling messages until the next channel may be joined
+                let wake = if self.pending.is_empty() {
+                    Instant::now() + Duration::from_secs(1)
+                } else {
+                    self.next_join
+                };
+                if let Some(status) = self.next_status(wake).await? {
+                    self.handle_message(status).await?;
+                }
+
+                self.check_in_flight().await;
+                if !self.pending.is_empty() && Instant::now() >= self.next_join {
+                    self.join_next().await;
+                }
+                self.report_progress();
+            }
+
+            if let Some(latency) = self.monitor.take_latency() {
+                self.report_latency(latency);
+            }
+
+            while let Ok(control) = self.control.try_recv() {
+                self.handle_control(control).await;
+            }
+
+            self.unpark();
+
+            if Instant::now() >= self.next_rotation {
+                self.rotate().await;
+            }
+        }
+    }
+
+    /// Reconnect if the last ping was not answered, otherwise send the next one
+    async fn check_connection(&mut self) -> Result<()> {
+        self.next_ping = Instant::now() + PING_INTERVAL;
+
+        if let Some(waiting) = self.monitor.unanswered() {
+            warn!(
+                "Twitch did not answer a PING as {} for {} seconds, reconnecting",
+                self.user_config.name,
+                waiting.as_secs()
+            );
+            return self.reconnect().await;
+        }
+        if self.slow_pings >= SLOW_PINGS {
+            warn!(
+                "The connection as {} was slow for {} pings, reconnecting",
+                self.user_config.name, self.slow_pings
+            );
+            return self.reconnect().await;
+        }
+
+        if let Err(err) = self.monitor.ping().await {
+            warn!(
+                "Could not send a PING as {}: {}",
+                self.user_config.name, err
+            );
+        }
+
+        Ok(())
+    }
+
+    fn report_latency(&mut self, latency: Duration) {
+        if latency >= SLOW_LATENCY {
+            self.slow_pings += 1;
+            warn!(
+                "Latency as {} is {} ms",
+                self.user_config.name,
+                latency.as_millis()
+            );
+        } else {
+            self.slow_pings = 0;
+            debug!(
+                "Latency as {} is {} ms",
+                self.user_config.name,
+                latency.as_millis()
+            );
+        }
+
+        for handler in &self.shared.handlers {
+            handler.on_latency(&self.user_config.name, latency);
+        }
+    }
+
+    /// Number of slices the channels are split into
+    fn slots(&self) -> usize {
+        match &self.rotation {
+            Some(rotation) => self
+                .channels
+                .len()
+                .div_ceil(rotation.channels.max(1))
+                .max(1),
+            None => 1,
+        }
+    }
+
+    /// Channels in the current slice
+    fn active(&self) -> &[Channel] {
+        match &self.rotation {
+            Some(rotation) => {
+                let size = rotation.channels.max(1);
+                let start = (self.slot % self.slots() * size).min(self.channels.len());
+                let end = (start + size).min(self.channels.len());
+                &self.channels[start..end]
+            }
+            None => &self.channels,
+        }
+    }
+
+    /// Leave the current slice of channels and join the next one
+    async fn rotate(&mut self) {
+        self.next_rotation = Instant::now() + rotation_interval(self.rotation.as_ref());
+        if self.slots() <= 1 {
+            return;
+        }
+
+        self.slot = (self.slot + 1) % self.slots();
+        info!(
+            "Rotating to slice {} of {} as {}",
+            self.slot + 1,
+            self.slots(),
+            self.user_config.name
+        );
+
+        self.join_slice().await;
+    }
+
+    /// Leave the joined channels that are not in the current slice and join the rest of it.
+    /// Needed whenever the slice changes, also if the list of channels changes under it.
+    async fn join_slice(&mut self) {
+        let active: HashSet<Channel> = self.active().iter().cloned().collect();
+        let stale: Vec<Channel> = self
+            .joined
+            .iter()
+            .filter(|channel| !active.contains(*channel))
+            .cloned()
+            .collect();
+        for channel in stale {
+            self.leave(&channel).await;
+        }
+
+        self.pending.retain(|channel| active.contains(channel));
+        self.join_channels();
+    }
+
+    async fn handle_control(&mut self, control: Control) {
+        match control {
+            Control::Part(channel) => {
+                self.forget(&channel);
+                self.in_flight.remove(&channel);
+                self.parked.remove(&channel);
+                self.leave(&channel).await;
+                // the channels after it moved up a place
+                if self.rotation.is_some() {
+                    self.join_slice().await;
+                }
+            }
+            Control::Add(channels) => {
+                let known: HashSet<&Channel> = self.channels.iter().collect();
+                let new: Vec<Channel> = channels
+                    .into_iter()
+                    .filter(|channel| !known.contains(channel))
+                    .collect();
+                if new.is_empty() {
+                    return;
+                }
+
+                info!(
+                    "Adding {} discovered channels as {}",
+                    new.len(),
+                    self.user_config.name
+                );
+                self.channels.extend(new);
+                self.report_channels();
+                // the new channels can change where the slices are
+                self.join_slice().await;
+            }
+        }
+    }
+
+    /// The next message, or `None` once `wake` is reached. Only the reading is raced against the
+    /// timer so handling a message is never cancelled halfway.
+    async fn next_status(&mut self, wake: Instant) -> Result<Option<Status<'static>>> {
+        let runner = &mut self.runner;
+        async { Ok(Some(runner.next_message().await?)) }
+            .or(async {
+                sleep_until(wake).await;
+                Ok(None)
+            })
+            .await
+    }
+
+    async fn handle_message(&mut self, status: Status<'static>) -> Result<()> {
+        if let Status::Message(msg) = &status {
+            let mut counters = self.shared.counters.lock().unwrap();
+            counters.received(msg.raw());
+            counters.link(&self.user_config.name).last_message = Some(Instant::now());
+            if let Commands::UserNotice(notice) = msg {
+                counters.session.user_notices += 1;
+                let channel = notice.channel().trim_start_matches('#');
+                if !counters.session.active.contains(channel) {
+                    let channel = self.shared.names.intern(channel);
+                    counters.session.active.insert(channel);
+                }
+            }
+        }
+
+        match status {
+            Status::Message(Commands::UserNotice(user_notice)) => {
+                self.handle_user_notice(user_notice).await
+            }
+
+            Status::Message(Commands::UserState(msg)) => self.handle_user_state(msg).await,
+
+            Status::Message(Commands::Join(msg)) if msg.name() == self.user_config.name => {
+                let channel = msg.channel().trim_start_matches('#');
+                if let Some((channel, _)) = self.in_flight.remove_entry(channel) {
+                    self.mark_joined(channel);
+                    self.progress.joined += 1;
+                }
+            }
+
+            Status::Message(Commands::Privmsg(msg)) => {
+                let channel = msg.channel().trim_start_matches('#');
+                let mut counters = self.shared.counters.lock().unwrap();
+                match counters.messages.get_mut(channel) {
+                    Some(count) => *count += 1,
+                    None => {
+                        counters
+                            .messages
+                            .insert(self.shared.names.intern(channel), 1);
+                    }
+                }
+            }
+
+            Status::Message(Commands::Notice(notice)) if is_login_failure(notice.message()) => {
+                return Err(LoginFailed {
+                    username: self.user_config.name.clone(),
+                }
+                .into());
+            }
+
+            // stop if we're stopping
+            Status::Quit => {
+                self.finished = true;
+            }
+
+            Status::Eof if self.shared.fed() => {
+                self.finished = true;
+            }
+
+            Status::Eof => {
+                info!("received an EOF, reconnecting");
+                self.reconnect().await?;
+            }
+
+            // ignore the rest
+            Status::Message(..) => {}
+        }
+
+        Ok(())
+    }
+
+    async fn handle_user_notice(&mut self, msg: UserNotice<'_>) {
+        if let Some(id) = msg.tags().get("id") {
+            let now = Instant::now();
+            self.seen
+                .retain(|_, seen| now.duration_since(*seen) < DUPLICATE_WINDOW);
+            if self.seen.insert(id.to_string(), now).is_some() {
+                debug!("[{}] Dropping the repeated notice {}", msg.channel(), id);
+                return;
+            }
+        }
+
+        if matches!(
+            msg.msg_id(),
+            Some(NoticeType::SubGift)
+                | Some(NoticeType::AnonSubGift)
+                | Some(NoticeType::SubMysteryGift)
+                | Some(NoticeType::Unknown("anonsubmysterygift"))
+        ) {
+            self.shared.state.lock().unwrap().gift_event(msg.channel());
+        }
+
+        match msg.msg_id() {
+            Some(NoticeType::SubMysteryGift) => self.handle_community_gift(&msg),
+            Some(NoticeType::Unknown("anonsubmysterygift")) => self.handle_community_gift(&msg),
+            Some(NoticeType::SubGift) => self.handle_sub_gift(&msg, GiftKind::SubGift).await,
+            Some(NoticeType::AnonSubGift) => {
+                self.handle_sub_gift(&msg, GiftKind::AnonSubGift).await
+            }
+            Some(NoticeType::GiftPaidUpgrade) => {
+                self.handle_upgrade(&msg, GiftKind::GiftPaidUpgrade).await
+            }
+            Some(NoticeType::AnonGiftPaidUpgrade) => {
+                self.handle_upgrade(&msg, GiftKind::AnonGiftPaidUpgrade)
+                    .await
+            }
+            Some(NoticeType::Unknown("primepaidupgrade")) => {
+                self.handle_upgrade(&msg, GiftKind::PrimePaidUpgrade).await
+            }
+            Some(NoticeType::Unknown("standardpayforward")) => {
+                self.handle_pay_forward(&msg, GiftKind::StandardPayForward)
+                    .await
+            }
+            Some(NoticeType::Unknown("communitypayforward")) => {
+                self.handle_pay_forward(&msg, GiftKind::CommunityPayForward)
+                    .await
+            }
+            _ => {}
+        }
+    }
+
+    async fn handle_upgrade(&mut self, msg: &UserNotice<'_>, kind: GiftKind) {
+        if msg.login() != Some(&self.user_config.name) {
+            return;
+        }
+
+        let gifter = match kind {
+            GiftKind::GiftPaidUpgrade => msg
+                .msg_param_sender_name()
+                .or(msg.msg_param_sender_login())
+                .unwrap_or("unknown"),
+            GiftKind::AnonGiftPaidUpgrade => "anonymous",
+            _ => msg.display_name().unwrap_or(&self.user_config.name),
+        };
+        let tier = Tier::from(msg.msg_param_sub_plan());
+
+        info!(
+            "[{}] {} {} (originally from {})",
+            msg.channel(),
+            self.user_config.name,
+            kind.as_str(),
+            gifter
+        );
+
+        let gift = Gift {
+            tier,
+            plan_name: msg
+                .msg_param_sub_plan_name()
+                .unwrap_or("unknown")
+                .replace("\\s", " "),
+            ..Gift::new(&self.user_config.name, msg.channel(), gifter, kind)
+        };
+        self.publish(&gift, msg).await;
+        self.record(gift).await;
+    }
+
+    async fn handle_pay_forward(&mut self, msg: &UserNotice<'_>, kind: GiftKind) {
+        // only pay forwards to us are interesting, community pay forwards are recorded for
+        // every channel so the lineage of community gifts can be followed
+        if kind == GiftKind::StandardPayForward
+            && msg.msg_param_recipient_user_name() != Some(&self.user_config.name)
+        {
+            return;
+        }
+
+        let tags = msg.tags();
+        let gifter = msg.display_name().or(msg.login()).unwrap_or("unknown");
+        let prior_gifter = if tags.get("msg-param-prior-gifter-anonymous") == Some("true") {
+            "anonymous"
+        } else {
+            tags.get("msg-param-prior-gifter-display-name")
+                .or_else(|| tags.get("msg-param-prior-gifter-user-name"))
+                .unwrap_or("unknown")
+        };
+
+        info!(
+            "[{}] {} paid the gift from {} forward to {}",
+            msg.channel(),
+            gifter,
+            prior_gifter,
+            match kind {
+                GiftKind::CommunityPayForward => "the community",
+                _ => &self.user_config.name,
+            }
+        );
+
+        let gift = Gift {
+            prior_gifter: Some(prior_gifter.to_string()),
+            ..Gift::new(&self.user_config.name, msg.channel(), gifter, kind)
+        };
+        self.publish(&gift, msg).await;
+        self.record(gift).await;
+    }
+
+    /// Let the event handlers and scripts know about `gift`
+    async fn publish(&mut self, gift: &Gift, msg: &UserNotice<'_>) {
+        #[cfg(not(feature = "scripting"))]
+        if self.shared.handlers.is_empty() {
+            return;
+        }
+        #[cfg(feature = "scripting")]
+        if self.shared.handlers.is_empty() && self.shared.scripts.is_empty() {
+            return;
+        }
+
+        let event = GiftEvent {
+            kind: gift.kind,
+            channel: gift.channel.clone(),
+            gifter: gift.gifter.clone(),
+            recipient: match gift.kind {
+                GiftKind::CommunityPayForward => None,
+                _ => Some(gift.account.clone()),
+            },
+            tier: gift.tier,
+            months: gift.months,
+            tags: msg
+                .tags()
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+        };
+
+        for handler in &self.shared.handlers {
+            handler.on_gift(&event);
+        }
+
+        #[cfg(feature = "scripting")]
+        for message in self.shared.scripts.on_gift(&event) {
+            let channel = msg.channel();
+            if !self.may_speak() {
+                debug!(
+                    "Not sending script message in {}, still on cooldown",
+                    channel
+                );
+                continue;
+            }
+            if let Err(err) = self.say(channel, &message).await {
+                error!("Could not send script message in {}: {}", channel, err);
+            }
+        }
+    }
+
+    /// Queue `gift` to be stored and notified without holding up the connection
+    async fn record(&self, gift: Gift) {
+        {
+            let mut counters = self.shared.counters.lock().unwrap();
+            counters.gifts += 1;
+            *counters.session.gifts_by_tier.entry(gift.tier).or_insert(0) += 1;
+        }
+
+        let gift = match self.shared.gifts.try_send(gift) {
+            Err(TrySendError::Full(gift)) => gift,
+            _ => return,
+        };
+        warn!("Storing and notifying gifts is falling behind, waiting for the queue");
+        self.shared.gifts.send(gift).await.ok();
+    }
+
+    fn handle_community_gift(&mut self, msg: &UserNotice<'_>) {
+        let total = match msg.tags().get_parsed("msg-param-mass-gift-count") {
+            Some(total) => total,
+            None => return,
+        };
+        let gifter = msg.display_name().or(msg.login()).unwrap_or("anonymous");
+
+        debug!(
+            "[{}] {} is gifting {} subs to the community",
+            msg.channel(),
+            gifter,
+            total
+        );
+
+        self.community_gifts
+            .retain(|_, gift| gift.started.elapsed() < COMMUNITY_GIFT_TIMEOUT || gift.finish());
+
+        self.community_gifts.insert(
+            community_gift_key(msg),
+            CommunityGift {
+                channel: msg.channel().to_string(),
+                total,
+                seen: 0,
+                landed: 0,
+                started: Instant::now(),
+            },
+        );
+    }
+
+    async fn handle_sub_gift(&mut self, msg: &UserNotice<'_>, kind: GiftKind) {
+        let is_for_us = msg.msg_param_recipient_user_name() == Some(&self.user_config.name);
+
+        let key = community_gift_key(msg);
+        let community_gift = match self.community_gifts.get_mut(&key) {
+            Some(gift) => {
+                gift.seen += 1;
+                if is_for_us {
+                    gift.landed += 1;
+                }
+
+                let total = gift.total;
+                if gift.seen >= gift.total {
+                    self.community_gifts.remove(&key).unwrap().finish();
+                }
+
+                Some(total)
+            }
+            None => None,
+        };
+
+        if !is_for_us {
+            return;
+        }
+
+        let recipient = msg.msg_param_recipient_display_name().unwrap_or("unkown");
+        let tier = Tier::from(msg.msg_param_sub_plan());
+        let display_name = msg.display_name().or(msg.login()).unwrap_or("anonymous");
+        let sub_plan_name = msg
+            .msg_param_sub_plan_name()
+            .unwrap_or("unknown")
+            .replace("\\s", " ");
+
+        let months = msg.tags().get_parsed("msg-param-gift-months").unwrap_or(1);
+
+        let friend = kind == GiftKind::SubGift && self.shared.is_friend(display_name);
+        info!(
+            "[{}] {}{} received a {} month {} {} from {}. Subscription Plan: {}",
+            msg.channel(),
+            // a star in front of gifts from friends
+            if friend { "\u{2b50} " } else { "" },
+            recipient,
+            months,
+            tier.as_str(),
+            kind.as_str(),
+            display_name,
+            sub_plan_name,
+        );
+
+        let gift = Gift {
+            tier,
+            plan_name: sub_plan_name.clone(),
+            months,
+            recipient_months: msg.msg_param_months(),
+            community_gift,
+            ..Gift::new(&self.user_config.name, msg.channel(), display_name, kind)
+        };
+        let channel = gift.channel.clone();
+        let until = gift.time + chrono::Duration::days(30 * gift.months as i64);
+        self.publish(&gift, msg).await;
+        self.record(gift).await;
+
+        let months = months.to_string();
+        let vars = [
+            ("gifter", display_name),
+            ("months", months.as_str()),
+            ("tier", tier.as_str()),
+            ("plan", sub_plan_name.as_str()),
+            ("channel", msg.channel().trim_start_matches('#')),
+        ];
+
+        self.thank(msg.channel(), display_name, &vars).await;
+
+        // anonymous gifts are sent by the AnAnonymousGifter account
+        if kind == GiftKind::SubGift {
+            if let Some(gifter_id) = msg.user_id() {
+                self.whisper(gifter_id, display_name, &vars).await;
+            }
+        }
+
+        // no more gifts can land on the account until the sub runs out
+        self.shared
+            .state
+            .lock()
+            .unwrap()
+            .subscribed(&self.user_config.name, &channel, until);
+        self.park(&channel).await;
+    }
+
+    async fn thank(&mut self, channel: &str, gifter: &str, vars: &[(&str, &str)]) {
+        let thanks = match &self.shared.thanks {
+            Some(thanks) if thanks.enabled_in(channel) => thanks,
+            _ => return,
+        };
+
+        if !self.may_speak() {
+            debug!("Not thanking {} in {}, still on cooldown", gifter, channel);
+            return;
+        }
+
+        let message = render(&thanks.message, vars);
+        if let Err(err) = self.say(channel, &message).await {
+            error!("Could not thank {} in {}: {}", gifter, channel, err);
+        }
+    }
+
+    /// The cooldown of the thanks is over, it applies to every message the account sends
+    fn may_speak(&self) -> bool {
+        let cooldown = self
+            .shared
+            .thanks
+            .as_ref()
+            .map_or_else(config::default_thanks_cooldown, |thanks| thanks.cooldown);
+
+        self.last_message
+            .map_or(true, |last| last.elapsed() >= Duration::from_secs(cooldown))
+    }
+
+    /// Send `message` to `channel`, only log it when not connected to Twitch
+    async fn say(&mut self, channel: &str, message: &str) -> io::Result<()> {
+        if self.shared.offline() {
+            info!("[{}] Would send: {}", channel, message);
+        } else {
+            self.runner
+                .writer()
+                .encode(commands::privmsg(channel, message))
+                .await?;
+            info!("[{}] Sent: {}", channel, message);
+        }
+
+        self.last_message = Some(Instant::now());
+        Ok(())
+    }
+
+    async fn whisper(&mut self, gifter_id: u64, gifter: &str, vars: &[(&str, &str)]) {
+        let whisperer = match &mut self.whisperer {
+            Some(whisperer) => whisperer,
+            None => return,
+        };
+
+        let today = Local::now().date_naive();
+        if whisperer.day != today {
+            whisperer.day = today;
+            whisperer.sent = 0;
+        }
+
+        if whisperer.sent >= whisperer.config.daily_cap {
+            debug!("Not whispering {}, daily cap reached", gifter);
+            return;
+        }
+
+        let message = render(&whisperer.config.message, vars);
+
+        match whisperer.helix.send_whisper(gifter_id, &message).await {
+            Ok(()) => {
+                info!("Whispered {}: {}", gifter, message);
+                whisperer.sent += 1;
+            }
+            Err(err) => error!("Could not whisper {}: {:#}", gifter, err),
+        }
+    }
+}
+
+struct Whisperer {
+    helix: Helix,
+    config: Whisper,
+    day: NaiveDate,
+    sent: u32,
+}
+
+impl Whisperer {
+    async fn new(token: &str, config: Whisper) -> Result<Self> {
+        Ok(Self {
+            helix: Helix::with_user_token(token).await?,
+            config,
+            day: Local::now().date_naive(),
+            sent: 0,
+        })
+    }
+}
+
+/// Time until the next slice of channels is joined, practically never without a rotation
+fn rotation_interval(rotation: Option<&Rotation>) -> Duration {
+    match rotation {
+        Some(rotation) => Duration::from_secs(rotation.minutes.max(1) * 60),
+        None => Duration::from_secs(u64::from(u32::MAX)),
+    }
+}
+
+/// Time between two joins, Twitch allows 20 join attempts per 10 seconds
+pub const JOIN_INTERVAL: Duration = Duration::from_millis(510);
+
+/// Time Twitch has to confirm a join
+const JOIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Time between two progress reports while joining channels
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How many hours before a sub is expected to end its channel is joined again
+const REJOIN_EARLY_HOURS: i64 = 24;
+
+/// How long to wait for the individual gifts of a community gift
+const COMMUNITY_GIFT_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// Time between two logs of the [`SessionStats`]
+pub const STATS_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+/// Time between two PINGs measuring the latency, an unanswered PING makes the bot reconnect
+const PING_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Latency from which the connection counts as degraded
+const SLOW_LATENCY: Duration = Duration::from_secs(5);
+
+/// Degraded pings in a row after which the bot reconnects
+const SLOW_PINGS: u32 = 3;
+
+/// Gifts that can wait to be stored and notified before the bots wait for them
+const GIFT_QUEUE: usize = 64;
+
+/// Gifts that are stored and notified at the same time
+const GIFT_WORKERS: usize = 4;
+
+/// How long the queued events may take to be published when the farm stops
+const PUBLISH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long the ids of USERNOTICEs are remembered to drop notices Twitch sends again
+const DUPLICATE_WINDOW: Duration = Duration::from_secs(10 * 60);
+
+/// A community gift whose individual gifts are still arriving
+struct CommunityGift {
+    channel: String,
+    total: u64,
+    seen: u64,
+    landed: u64,
+    started: Instant,
+}
+
+impl CommunityGift {
+    /// Log how many of the gifts landed on us. Always returns `false` so it can be used in
+    /// `retain`.
+    fn finish(&self) -> bool {
+        if self.landed > 0 {
+            info!(
+                "{} of {} community gifts in {} landed on me",
+                self.landed, self.total, self.channel
+            );
+        } else {
+            debug!(
+                "None of {} community gifts in {} landed on me ({} seen)",
+                self.total, self.channel, self.seen
+            );
+        }
+
+        false
+    }
+}
+
+/// Identifies the community gift a sub gift belongs to.
+///
+/// Newer notices carry a community gift id on both the announcement and the individual gifts,
+/// older ones only share the origin id. As a last resort gifts are matched by channel and
+/// gifter.
+fn community_gift_key(msg: &UserNotice<'_>) -> String {
+    msg.tags()
+        .get("msg-param-community-gift-id")
+        .or_else(|| msg.tags().get("msg-param-origin-id"))
+        .map(ToString::to_string)
+        .unwrap_or_else(|| format!("{}:{}", msg.channel(), msg.login().unwrap_or_default()))
+}
+
+/// Periodically add the time channels were joined to the channel state and prune channels
+async fn track_channels(prune: Option<Prune>, shared: Arc<Shared>) {
+    const INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+    loop {
+        sleep(INTERVAL).await;
+
+        let (joined, messages) = {
+            let mut counters = shared.counters.lock().unwrap();
+            let joined: HashSet<Channel> = counters.joined.keys().cloned().collect();
+            (joined, std::mem::take(&mut counters.messages))
+        };
+        shared.names.shrink();
+
+        let mut state = shared.state.lock().unwrap();
+        state.track(
+            joined.iter().map(|channel| &**channel),
+            chrono::Duration::from_std(INTERVAL).unwrap(),
+            &messages,
+        );
+
+        let mut prunable = Vec::new();
+        let mut demotable = Vec::new();
+        if let Some(prune) = &prune {
+            if let Some(days) = prune.after_days {
+                for channel in state.giftless(days) {
+                    let reason = (
+                        channel.to_string(),
+                        format!("no gift events in {} days", days),
+                    );
+                    if prune.demote {
+                        demotable.push(reason);
+                    } else {
+                        prunable.push(reason);
+                    }
+                }
+            }
+            if let Some(days) = prune.silent_days {
+                for channel in state.silent(days) {
+                    prunable.push((
+                        channel.to_string(),
+                        format!("chat silent for {} days", days),
+                    ));
+                }
+            }
+        }
+        prunable.retain(|(channel, _)| joined.contains(channel.as_str()));
+        prunable.sort_by(|a, b| a.0.cmp(&b.0));
+        prunable.dedup_by(|a, b| a.0 == b.0);
+        // pruning wins over demoting
+        demotable.retain(|(channel, _)| {
+            joined.contains(channel.as_str())
+                && !prunable.iter().any(|(pruned, _)| pruned == channel)
+        });
+
+        // start counting again in case the channel is restored
+        for (channel, _) in prunable.iter().chain(&demotable) {
+            state.reset(channel);
+        }
+
+        if let Err(err) = state.save() {
+            error!("Could not save the channel state: {:#}", err);
+        }
+        drop(state);
+
+        if !prunable.is_empty() {
+            prune_channels(&prunable, &shared);
+        }
+        if !demotable.is_empty() {
+            demote_channels(&demotable, &shared);
+        }
+    }
+}
+
+/// Remove `channels` from the config and make all bots leave them
+fn prune_channels(channels: &[(String, String)], shared: &Shared) {
+    let registry = shared.registry.lock().unwrap();
+    let result = channels
+        .iter()
+        .try_for_each(|(channel, reason)| -> Result<()> {
+            for pruned in registry.prune(channel, reason)? {
+                info!(
+                    "Pruned {} from the {} channels, {}",
+                    channel,
+                    pruned.account.as_deref().unwrap_or("shared"),
+                    reason
+                );
+            }
+            Ok(())
+        });
+    drop(registry);
+    if let Err(err) = result {
+        error!("Could not prune channels: {:#}", err);
+        return;
+    }
+
+    part_all(channels, shared);
+}
+
+/// Move `channels` to the scout pool and make all bots leave them
+fn demote_channels(channels: &[(String, String)], shared: &Shared) {
+    let registry = shared.registry.lock().unwrap();
+    let result = channels
+        .iter()
+        .try_for_each(|(channel, reason)| -> Result<()> {
+            for account in registry.demote(channel, reason)? {
+                info!(
+                    "Moved {} from the {} channels to the scout pool, {}",
+                    channel,
+                    account.as_deref().unwrap_or("shared"),
+                    reason
+                );
+            }
+            Ok(())
+        });
+    drop(registry);
+    if let Err(err) = result {
+        error!("Could not demote channels: {:#}", err);
+        return;
+    }
+
+    part_all(channels, shared);
+}
+
+fn part_all(channels: &[(String, String)], shared: &Shared) {
+    for bot in shared.bots.lock().unwrap().values() {
+        for (channel, _) in channels {
+            // the bot stopped if this fails
+            bot.try_send(Control::Part(shared.names.intern(channel)))
+                .ok();
+        }
+    }
+}
+
+/// Add the channels of a discovery run to the registry every `interval`, the bots pick them up
+/// in `watch_channels`
+async fn discover_channels(interval: Duration, discovery: Discovery) {
+    loop {
+        sleep(interval).await;
+
+        let discovered = match get_streams(&discovery).await {
+            Ok(discovered) => discovered,
+            Err(err) => {
+                error!("Could not discover channels: {:#}", err);
+                continue;
+            }
+        };
+
+        let result = Registry::open().and_then(|mut registry| {
+            let selection = select(&registry, None, None, &discovery, &discovered.channels)?;
+            let added = registry.add(None, &selection.new, Source::Discovery)?;
+            registry.live(&discovered.channels)?;
+            Ok(added)
+        });
+
+        match result {
+            Ok(added) => info!(
+                "Discovered {} new of {} live channels",
+                added,
+                discovered.channels.len()
+            ),
+            Err(err) => error!("Could not add discovered channels: {:#}", err),
+        }
+    }
+}
+
+/// Join channels that were added to the registry while farming and leave the ones that were
+/// removed. `channels` are the channels the bots started with.
+async fn watch_channels(
+    config: Config<'static>,
+    mut channels: Vec<Vec<Channel>>,
+    shared: Arc<Shared>,
+) {
+    const INTERVAL: Duration = Duration::from_secs(30);
+
+    let registry = match Registry::open() {
+        Ok(registry) => registry,
+        Err(err) => {
+            error!("Not watching the channel registry: {:#}", err);
+            return;
+        }
+    };
+    let mut revision = registry.revision().ok();
+
+    loop {
+        sleep(INTERVAL).await;
+
+        let current = registry.revision().ok();
+        if current.is_some() && current == revision {
+            continue;
+        }
+        revision = current;
+
+        for (index, account) in config.accounts.iter().enumerate() {
+            let new: Vec<Channel> = match registry.channels_for(&config, index) {
+                Ok(new) => new
+                    .iter()
+                    .map(|channel| shared.names.intern(channel))
+                    .collect(),
+                Err(err) => {
+                    error!("Could not read the channel registry: {:#}", err);
+                    break;
+                }
+            };
+            let old: HashSet<&Channel> = channels[index].iter().collect();
+            let added: Vec<Channel> = new
+                .iter()
+                .filter(|channel| !old.contains(channel))
+                .cloned()
+                .collect();
+            let current: HashSet<&Channel> = new.iter().collect();
+            let removed: Vec<Channel> = channels[index]
+                .iter()
+                .filter(|channel| !current.contains(channel))
+                .cloned()
+                .collect();
+
+            if !added.is_empty() || !removed.is_empty() {
+                debug!(
+                    "Channels of {} changed: {} added, {} removed",
+                    account.username,
+                    added.len(),
+                    removed.len()
+                );
+
+                if let Some(bot) = shared.bots.lock().unwrap().get(account.username.as_ref()) {
+                    // the bot stopped if this fails
+                    if !added.is_empty() {
+                        bot.try_send(Control::Add(added)).ok();
+                    }
+                    for channel in removed {
+                        bot.try_send(Control::Part(channel)).ok();
+                    }
+                }
+            }
+
+            channels[index] = new;
+        }
+    }
+}
+
+/// Time until the next `at` local time, optionally on a specific day of the week
+fn until_next(at: NaiveTime, day: Option<Weekday>) -> Duration {
+    let now = Local::now().naive_local();
+    let mut next = now.date().and_time(at);
+    while next <= now || day.is_some_and(|day| next.weekday() != day) {
+        next += chrono::Duration::days(1);
+    }
+
+    (next - now).to_std().unwrap_or_default()
+}
+
+/// Log the session statistics every [`STATS_INTERVAL`]
+async fn log_stats(shared: Arc<Shared>) {
+    loop {
+        sleep(STATS_INTERVAL).await;
+
+        let stats = shared.counters.lock().unwrap().stats();
+        info!("Session: {}", stats);
+        for handler in &shared.handlers {
+            handler.on_stats(&stats);
+        }
+    }
+}
+
+/// Keep publishing to the MQTT broker and update the health there every minute
+async fn publish_mqtt(shared: Arc<Shared>) {
+    let mqtt = match &shared.mqtt {
+        Some(mqtt) => mqtt,
+        None => return,
+    };
+
+    let health = async {
+        loop {
+            let health = shared.counters.lock().unwrap().health();
+            mqtt.health(&health);
+            sleep(Duration::from_secs(60)).await;
+        }
+    };
+    mqtt.run().or(health).await
+}
+
+/// Ping `healthcheck` every few minutes, or its `/fail` URL if a bot stopped receiving messages
+async fn ping_healthcheck(healthcheck: Healthcheck, shared: Arc<Shared>) {
+    let client = match proxy::client() {
+        Ok(client) => client,
+        Err(err) => {
+            error!("Not pinging the healthcheck: {:#}", err);
+            return;
+        }
+    };
+    let interval = Duration::from_secs(healthcheck.minutes.max(1) * 60);
+
+    loop {
+        let stalled: Vec<String> = {
+            let counters = shared.counters.lock().unwrap();
+            counters
+                .health()
+                .accounts
+                .into_iter()
+                .filter(|account| !account.live())
+                .map(|account| account.username)
+                .collect()
+        };
+
+        let url = if stalled.is_empty() {
+            healthcheck.url.clone()
+        } else {
+            warn!(
+                "Reporting a failure to the healthcheck, nothing received as {}",
+                stalled.join(", ")
+            );
+            format!("{}/fail", healthcheck.url.trim_end_matches('/'))
+        };
+        let result = compat(async { client.get(&url).send().await?.error_for_status() }).await;
+        if let Err(err) = result {
+            warn!("Could not ping the healthcheck: {}", err);
+        }
+
+        sleep(interval).await;
+    }
+}
+
+/// Tell systemd the farm is ready once every bot joined its channels, then feed the watchdog
+/// while every bot is receiving messages
+async fn notify_systemd(shared: Arc<Shared>) {
+    let watchdog = systemd::watchdog();
+    let mut ready = false;
+
+    loop {
+        let (settled, health) = {
+            let counters = shared.counters.lock().unwrap();
+            (counters.settled(), counters.health())
+        };
+
+        if !ready && settled {
+            ready = true;
+            let state = format!("READY=1\nSTATUS=Joined {} channels", health.joined);
+            match systemd::notify(&state) {
+                Ok(()) => debug!("Told systemd the farm is ready"),
+                Err(err) => warn!("Could not tell systemd the farm is ready: {}", err),
+            }
+        }
+
+        match watchdog {
+            Some(interval) => {
+                if health.live() {
+                    if let Err(err) = systemd::notify("WATCHDOG=1") {
+                        warn!("Could not feed the systemd watchdog: {}", err);
+                    }
+                } else {
+                    debug!("Not feeding the systemd watchdog, a bot is not receiving anything");
+                }
+
+                // feed it twice per interval so a late wake up does not trigger it
+                let wait = interval / 2;
+                sleep(if ready {
+                    wait
+                } else {
+                    wait.min(Duration::from_secs(1))
+                })
+                .await;
+            }
+            None if ready => return,
+            None => sleep(Duration::from_secs(1)).await,
+        }
+    }
+}
+
+/// Send the gift notifications collected during each `window` as one
+async fn send_batches(window: Duration, shared: Arc<Shared>) {
+    loop {
+        sleep(window).await;
+        send_batch(&shared).await;
+    }
+}
+
+/// Send the collected gift notifications, a single gift is sent like it would be without
+/// batching
+async fn send_batch(shared: &Shared) {
+    let mut gifts = match &shared.batch {
+        Some((_, batch)) => std::mem::take(&mut *batch.lock().unwrap()),
+        None => return,
+    };
+
+    let notification = match gifts.len() {
+        0 => return,
+        1 => {
+            let gift = gifts.remove(0);
+            Notification::Gift {
+                value: shared.prices.value(&gift),
+                friend: gift.kind == GiftKind::SubGift && shared.is_friend(&gift.gifter),
+                gift,
+            }
+        }
+        _ => Notification::Gifts {
+            value: shared.prices.total(&gifts),
+            gifts,
+        },
+    };
+    shared.notifier.notify(&notification).await;
+}
+
+/// Send a summary of the last 24 hours every day at `at` local time
+async fn daily_summary(at: NaiveTime, shared: Arc<Shared>) {
+    loop {
+        sleep(until_next(at, None)).await;
+
+        let history = match History::load() {
+            Ok(history) => history,
+            Err(err) => {
+                error!("Could not summarize the last 24 hours: {:#}", err);
+                continue;
+            }
+        };
+
+        let summary = {
+            let mut counters = shared.counters.lock().unwrap();
+            let summary = Summary::new(
+                &history,
+                &shared.prices,
+                chrono::Duration::days(1),
+                counters.new_channels,
+                counters.reconnects,
+            );
+            counters.new_channels = 0;
+            counters.reconnects = 0;
+            summary
+        };
+
+        info!("Last 24 hours: {}", summary);
+        shared.notify(&Notification::DailySummary(summary)).await;
+    }
+}
+
+/// Mail a digest of the last week at the configured day and time
+#[cfg(feature = "smtp")]
+async fn weekly_digest(digest: Digest, shared: Arc<Shared>) {
+    loop {
+        sleep(until_next(digest.at, Some(digest.day))).await;
+
+        let result = match History::load() {
+            Ok(history) => {
+                digest
+                    .send(&history, &shared.prices, chrono::Utc::now())
+                    .await
+            }
+            Err(err) => Err(err),
+        };
+
+        match result {
+            Ok(()) => info!("Sent the weekly digest to {}", digest.to),
+            Err(err) => error!("Could not send the weekly digest: {:#}", err),
+        }
+    }
+}
+
+/// Connect to the chat server, or to the recorded lines when replaying, or to the simulation
+async fn connect(shared: &Shared, user_config: &UserConfig) -> Result<(AsyncRunner, Arc<Monitor>)> {
+    match (&shared.replay, &shared.simulation) {
+        (Some(lines), _) => connector::replay(lines, user_config).await,
+        (None, Some(simulation)) => connector::simulate(simulation, user_config).await,
+        (None, None) => connect_capturing(&shared.irc, user_config, shared.capture.clone()).await,
+    }
+}
+
+/// Feed the recorded lines to `account`
+async fn replay(account: &Account<'_>, shared: Arc<Shared>) -> Result<()> {
+    info!("Replaying as {}", account.username);
+
+    let mut bot = Bot::new(
+        account.user_config()?,
+        Vec::new(),
+        shared.clone(),
+        None,
+        None,
+        1,
+    )
+    .await?;
+    bot.run().await?;
+
+    info!(
+        "Replay finished, {} gifts landed on {}",
+        shared.counters.lock().unwrap().gifts,
+        account.username
+    );
+
+    Ok(())
+}
+
+/// Feed made up gifts to `account` until stopped
+async fn simulate(account: &Account<'_>, shared: Arc<Shared>) -> Result<()> {
+    if let Some(simulation) = &shared.simulation {
+        warn!(
+            "Simulating {} gifts per minute to {} in {} channels, they are stored like real gifts",
+            simulation.rate, account.username, simulation.channels
+        );
+    }
+
+    let mut bot = Bot::new(account.user_config()?, Vec::new(), shared, None, None, 1).await?;
+    bot.run().await
+}
+
+async fn farm(
+    account: &Account<'_>,
+    channels: Vec<Channel>,
+    config: &Config<'_>,
+    shared: Arc<Shared>,
+) -> Result<()> {
+    let user_config = account.user_config()?;
+    // the account counts as down for the health endpoint until it is connected
+    shared.counters.lock().unwrap().link(&user_config.name);
+
+    let whisperer = match &config.whisper {
+        Some(whisper) => match Whisperer::new(&account.token, whisper.clone()).await {
+            Ok(whisperer) => Some(whisperer),
+            Err(err) => {
+                warn!("Not whispering gifters of {}: {:#}", account.username, err);
+                None
+            }
+        },
+        None => None,
+    };
+
+    let mut bot = Bot::new(
+        user_config,
+        channels,
+        shared,
+        whisperer,
+        config.rotation.clone(),
+        config.join_batch,
+    )
+    .await
+    .with_context(|| format!("Could not connect as {}", account.username))?;
+
+    let result = bot.run().await;
+    bot.shared
+        .counters
+        .lock()
+        .unwrap()
+        .link(&bot.user_config.name)
+        .connected = false;
+    result
+}
@@ -0,0 +1,144 @@
+use crate::runtime::sleep;
+use futures::stream::{FuturesUnordered, StreamExt};
+use log::{debug, warn};
+use serde::Serialize;
+use smol::{
+    future::{self, FutureExt},
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+use std::{io, time::Duration};
+
+/// A bot that received nothing for this long is not live
+pub const STALLED_AFTER: Duration = Duration::from_secs(3 * 60);
+/// Connections that did not send their request by then are closed
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How the farm is doing, served as JSON by the health endpoint
+#[derive(Debug, Clone, Serialize)]
+pub struct Health {
+    pub accounts: Vec<AccountHealth>,
+    /// Channels joined by all accounts together
+    pub joined: usize,
+    /// Channels of all accounts together
+    pub configured: usize,
+    /// Seconds since any account received something
+    pub last_message_secs: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AccountHealth {
+    pub username: String,
+    pub connected: bool,
+    pub joined: usize,
+    pub configured: usize,
+    pub last_message_secs: Option<u64>,
+}
+
+impl AccountHealth {
+    /// Connected and received something within [`STALLED_AFTER`]
+    pub fn live(&self) -> bool {
+        self.connected
+            && self
+                .last_message_secs
+                .is_some_and(|secs| secs <= STALLED_AFTER.as_secs())
+    }
+}
+
+impl Health {
+    /// Every account is live
+    pub fn live(&self) -> bool {
+        !self.accounts.is_empty() && self.accounts.iter().all(AccountHealth::live)
+    }
+
+    /// Every account is live and joined at least one of its channels
+    pub fn ready(&self) -> bool {
+        self.live()
+            && self
+                .accounts
+                .iter()
+                .all(|account| account.joined > 0 || account.configured == 0)
+    }
+}
+
+/// Answer `GET /healthz` and `GET /readyz` on `listener` with the current `health`.
+///
+/// Both answer `200 OK` or `503 Service Unavailable` with the [`Health`] as body.
+pub async fn serve(listener: TcpListener, health: impl Fn() -> Health) {
+    // answered side by side, so a client that sends nothing can't hold up the probes
+    let mut connections = FuturesUnordered::new();
+    loop {
+        let accepted = listener.accept().or(async {
+            while connections.next().await.is_some() {}
+            future::pending().await
+        });
+        let stream = match accepted.await {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+                warn!("Health endpoint could not accept a connection: {}", err);
+                continue;
+            }
+        };
+
+        let health = &health;
+        connections.push(async move {
+            let answered = respond(stream, health).or(async {
+                sleep(REQUEST_TIMEOUT).await;
+                Err(io::ErrorKind::TimedOut.into())
+            });
+            if let Err(err) = answered.await {
+                debug!("Health endpoint could not answer: {}", err);
+            }
+        });
+    }
+}
+
+async fn respond(stream: TcpStream, health: &impl Fn() -> Health) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.clone());
+    let mut request = String::new();
+    reader.read_line(&mut request).await?;
+
+    // skip the headers, nothing in them matters
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 || line == "\r\n" || line == "\n" {
+            break;
+        }
+    }
+
+    let mut parts = request.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some(path)) if path == "/healthz" || path == "/readyz" => {
+            let health = health();
+            let ok = if path == "/healthz" {
+                health.live()
+            } else {
+                health.ready()
+            };
+            let body = serde_json::to_string(&health).map_err(io::Error::from)?;
+            let status = if ok {
+                "200 OK"
+            } else {
+                "503 Service Unavailable"
+            };
+            (status, body)
+        }
+        (Some("GET"), Some(_)) => ("404 Not Found", String::new()),
+        _ => ("405 Method Not Allowed", String::new()),
+    };
+
+    let mut stream = stream;
+    stream
+        .write_all(
+            format!(
+                "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+                 Connection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            )
+            .as_bytes(),
+        )
+        .await?;
+    stream.flush().await
+}
@@ -0,0 +1,271 @@
+use crate::{config::project_dirs, proxy, runtime::compat};
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use lazy_static::lazy_static;
+use log::{debug, warn};
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION},
+    Client, Response, StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    borrow::Cow,
+    fs,
+    path::{Path, PathBuf},
+};
+
+const VALIDATE: &str = "https://id.twitch.tv/oauth2/validate";
+const TOKEN: &str = "https://id.twitch.tv/oauth2/token";
+const HELIX_WHISPERS: &str = "https://api.twitch.tv/helix/whispers";
+const HELIX_FOLLOWED: &str = "https://api.twitch.tv/helix/channels/followed";
+const APP_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+#[derive(Debug, Deserialize)]
+struct ValidateResponse<'a> {
+    client_id: Cow<'a, str>,
+    user_id: Cow<'a, str>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    /// Seconds until the token expires
+    expires_in: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorResponse<'a> {
+    // the id endpoints leave this out
+    #[serde(default)]
+    error: Cow<'a, str>,
+    status: u16,
+    message: Cow<'a, str>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FollowedResponse {
+    data: Vec<Followed>,
+    #[serde(default)]
+    pagination: Pagination,
+}
+
+#[derive(Debug, Deserialize)]
+struct Followed {
+    broadcaster_login: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Pagination {
+    cursor: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct WhisperRequest<'a> {
+    message: &'a str,
+}
+
+/// A client for the Helix API acting as the owner of a user access token
+pub struct Helix {
+    client: Client,
+    user_id: u64,
+}
+
+impl Helix {
+    /// Validate `token` and create a client that uses it.
+    ///
+    /// `token` may be prefixed with `oauth:` like the chat token.
+    pub async fn with_user_token(token: &str) -> Result<Self> {
+        let token = token.trim_start_matches("oauth:");
+
+        compat(async {
+            let resp = proxy::client()?
+                .get(VALIDATE)
+                .header(AUTHORIZATION, format!("OAuth {}", token))
+                .send()
+                .await?;
+            let validate = check(resp, "Could not validate token")
+                .await?
+                .json::<ValidateResponse>()
+                .await?;
+
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                AUTHORIZATION,
+                HeaderValue::from_str(&format!("Bearer {}", token))?,
+            );
+            headers.insert(
+                HeaderName::from_static("client-id"),
+                HeaderValue::from_str(&validate.client_id)?,
+            );
+
+            let client = proxy::client_builder()
+                .default_headers(headers)
+                .user_agent(APP_USER_AGENT)
+                .build()?;
+
+            Ok(Self {
+                client,
+                user_id: validate
+                    .user_id
+                    .parse()
+                    .context("Invalid user id in token")?,
+            })
+        })
+        .await
+    }
+
+    /// Whisper `message` to the user with the id `to`.
+    ///
+    /// Requires the `user:manage:whispers` scope.
+    pub async fn send_whisper(&self, to: u64, message: &str) -> Result<()> {
+        compat(async {
+            let resp = self
+                .client
+                .post(HELIX_WHISPERS)
+                .query(&[("from_user_id", self.user_id), ("to_user_id", to)])
+                .json(&WhisperRequest { message })
+                .send()
+                .await?;
+            check(resp, "Could not send whisper").await?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    /// Logins of all channels the user follows.
+    ///
+    /// Requires the `user:read:follows` scope.
+    pub async fn followed_channels(&self) -> Result<Vec<String>> {
+        compat(async {
+            let user_id = self.user_id.to_string();
+            let mut channels = Vec::new();
+            let mut cursor: Option<String> = None;
+
+            loop {
+                let mut query = vec![("user_id", user_id.as_str()), ("first", "100")];
+                if let Some(cursor) = &cursor {
+                    query.push(("after", cursor.as_str()));
+                }
+
+                let resp = self.client.get(HELIX_FOLLOWED).query(&query).send().await?;
+                if resp.status() == StatusCode::UNAUTHORIZED {
+                    return Err(anyhow!(
+                        "Could not get followed channels: the token is missing the \
+                         user:read:follows scope"
+                    ));
+                }
+                let page = check(resp, "Could not get followed channels")
+                    .await?
+                    .json::<FollowedResponse>()
+                    .await?;
+
+                channels.extend(page.data.into_iter().map(|f| f.broadcaster_login));
+
+                match page.pagination.cursor {
+                    Some(next) if !next.is_empty() => cursor = Some(next),
+                    _ => break,
+                }
+            }
+
+            Ok(channels)
+        })
+        .await
+    }
+}
+
+/// An app access token from the client credentials flow, cached in the data directory
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AppToken {
+    pub client_id: String,
+    pub access_token: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl AppToken {
+    /// The cached token of the application or a new one if it is about to expire
+    pub async fn get(client_id: &str, client_secret: &str) -> Result<Self> {
+        let cached = Self::load().filter(|token| {
+            token.client_id == client_id && token.expires_at > Utc::now() + Duration::minutes(10)
+        });
+
+        match cached {
+            Some(token) => Ok(token),
+            None => Self::fetch(client_id, client_secret).await,
+        }
+    }
+
+    /// Get a new token and cache it
+    pub async fn fetch(client_id: &str, client_secret: &str) -> Result<Self> {
+        debug!("Getting an app access token for {}", client_id);
+
+        let resp = compat(async {
+            let resp = proxy::client()?
+                .post(TOKEN)
+                .query(&[
+                    ("client_id", client_id),
+                    ("client_secret", client_secret),
+                    ("grant_type", "client_credentials"),
+                ])
+                .send()
+                .await?;
+
+            Ok::<_, anyhow::Error>(
+                check(resp, "Could not get an app access token")
+                    .await?
+                    .json::<TokenResponse>()
+                    .await?,
+            )
+        })
+        .await?;
+
+        let token = Self {
+            client_id: client_id.to_string(),
+            access_token: resp.access_token,
+            expires_at: Utc::now() + Duration::seconds(resp.expires_in),
+        };
+
+        if let Err(err) = token.save() {
+            warn!("Could not cache the app access token: {:#}", err);
+        }
+
+        Ok(token)
+    }
+
+    fn load() -> Option<Self> {
+        let content = fs::read_to_string(Self::path()).ok()?;
+        serde_json::from_str(&content).ok()
+    }
+
+    fn save(&self) -> Result<()> {
+        let path = Self::path();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).context("Could not create data directory")?;
+        }
+
+        fs::write(path, serde_json::to_string(self)?).context("Could not write app token")
+    }
+
+    pub fn path() -> &'static Path {
+        lazy_static! {
+            static ref PATH: PathBuf = project_dirs().data_dir().join("app_token.json");
+        }
+
+        PATH.as_ref()
+    }
+}
+
+pub(crate) async fn check(resp: Response, context: &str) -> Result<Response> {
+    if resp.status().is_client_error() || resp.status().is_server_error() {
+        let error = resp.json::<ErrorResponse>().await?;
+        return Err(anyhow!(
+            "{}: {} {}: {}",
+            context,
+            error.status,
+            error.error,
+            error.message
+        ));
+    }
+
+    Ok(resp)
+}
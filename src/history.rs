@@ -0,0 +1,263 @@
+use crate::config::project_dirs;
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+use lazy_static::lazy_static;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+use twitchchat::messages::SubPlan;
+
+/// A gift that landed on one of the accounts
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Gift {
+    pub time: DateTime<Utc>,
+    /// The account that received the gift
+    pub account: String,
+    pub channel: String,
+    pub gifter: String,
+    pub kind: GiftKind,
+    pub tier: Tier,
+    pub plan_name: String,
+    /// Number of months that were gifted
+    #[serde(default = "default_months")]
+    pub months: u64,
+    /// Number of months the account has been subscribed to the channel
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recipient_months: Option<u64>,
+    /// Number of gifts in the community gift this gift was part of
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub community_gift: Option<u64>,
+    /// For pay forwards, who gifted the sub that was paid forward
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prior_gifter: Option<String>,
+}
+
+fn default_months() -> u64 {
+    1
+}
+
+impl Gift {
+    /// A gift that happened just now with an unknown tier
+    pub fn new(account: &str, channel: &str, gifter: &str, kind: GiftKind) -> Self {
+        Self {
+            time: Utc::now(),
+            account: account.to_string(),
+            channel: channel.trim_start_matches('#').to_string(),
+            gifter: gifter.to_string(),
+            kind,
+            tier: Tier::Unknown,
+            plan_name: String::new(),
+            months: default_months(),
+            recipient_months: None,
+            community_gift: None,
+            prior_gifter: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GiftKind {
+    SubGift,
+    AnonSubGift,
+    /// The account continued a gifted sub
+    GiftPaidUpgrade,
+    /// The account continued a sub gifted anonymously
+    AnonGiftPaidUpgrade,
+    /// The account converted a prime sub to a paid sub
+    PrimePaidUpgrade,
+    /// Someone paid a gift they received forward to the account
+    StandardPayForward,
+    /// Someone paid a gift they received forward to the community of a channel
+    CommunityPayForward,
+}
+
+impl GiftKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::SubGift => "sub gift",
+            Self::AnonSubGift => "anonymous sub gift",
+            Self::GiftPaidUpgrade => "gift paid upgrade",
+            Self::AnonGiftPaidUpgrade => "anonymous gift paid upgrade",
+            Self::PrimePaidUpgrade => "prime paid upgrade",
+            Self::StandardPayForward => "pay forward",
+            Self::CommunityPayForward => "community pay forward",
+        }
+    }
+
+    pub fn is_gift(self) -> bool {
+        matches!(self, Self::SubGift | Self::AnonSubGift)
+    }
+
+    pub fn is_upgrade(self) -> bool {
+        matches!(
+            self,
+            Self::GiftPaidUpgrade | Self::AnonGiftPaidUpgrade | Self::PrimePaidUpgrade
+        )
+    }
+
+    pub fn is_pay_forward(self) -> bool {
+        matches!(self, Self::StandardPayForward | Self::CommunityPayForward)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Tier {
+    Prime,
+    Tier1,
+    Tier2,
+    Tier3,
+    Unknown,
+}
+
+impl Tier {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Prime => "prime",
+            Self::Tier1 => "tier1",
+            Self::Tier2 => "tier2",
+            Self::Tier3 => "tier3",
+            Self::Unknown => "unknown",
+        }
+    }
+}
+
+impl From<Option<SubPlan<'_>>> for Tier {
+    fn from(plan: Option<SubPlan<'_>>) -> Self {
+        match plan {
+            Some(SubPlan::Prime) => Self::Prime,
+            // Twitch sends the tiers as 1000, 2000 and 3000, twitchchat only knows their names
+            Some(SubPlan::Tier1) | Some(SubPlan::Unknown("1000")) => Self::Tier1,
+            Some(SubPlan::Tier2) | Some(SubPlan::Unknown("2000")) => Self::Tier2,
+            Some(SubPlan::Tier3) | Some(SubPlan::Unknown("3000")) => Self::Tier3,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+/// Append-only log of received gifts, stored as one JSON object per line
+#[derive(Debug)]
+pub struct History {
+    file: Mutex<File>,
+}
+
+impl History {
+    pub fn open() -> Result<Self> {
+        let path = Self::path();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).context("Could not create data directory")?;
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .context("Could not open history file")?;
+
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+
+    pub fn append(&self, gift: &Gift) -> Result<()> {
+        let mut line = serde_json::to_vec(gift)?;
+        line.push(b'\n');
+
+        self.file
+            .lock()
+            .unwrap()
+            .write_all(&line)
+            .context("Could not write to history file")
+    }
+
+    /// Read all recorded gifts, oldest first
+    pub fn load() -> Result<Vec<Gift>> {
+        let path = Self::path();
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+
+        debug!("Loading history from {}", path.display());
+
+        let file = File::open(path).context("Could not open history file")?;
+        BufReader::new(file)
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !matches!(line, Ok(line) if line.trim().is_empty()))
+            .map(|(index, line)| {
+                serde_json::from_str(&line?)
+                    .with_context(|| format!("Invalid gift in line {} of history", index + 1))
+            })
+            .collect()
+    }
+
+    pub fn path() -> &'static Path {
+        lazy_static! {
+            static ref PATH: PathBuf = project_dirs().data_dir().join("history.jsonl");
+        }
+
+        PATH.as_ref()
+    }
+}
+
+/// The gifts of each channel in a log file of its own, one tab separated line per gift
+#[derive(Debug)]
+pub struct ChannelLogs {
+    dir: PathBuf,
+}
+
+impl ChannelLogs {
+    pub fn open() -> Result<Self> {
+        let dir = Self::dir();
+        fs::create_dir_all(dir).context("Could not create channel log directory")?;
+
+        Ok(Self {
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    /// Append a line like `2024-01-02T03:04:05Z  sub gift  tier1  1  gifter  account`
+    pub fn append(&self, gift: &Gift) -> Result<()> {
+        let channel = gift.channel.trim_start_matches('#');
+        // the name becomes part of a path
+        if channel.is_empty()
+            || !channel
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            return Err(anyhow!("Not a channel name: {}", gift.channel));
+        }
+
+        let line = format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\n",
+            gift.time.to_rfc3339_opts(SecondsFormat::Secs, true),
+            gift.kind.as_str(),
+            gift.tier.as_str(),
+            gift.months,
+            gift.gifter,
+            gift.account
+        );
+
+        let path = self.dir.join(format!("{}.log", channel));
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .and_then(|mut file| file.write_all(line.as_bytes()))
+            .with_context(|| format!("Could not write to {}", path.display()))
+    }
+
+    pub fn dir() -> &'static Path {
+        lazy_static! {
+            static ref PATH: PathBuf = project_dirs().data_dir().join("channels");
+        }
+
+        PATH.as_ref()
+    }
+}
@@ -0,0 +1,52 @@
+use crate::config::runtime_dir;
+use anyhow::{anyhow, Context, Result};
+use fs2::FileExt;
+use log::debug;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+};
+
+/// Makes sure only one process farms with an account at a time.
+///
+/// The lock is released when this is dropped or the process exits. The lock file itself is left
+/// behind, it only records the pid of the last owner.
+#[derive(Debug)]
+pub struct InstanceLock {
+    _file: File,
+}
+
+impl InstanceLock {
+    pub fn acquire(username: &str) -> Result<Self> {
+        let dir = runtime_dir();
+        fs::create_dir_all(dir).context("Could not create runtime directory")?;
+
+        let path = dir.join(format!("{}.lock", username));
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .with_context(|| format!("Could not open lock file {}", path.display()))?;
+
+        if file.try_lock_exclusive().is_err() {
+            let mut pid = String::new();
+            file.read_to_string(&mut pid).ok();
+
+            return Err(anyhow!(
+                "Already farming as {} in process {}, use --force to ignore",
+                username,
+                pid.trim()
+            ));
+        }
+
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        write!(file, "{}", std::process::id())?;
+
+        debug!("Locked {}", path.display());
+
+        Ok(Self { _file: file })
+    }
+}
@@ -0,0 +1,130 @@
+use anyhow::{anyhow, Result};
+use chrono::{
+    format::{Item, StrftimeItems},
+    Utc,
+};
+use flexi_logger::{style, DeferredNow, ReconfigurationHandle, Record};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::{
+    env,
+    io::{self, IsTerminal},
+    sync::{Mutex, RwLock},
+};
+
+lazy_static! {
+    static ref LOGGING: RwLock<Logging> = RwLock::default();
+    static ref HANDLE: Mutex<Option<ReconfigurationHandle>> = Mutex::default();
+}
+
+/// Log spec of `--quiet`, the gifts and the join progress but not every single channel
+pub const QUIET_SPEC: &str = "info";
+
+/// How log lines are written
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Logging {
+    /// strftime format of the timestamps, like `%Y-%m-%dT%H:%M:%S%.3fZ`
+    #[serde(default = "default_timestamp_format")]
+    pub timestamp_format: String,
+    /// Write the timestamps in UTC instead of local time
+    #[serde(default)]
+    pub utc: bool,
+    /// Like `--quiet`, only log info and above so joining thousands of channels is summarized
+    /// by the progress lines
+    #[serde(default)]
+    pub quiet: bool,
+}
+
+fn default_timestamp_format() -> String {
+    "%Y-%m-%d %H:%M:%S%.6f %:z".to_string()
+}
+
+impl Default for Logging {
+    fn default() -> Self {
+        Self {
+            timestamp_format: default_timestamp_format(),
+            utc: false,
+            quiet: false,
+        }
+    }
+}
+
+/// Write the timestamps of all log lines from now on like `logging` says
+pub fn set(logging: &Logging) -> Result<()> {
+    if StrftimeItems::new(&logging.timestamp_format).any(|item| item == Item::Error) {
+        return Err(anyhow!(
+            "Invalid log timestamp format: {}",
+            logging.timestamp_format
+        ));
+    }
+
+    *LOGGING.write().unwrap() = logging.clone();
+    if logging.quiet {
+        quiet();
+    }
+    Ok(())
+}
+
+/// Keep `handle` of the started logger, so the config can make it quiet
+pub fn keep(handle: ReconfigurationHandle) {
+    *HANDLE.lock().unwrap() = Some(handle);
+}
+
+/// Switch to [`QUIET_SPEC`] unless `RUST_LOG` chose what to log
+fn quiet() {
+    if env::var_os("RUST_LOG").is_some() {
+        return;
+    }
+    if let Some(handle) = HANDLE.lock().unwrap().as_mut() {
+        handle.parse_new_spec(QUIET_SPEC);
+    }
+}
+
+fn timestamp(now: &mut DeferredNow) -> String {
+    let logging = LOGGING.read().unwrap();
+    if logging.utc {
+        now.now()
+            .with_timezone(&Utc)
+            .format(&logging.timestamp_format)
+            .to_string()
+    } else {
+        now.now().format(&logging.timestamp_format).to_string()
+    }
+}
+
+/// Whether the log on stderr should be colored: it goes to a terminal and `NO_COLOR` is not set
+pub fn use_color() -> bool {
+    env::var_os("NO_COLOR").map_or(true, |value| value.is_empty()) && io::stderr().is_terminal()
+}
+
+pub fn logger_format(
+    w: &mut dyn std::io::Write,
+    now: &mut DeferredNow,
+    record: &Record,
+) -> Result<(), std::io::Error> {
+    let level = record.level();
+    write!(
+        w,
+        "[{}] {} [{}] {}",
+        timestamp(now),
+        style(level, level),
+        record.module_path().unwrap_or("<unnamed>"),
+        style(level, record.args())
+    )
+}
+
+/// Like [`logger_format`] without colors, for log files and pipes
+pub fn plain_format(
+    w: &mut dyn std::io::Write,
+    now: &mut DeferredNow,
+    record: &Record,
+) -> Result<(), std::io::Error> {
+    write!(
+        w,
+        "[{}] {} [{}] {}",
+        timestamp(now),
+        record.level(),
+        record.module_path().unwrap_or("<unnamed>"),
+        record.args()
+    )
+}
@@ -0,0 +1,93 @@
+use anyhow::Result;
+use clap::{Parser, Subcommand};
+use twitch_gift_farm::{
+    logger::{self, QUIET_SPEC},
+    logger_format, plain_format, use_color,
+};
+
+mod cmd;
+
+/// Farm gifted subscriptions on Twitch
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Opts {
+    /// Log without colors, also the case if NO_COLOR is set or stderr is not a terminal
+    #[arg(long, global = true)]
+    no_color: bool,
+
+    /// Only log info and above, the joins of single channels are left out
+    #[arg(short, long, global = true)]
+    quiet: bool,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Join all configured channels and wait for gifted subs
+    Farm(cmd::farm::Opts),
+    /// Add channels that are currently live to the config
+    Discover(cmd::discover::Opts),
+    /// Show statistics about the configured channels
+    Stats(cmd::stats::Opts),
+    /// Save the account credentials to the config
+    Auth(cmd::auth::Opts),
+    /// List, add or remove configured channels
+    Channels(cmd::channels::Opts),
+    /// Check the config and the connection to Twitch
+    Doctor,
+    /// Look back on the gifts received in a year
+    Report(cmd::report::Opts),
+    /// Export the gift history for analysis in other tools
+    Export(cmd::export::Opts),
+    /// Remove channels that have been offline or deleted for a while
+    Prune(cmd::prune::Opts),
+    /// Watch channels anonymously and add the ones with subs and gifts
+    Scout(cmd::scout::Opts),
+    /// Feed one made up gift to an account to try out notifications and templates
+    SimulateEvent(cmd::simulate_event::Opts),
+}
+
+fn main() -> Result<()> {
+    let opts = Opts::parse();
+
+    let format = if !opts.no_color && use_color() {
+        logger_format
+    } else {
+        plain_format
+    };
+    let spec = if opts.quiet {
+        QUIET_SPEC
+    } else {
+        "info,twitch_gift_farm=trace"
+    };
+    let logger = flexi_logger::Logger::with_env_or_str(spec).format(format);
+    #[cfg(windows)]
+    let logger = match &opts.command {
+        Command::Farm(opts) if opts.service => logger.log_target(flexi_logger::LogTarget::Writer(
+            Box::new(cmd::service::EventLogWriter::new()?),
+        )),
+        _ => logger,
+    };
+    #[cfg(unix)]
+    let logger = match &opts.command {
+        Command::Farm(opts) if opts.daemon => cmd::daemon::start(logger)?,
+        _ => logger,
+    };
+    logger::keep(logger.start()?);
+
+    match opts.command {
+        Command::Farm(opts) => cmd::farm::run(opts),
+        Command::Discover(opts) => cmd::discover::run(opts),
+        Command::Stats(opts) => cmd::stats::run(opts),
+        Command::Auth(opts) => cmd::auth::run(opts),
+        Command::Channels(opts) => cmd::channels::run(opts),
+        Command::Doctor => cmd::doctor::run(),
+        Command::Report(opts) => cmd::report::run(opts),
+        Command::Export(opts) => cmd::export::run(opts),
+        Command::Prune(opts) => cmd::prune::run(opts),
+        Command::Scout(opts) => cmd::scout::run(opts),
+        Command::SimulateEvent(opts) => cmd::simulate_event::run(opts),
+    }
+}
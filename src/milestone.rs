@@ -0,0 +1,107 @@
+use crate::history::{Gift, Tier};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, fmt};
+
+/// Which milestones trigger a notification
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Milestones {
+    /// Total numbers of received gifts
+    #[serde(default = "default_counts")]
+    pub counts: Vec<u64>,
+    /// The first tier 3 gift
+    #[serde(default = "default_true")]
+    pub first_tier3: bool,
+    /// The first gift in a channel
+    #[serde(default = "default_true")]
+    pub first_in_channel: bool,
+}
+
+fn default_counts() -> Vec<u64> {
+    vec![50, 100, 500]
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Default for Milestones {
+    fn default() -> Self {
+        Self {
+            counts: default_counts(),
+            first_tier3: true,
+            first_in_channel: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Milestone {
+    Count(u64),
+    FirstTier3,
+    FirstInChannel(String),
+}
+
+impl fmt::Display for Milestone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Count(count) => write!(f, "{} gifts received", count),
+            Self::FirstTier3 => write!(f, "First tier 3 gift received"),
+            Self::FirstInChannel(channel) => write!(f, "First gift in {}", channel),
+        }
+    }
+}
+
+/// Keeps the totals needed to detect milestones
+#[derive(Debug)]
+pub struct MilestoneTracker {
+    config: Milestones,
+    count: u64,
+    had_tier3: bool,
+    channels: HashSet<String>,
+}
+
+impl MilestoneTracker {
+    /// Start with the totals of the gifts in the history
+    pub fn new<'a>(config: Milestones, history: impl IntoIterator<Item = &'a Gift>) -> Self {
+        let mut tracker = Self {
+            config,
+            count: 0,
+            had_tier3: false,
+            channels: HashSet::new(),
+        };
+
+        for gift in history {
+            tracker.record(gift);
+        }
+
+        tracker
+    }
+
+    /// Count `gift` and return all milestones it reached
+    pub fn record(&mut self, gift: &Gift) -> Vec<Milestone> {
+        let mut reached = Vec::new();
+
+        if !gift.kind.is_gift() {
+            return reached;
+        }
+
+        self.count += 1;
+        if self.config.counts.contains(&self.count) {
+            reached.push(Milestone::Count(self.count));
+        }
+
+        if gift.tier == Tier::Tier3 && !self.had_tier3 {
+            self.had_tier3 = true;
+            if self.config.first_tier3 {
+                reached.push(Milestone::FirstTier3);
+            }
+        }
+
+        if self.channels.insert(gift.channel.clone()) && self.config.first_in_channel {
+            reached.push(Milestone::FirstInChannel(gift.channel.clone()));
+        }
+
+        reached
+    }
+}
@@ -0,0 +1,356 @@
+use crate::{health::Health, history::Gift, runtime::sleep, value::Value};
+use anyhow::{bail, Context, Result};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use smol::{
+    channel::{self, Receiver, Sender, TrySendError},
+    future::FutureExt,
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+use std::time::Duration;
+
+/// Messages waiting while the broker is not reachable, newer ones are dropped
+const QUEUE: usize = 64;
+const KEEP_ALIVE: Duration = Duration::from_secs(60);
+/// Time before connecting again after the connection failed, doubled up to [`MAX_RETRY_DELAY`]
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(5 * 60);
+/// The states of the gift event entity, one for every tier
+const EVENT_TYPES: [&str; 5] = ["tier1", "tier2", "tier3", "prime", "unknown"];
+
+/// The MQTT broker gifts and the health of the farm are published to
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Mqtt {
+    /// Address like `localhost:1883`, connected to without TLS
+    pub broker: String,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    #[serde(default = "default_client_id")]
+    pub client_id: String,
+    /// Topic the others are below, gifts go to `<topic>/gift`, the health to `<topic>/health`
+    /// and `online` or `offline` to `<topic>/status`
+    #[serde(default = "default_topic")]
+    pub topic: String,
+    /// Publish the Home Assistant discovery messages below this prefix, nothing if unset
+    #[serde(default = "default_discovery_prefix")]
+    pub discovery_prefix: Option<String>,
+}
+
+fn default_client_id() -> String {
+    "tgf".to_string()
+}
+
+fn default_topic() -> String {
+    "tgf".to_string()
+}
+
+fn default_discovery_prefix() -> Option<String> {
+    Some("homeassistant".to_string())
+}
+
+struct Message {
+    topic: String,
+    payload: String,
+    retain: bool,
+}
+
+/// Publishes to the broker of `config` while [`run`](Self::run) is running
+pub struct Publisher {
+    config: Mqtt,
+    messages: Sender<Message>,
+    queue: Receiver<Message>,
+}
+
+impl Publisher {
+    pub fn new(config: Mqtt) -> Self {
+        let (messages, queue) = channel::bounded(QUEUE);
+
+        Self {
+            config,
+            messages,
+            queue,
+        }
+    }
+
+    /// Publish `gift` with the tier as `event_type` like the event entity wants it
+    pub fn gift(&self, gift: &Gift, value: &Value) {
+        let payload = json!({
+            "event_type": gift.tier.as_str(),
+            "kind": gift.kind.as_str(),
+            "account": gift.account,
+            "channel": gift.channel,
+            "gifter": gift.gifter,
+            "months": gift.months,
+            "value": value.amount,
+            "currency": value.currency,
+        });
+        self.publish("gift", payload.to_string(), false);
+    }
+
+    pub fn health(&self, health: &Health) {
+        let payload = json!({
+            "live": health.live(),
+            "ready": health.ready(),
+            "joined": health.joined,
+            "configured": health.configured,
+            "last_message_secs": health.last_message_secs,
+        });
+        self.publish("health", payload.to_string(), true);
+    }
+
+    /// Queue `payload` for `<topic>/<subtopic>`
+    fn publish(&self, subtopic: &str, payload: String, retain: bool) {
+        let message = Message {
+            topic: format!("{}/{}", self.config.topic, subtopic),
+            payload,
+            retain,
+        };
+        if let Err(TrySendError::Full(message)) = self.messages.try_send(message) {
+            debug!("Not publishing to {}, the queue is full", message.topic);
+        }
+    }
+
+    /// Stop taking messages and publish the queued ones, if any
+    pub async fn finish(&self) {
+        self.messages.close();
+        if !self.queue.is_empty() {
+            self.run().await;
+        }
+    }
+
+    /// Keep connected to the broker and publish the queued messages, connect again if the
+    /// connection fails
+    pub async fn run(&self) {
+        let mut delay = RETRY_DELAY;
+        loop {
+            match self.session(&mut delay).await {
+                Ok(()) => return,
+                Err(err) => warn!(
+                    "MQTT connection to {} failed, retrying in {}s: {:#}",
+                    self.config.broker,
+                    delay.as_secs(),
+                    err
+                ),
+            }
+            sleep(delay).await;
+            delay = (delay * 2).min(MAX_RETRY_DELAY);
+        }
+    }
+
+    /// Connect once and publish until the connection fails. `delay` is reset once connected.
+    async fn session(&self, delay: &mut Duration) -> Result<()> {
+        let mut stream = TcpStream::connect(self.config.broker.as_str())
+            .await
+            .context("Could not connect")?;
+        stream.write_all(&self.connect_packet()).await?;
+
+        let (kind, body) = read_packet(&mut stream).await?;
+        match (kind >> 4, body.get(1)) {
+            (2, Some(0)) => {}
+            (2, Some(code)) => bail!("the broker refused the connection with code {}", code),
+            _ => bail!("expected CONNACK, got packet type {}", kind >> 4),
+        }
+        info!("Connected to the MQTT broker {}", self.config.broker);
+        *delay = RETRY_DELAY;
+
+        let mut writer = stream.clone();
+        writer
+            .write_all(&publish_packet(&self.status_topic(), "online", true))
+            .await?;
+        for (topic, payload) in self.discovery() {
+            writer
+                .write_all(&publish_packet(&topic, &payload, true))
+                .await?;
+        }
+
+        let publishing = async {
+            loop {
+                let next = async { self.queue.recv().await.map(Some) }.or(async {
+                    sleep(KEEP_ALIVE / 2).await;
+                    Ok(None)
+                });
+                let message = match next.await {
+                    Ok(Some(message)) => message,
+                    Ok(None) => {
+                        // PINGREQ
+                        writer.write_all(&[0xc0, 0]).await?;
+                        continue;
+                    }
+                    Err(_) => return Ok(()),
+                };
+                writer
+                    .write_all(&publish_packet(
+                        &message.topic,
+                        &message.payload,
+                        message.retain,
+                    ))
+                    .await?;
+            }
+        };
+        // the broker only answers pings, but reading notices when the connection is gone
+        let reading = async {
+            loop {
+                read_packet(&mut stream).await?;
+            }
+        };
+
+        publishing.or(reading).await
+    }
+
+    fn status_topic(&self) -> String {
+        format!("{}/status", self.config.topic)
+    }
+
+    /// CONNECT with a clean session and a will that marks the farm offline
+    fn connect_packet(&self) -> Vec<u8> {
+        let mut flags = 0x02 | 0x04 | 0x20;
+        let mut payload = Vec::new();
+        put_str(&mut payload, &self.config.client_id);
+        put_str(&mut payload, &self.status_topic());
+        put_str(&mut payload, "offline");
+        if let Some(username) = &self.config.username {
+            flags |= 0x80;
+            put_str(&mut payload, username);
+        }
+        if let Some(password) = &self.config.password {
+            flags |= 0x40;
+            put_str(&mut payload, password);
+        }
+
+        let mut body = Vec::new();
+        put_str(&mut body, "MQTT");
+        body.push(4);
+        body.push(flags);
+        body.extend_from_slice(&(KEEP_ALIVE.as_secs() as u16).to_be_bytes());
+        body.extend(payload);
+
+        packet(0x10, &body)
+    }
+
+    /// The Home Assistant discovery messages for the gift event, whether the farm is live and
+    /// ready and the number of joined channels
+    fn discovery(&self) -> Vec<(String, String)> {
+        let prefix = match &self.config.discovery_prefix {
+            Some(prefix) => prefix,
+            None => return Vec::new(),
+        };
+        let id = &self.config.client_id;
+        let device = json!({
+            "identifiers": [id],
+            "name": "Twitch Gift Farm",
+            "sw_version": env!("CARGO_PKG_VERSION"),
+        });
+        let health = format!("{}/health", self.config.topic);
+        let entity = |component: &str, object: &str, mut config: serde_json::Value| {
+            config["unique_id"] = json!(format!("{}_{}", id, object));
+            config["object_id"] = json!(format!("{}_{}", id, object));
+            config["availability_topic"] = json!(self.status_topic());
+            config["device"] = device.clone();
+            (
+                format!("{}/{}/{}/{}/config", prefix, component, id, object),
+                config.to_string(),
+            )
+        };
+
+        vec![
+            entity(
+                "event",
+                "gift",
+                json!({
+                    "name": "Gift",
+                    "state_topic": format!("{}/gift", self.config.topic),
+                    "event_types": EVENT_TYPES,
+                    "icon": "mdi:gift",
+                }),
+            ),
+            entity(
+                "binary_sensor",
+                "live",
+                json!({
+                    "name": "Live",
+                    "state_topic": health,
+                    "value_template": "{{ 'ON' if value_json.live else 'OFF' }}",
+                    "device_class": "connectivity",
+                }),
+            ),
+            entity(
+                "binary_sensor",
+                "ready",
+                json!({
+                    "name": "Ready",
+                    "state_topic": health,
+                    "value_template": "{{ 'ON' if value_json.ready else 'OFF' }}",
+                    "device_class": "running",
+                }),
+            ),
+            entity(
+                "sensor",
+                "joined",
+                json!({
+                    "name": "Joined channels",
+                    "state_topic": health,
+                    "value_template": "{{ value_json.joined }}",
+                    "state_class": "measurement",
+                    "icon": "mdi:television-classic",
+                }),
+            ),
+        ]
+    }
+}
+
+/// A packet of `kind` with the remaining length in front of `body`
+fn packet(kind: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![kind];
+    let mut length = body.len();
+    loop {
+        let mut byte = (length % 128) as u8;
+        length /= 128;
+        if length > 0 {
+            byte |= 0x80;
+        }
+        packet.push(byte);
+        if length == 0 {
+            break;
+        }
+    }
+    packet.extend_from_slice(body);
+    packet
+}
+
+/// PUBLISH with QoS 0
+fn publish_packet(topic: &str, payload: &str, retain: bool) -> Vec<u8> {
+    let mut body = Vec::new();
+    put_str(&mut body, topic);
+    body.extend_from_slice(payload.as_bytes());
+
+    packet(0x30 | retain as u8, &body)
+}
+
+fn put_str(buffer: &mut Vec<u8>, text: &str) {
+    buffer.extend_from_slice(&(text.len() as u16).to_be_bytes());
+    buffer.extend_from_slice(text.as_bytes());
+}
+
+/// The first byte and the body of the next packet
+async fn read_packet(stream: &mut TcpStream) -> Result<(u8, Vec<u8>)> {
+    let mut byte = [0];
+    stream.read_exact(&mut byte).await?;
+    let kind = byte[0];
+
+    let mut length = 0;
+    for shift in (0..28).step_by(7) {
+        stream.read_exact(&mut byte).await?;
+        length |= ((byte[0] & 0x7f) as usize) << shift;
+        if byte[0] & 0x80 == 0 {
+            let mut body = vec![0; length];
+            stream.read_exact(&mut body).await?;
+            return Ok((kind, body));
+        }
+    }
+
+    bail!("malformed packet length")
+}
@@ -0,0 +1,802 @@
+use crate::{
+    history::{Gift, GiftKind, Tier},
+    milestone::Milestone,
+    proxy,
+    runtime::{self, compat, sleep, Task},
+    summary::Summary,
+    value::Value,
+};
+use anyhow::{anyhow, Result};
+use futures::future::join_all;
+use log::{debug, info, warn};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use smol::future::FutureExt;
+use std::{
+    collections::VecDeque,
+    fmt, mem, process,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+const PUSHOVER_URL: &str = "https://api.pushover.net/1/messages.json";
+const APP_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+/// Where notifications are sent to
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub enum Sink {
+    /// POST a JSON object with `title`, `message` and the `event` to an URL
+    Webhook {
+        url: String,
+        /// Seconds to wait for the webhook before an attempt fails
+        #[serde(default = "default_timeout")]
+        timeout: u64,
+        /// How often a failed attempt is repeated
+        #[serde(default = "default_retries")]
+        retries: u32,
+        /// Send at most this many notifications per minute. The ones over the limit are held
+        /// back and sent as one summary as soon as the limit allows.
+        #[serde(default)]
+        per_minute: Option<u32>,
+    },
+    /// Post to a Slack channel through an incoming webhook, formatted with Block Kit
+    Slack {
+        url: String,
+        #[serde(default = "default_timeout")]
+        timeout: u64,
+        #[serde(default = "default_retries")]
+        retries: u32,
+        #[serde(default)]
+        per_minute: Option<u32>,
+    },
+    /// Post to a Matrix room with the title in bold
+    Matrix {
+        /// The URL of the homeserver, like `https://matrix.org`
+        homeserver: String,
+        /// Access token of the account that posts, it has to be in the room already
+        token: String,
+        /// The id of the room, like `!abcdefg:matrix.org`
+        room: String,
+        #[serde(default = "default_timeout")]
+        timeout: u64,
+        #[serde(default = "default_retries")]
+        retries: u32,
+        #[serde(default)]
+        per_minute: Option<u32>,
+    },
+    /// Push the `title` and `message` to phones with Pushover
+    Pushover {
+        /// The API token of the application
+        token: String,
+        /// The user or group key to send to
+        user: String,
+        /// From -2 (no alert) to 1 (bypass quiet hours)
+        #[serde(default = "Priorities::pushover")]
+        priorities: Priorities,
+        #[serde(default = "default_timeout")]
+        timeout: u64,
+        #[serde(default = "default_retries")]
+        retries: u32,
+        #[serde(default)]
+        per_minute: Option<u32>,
+    },
+    /// Publish the `title` and `message` to an ntfy topic
+    Ntfy {
+        /// The ntfy server, ntfy.sh by default
+        #[serde(default = "default_ntfy_server")]
+        server: String,
+        topic: String,
+        /// Access token for protected topics
+        #[serde(default)]
+        token: Option<String>,
+        /// From 1 (min) to 5 (max)
+        #[serde(default = "Priorities::ntfy")]
+        priorities: Priorities,
+        /// Tags added to every message, tags that are emoji shortcodes show up as emoji
+        #[serde(default)]
+        tags: Vec<String>,
+        #[serde(default = "default_timeout")]
+        timeout: u64,
+        #[serde(default = "default_retries")]
+        retries: u32,
+        #[serde(default)]
+        per_minute: Option<u32>,
+    },
+    /// Send the `title` and `message` to a Gotify server
+    Gotify {
+        /// The URL of the server, like `https://gotify.example.com`
+        server: String,
+        /// The token of the application
+        token: String,
+        /// From 0 (silent) to 10, 8 and above is shown as high priority
+        #[serde(default = "Priorities::gotify")]
+        priorities: Priorities,
+        #[serde(default = "default_timeout")]
+        timeout: u64,
+        #[serde(default = "default_retries")]
+        retries: u32,
+        #[serde(default)]
+        per_minute: Option<u32>,
+    },
+}
+
+/// Priority of gifts by tier and of all other notifications
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Priorities {
+    pub tier1: i8,
+    pub tier2: i8,
+    pub tier3: i8,
+    pub other: i8,
+}
+
+impl Priorities {
+    fn pushover() -> Self {
+        Self {
+            tier1: 0,
+            tier2: 0,
+            tier3: 1,
+            other: 0,
+        }
+    }
+
+    fn ntfy() -> Self {
+        Self {
+            tier1: 3,
+            tier2: 3,
+            tier3: 4,
+            other: 3,
+        }
+    }
+
+    fn gotify() -> Self {
+        Self {
+            tier1: 5,
+            tier2: 5,
+            tier3: 8,
+            other: 4,
+        }
+    }
+
+    fn of(&self, notification: &Notification) -> i8 {
+        let tier = |tier| match tier {
+            Tier::Tier1 => self.tier1,
+            Tier::Tier2 => self.tier2,
+            Tier::Tier3 => self.tier3,
+            Tier::Prime | Tier::Unknown => self.other,
+        };
+
+        match notification {
+            Notification::Gift { gift, .. } => tier(gift.tier),
+            Notification::Gifts { gifts, .. } => gifts
+                .iter()
+                .map(|gift| tier(gift.tier))
+                .max()
+                .unwrap_or(self.other),
+            _ => self.other,
+        }
+    }
+}
+
+fn default_ntfy_server() -> String {
+    "https://ntfy.sh".to_string()
+}
+
+fn default_timeout() -> u64 {
+    10
+}
+
+fn default_retries() -> u32 {
+    2
+}
+
+impl fmt::Display for Sink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            // the rest of the URL may contain a secret
+            Self::Webhook { url, .. } => match reqwest::Url::parse(url) {
+                Ok(url) => write!(f, "webhook to {}", url.host_str().unwrap_or("?")),
+                Err(_) => write!(f, "webhook to an invalid URL"),
+            },
+            Self::Slack { .. } => write!(f, "Slack"),
+            Self::Matrix { room, .. } => write!(f, "Matrix room {}", room),
+            Self::Pushover { .. } => write!(f, "Pushover"),
+            // the topic works like a password on ntfy.sh
+            Self::Ntfy { server, .. } => match reqwest::Url::parse(server) {
+                Ok(url) => write!(f, "ntfy on {}", url.host_str().unwrap_or("?")),
+                Err(_) => write!(f, "ntfy on an invalid URL"),
+            },
+            Self::Gotify { server, .. } => match reqwest::Url::parse(server) {
+                Ok(url) => write!(f, "Gotify on {}", url.host_str().unwrap_or("?")),
+                Err(_) => write!(f, "Gotify on an invalid URL"),
+            },
+        }
+    }
+}
+
+impl Sink {
+    fn timeout(&self) -> Duration {
+        match self {
+            Self::Webhook { timeout, .. }
+            | Self::Slack { timeout, .. }
+            | Self::Matrix { timeout, .. }
+            | Self::Pushover { timeout, .. }
+            | Self::Ntfy { timeout, .. }
+            | Self::Gotify { timeout, .. } => Duration::from_secs(*timeout),
+        }
+    }
+
+    fn retries(&self) -> u32 {
+        match self {
+            Self::Webhook { retries, .. }
+            | Self::Slack { retries, .. }
+            | Self::Matrix { retries, .. }
+            | Self::Pushover { retries, .. }
+            | Self::Ntfy { retries, .. }
+            | Self::Gotify { retries, .. } => *retries,
+        }
+    }
+
+    fn per_minute(&self) -> Option<u32> {
+        match self {
+            Self::Webhook { per_minute, .. }
+            | Self::Slack { per_minute, .. }
+            | Self::Matrix { per_minute, .. }
+            | Self::Pushover { per_minute, .. }
+            | Self::Ntfy { per_minute, .. }
+            | Self::Gotify { per_minute, .. } => per_minute.map(|limit| limit.max(1)),
+        }
+    }
+}
+
+/// The notifications sent to a sink during the last minute and the ones held back since
+#[derive(Debug)]
+struct Limiter {
+    /// The minute, shorter in tests
+    window: Duration,
+    sent: VecDeque<Instant>,
+    held_back: Vec<String>,
+    /// Sends the summary of `held_back` once the limit allows it
+    summary: Option<Task>,
+}
+
+impl Limiter {
+    fn new(window: Duration) -> Self {
+        Self {
+            window,
+            sent: VecDeque::new(),
+            held_back: Vec::new(),
+            summary: None,
+        }
+    }
+
+    /// Count a notification as sent if `per_minute` allows it. Nothing is sent while
+    /// notifications are held back, the summary of them goes first.
+    fn admit(&mut self, per_minute: u32) -> bool {
+        let now = Instant::now();
+        while self
+            .sent
+            .front()
+            .is_some_and(|sent| now.duration_since(*sent) >= self.window)
+        {
+            self.sent.pop_front();
+        }
+
+        if !self.held_back.is_empty() || self.sent.len() >= per_minute as usize {
+            return false;
+        }
+        self.sent.push_back(now);
+        true
+    }
+
+    /// Time until the oldest notification leaves the window
+    fn free_in(&self) -> Duration {
+        self.sent.front().map_or(Duration::ZERO, |sent| {
+            self.window.saturating_sub(sent.elapsed())
+        })
+    }
+
+    /// Take the titles of the held back notifications and count their summary as sent
+    fn release(&mut self) -> Vec<String> {
+        self.sent.push_back(Instant::now());
+        mem::take(&mut self.held_back)
+    }
+}
+
+/// How long the `per_minute` limits of the sinks count the notifications
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+/// Counts the notifications sent, the transaction ids of Matrix are made from it
+static TRANSACTION: AtomicU64 = AtomicU64::new(0);
+
+/// Time before the first retry of a failed notification, doubled for every further retry
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// Something that happened and should be pushed to the user
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Notification {
+    LoginFailed {
+        username: String,
+    },
+    Gift {
+        gift: Gift,
+        value: Value,
+        /// The gifter is one of the configured friends
+        friend: bool,
+    },
+    /// The gifts collected while notifications are batched
+    Gifts {
+        gifts: Vec<Gift>,
+        value: Value,
+    },
+    /// A gifted or prime sub of an account was converted to a paid sub
+    Upgrade(Gift),
+    /// A gift was paid forward in a channel
+    PayForward(Gift),
+    Milestone {
+        milestone: Milestone,
+        gift: Gift,
+    },
+    /// What happened during the last 24 hours
+    DailySummary(Summary),
+    /// An account is banned from a channel and won't join it again
+    Banned {
+        account: String,
+        channel: String,
+    },
+    /// Notifications a sink did not get because of its rate limit, by title
+    HeldBack {
+        titles: Vec<String>,
+    },
+}
+
+impl Notification {
+    pub fn title(&self) -> String {
+        match self {
+            Self::LoginFailed { .. } => "Login failed".to_string(),
+            Self::Gift { gift, friend, .. } if *friend => format!(
+                "{} gift from {} in {}",
+                gift.tier.as_str(),
+                gift.gifter,
+                gift.channel
+            ),
+            Self::Gift { gift, .. } => {
+                format!("{} gift in {}", gift.tier.as_str(), gift.channel)
+            }
+            Self::Gifts { gifts, .. } => format!("{} gifts", gifts.len()),
+            Self::Upgrade(gift) => format!("Upgrade in {}", gift.channel),
+            Self::PayForward(gift) => format!("Pay forward in {}", gift.channel),
+            Self::Milestone { milestone, .. } => format!("Milestone: {}", milestone),
+            Self::DailySummary(_) => "Daily summary".to_string(),
+            Self::Banned { channel, .. } => format!("Banned from {}", channel),
+            Self::HeldBack { titles } => format!("{} notifications held back", titles.len()),
+        }
+    }
+
+    /// The type of the notification like it is serialized
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::LoginFailed { .. } => "login_failed",
+            Self::Gift { .. } => "gift",
+            Self::Gifts { .. } => "gifts",
+            Self::Upgrade(_) => "upgrade",
+            Self::PayForward(_) => "pay_forward",
+            Self::Milestone { .. } => "milestone",
+            Self::DailySummary(_) => "daily_summary",
+            Self::Banned { .. } => "banned",
+            Self::HeldBack { .. } => "held_back",
+        }
+    }
+
+    /// An emoji shortcode that fits the notification and the tier of gifts
+    pub fn tags(&self) -> Vec<&'static str> {
+        match self {
+            Self::Gift { gift, .. } => vec!["gift", gift.tier.as_str()],
+            Self::Gifts { .. } => vec!["gift"],
+            Self::Upgrade(_) | Self::PayForward(_) => vec!["sparkles"],
+            Self::Milestone { .. } => vec!["tada"],
+            Self::DailySummary(_) => vec!["bar_chart"],
+            Self::LoginFailed { .. } | Self::Banned { .. } => vec!["warning"],
+            Self::HeldBack { .. } => vec!["hourglass"],
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            Self::Gift { gift, value, .. } => format!(
+                "{} received a {} month {} {} from {} in {} worth {}",
+                gift.account,
+                gift.months,
+                gift.tier.as_str(),
+                gift.kind.as_str(),
+                gift.gifter,
+                gift.channel,
+                value
+            ),
+            Self::Gifts { gifts, value } => {
+                let mut channels: Vec<(&str, usize)> = Vec::new();
+                for gift in gifts {
+                    match channels
+                        .iter_mut()
+                        .find(|(channel, _)| *channel == gift.channel)
+                    {
+                        Some((_, count)) => *count += 1,
+                        None => channels.push((&gift.channel, 1)),
+                    }
+                }
+                let channels: Vec<String> = channels
+                    .iter()
+                    .map(|(channel, count)| format!("{} in {}", count, channel))
+                    .collect();
+
+                format!(
+                    "{} gifts worth {}: {}",
+                    gifts.len(),
+                    value,
+                    channels.join(", ")
+                )
+            }
+            Self::Upgrade(gift) if gift.kind == GiftKind::PrimePaidUpgrade => format!(
+                "{} converted a prime sub to a {} sub in {}",
+                gift.account,
+                gift.tier.as_str(),
+                gift.channel
+            ),
+            Self::Upgrade(gift) => format!(
+                "{} continued the sub gifted by {} in {}",
+                gift.account, gift.gifter, gift.channel
+            ),
+            Self::PayForward(gift) => format!(
+                "{} paid the gift from {} forward to {} in {}",
+                gift.gifter,
+                gift.prior_gifter.as_deref().unwrap_or("unknown"),
+                match gift.kind {
+                    GiftKind::CommunityPayForward => "the community",
+                    _ => &gift.account,
+                },
+                gift.channel
+            ),
+            Self::Milestone { milestone, gift } => format!(
+                "{}! The latest gift was a {} from {} in {}",
+                milestone,
+                gift.tier.as_str(),
+                gift.gifter,
+                gift.channel
+            ),
+            Self::DailySummary(summary) => format!("Last 24 hours: {}", summary),
+            Self::Banned { account, channel } => format!(
+                "{} is banned from {} and won't join it again",
+                account, channel
+            ),
+            Self::LoginFailed { username } => format!(
+                "Twitch rejected the token for {}, run `auth` to update it",
+                username
+            ),
+            Self::HeldBack { titles } => format!(
+                "Held back to stay under the rate limit: {}",
+                titles.join(", ")
+            ),
+        }
+    }
+}
+
+pub struct Notifier {
+    client: Client,
+    sinks: Vec<Sink>,
+    /// The rate limit of each of `sinks`
+    limiters: Vec<Arc<Mutex<Limiter>>>,
+    dry_run: bool,
+}
+
+impl Notifier {
+    pub fn new(sinks: Vec<Sink>) -> Result<Self> {
+        let client = proxy::client_builder().user_agent(APP_USER_AGENT).build()?;
+
+        Ok(Self {
+            client,
+            limiters: sinks
+                .iter()
+                .map(|_| Arc::new(Mutex::new(Limiter::new(RATE_WINDOW))))
+                .collect(),
+            sinks,
+            dry_run: false,
+        })
+    }
+
+    /// Only log the notifications if `dry_run` is set
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Apply the rate limits of the sinks to `window` instead of a minute
+    pub fn rate_window(self, window: Duration) -> Self {
+        for limiter in &self.limiters {
+            limiter.lock().unwrap().window = window;
+        }
+        self
+    }
+
+    /// Send `notification` to all sinks at once. Failing sinks are retried, then logged and
+    /// skipped.
+    pub async fn notify(&self, notification: &Notification) {
+        if self.dry_run {
+            for sink in &self.sinks {
+                info!("Would notify {}: {}", sink, notification.message());
+            }
+            return;
+        }
+
+        join_all(
+            self.sinks
+                .iter()
+                .zip(&self.limiters)
+                .map(|(sink, limiter)| self.limit(sink, limiter, notification)),
+        )
+        .await;
+    }
+
+    /// Deliver `notification` to `sink` unless it is over its rate limit. The first
+    /// notification held back schedules the summary of all held back until the limit allows it.
+    async fn limit(&self, sink: &Sink, limiter: &Arc<Mutex<Limiter>>, notification: &Notification) {
+        let per_minute = match sink.per_minute() {
+            Some(per_minute) => per_minute,
+            None => return deliver(&self.client, sink, notification).await,
+        };
+
+        {
+            let mut locked = limiter.lock().unwrap();
+            if !locked.admit(per_minute) {
+                debug!("Holding back {:?} for {}", notification, sink);
+                locked.held_back.push(notification.title());
+                if locked.summary.is_none() {
+                    let wait = locked.free_in();
+                    let (client, sink, limiter) =
+                        (self.client.clone(), sink.clone(), limiter.clone());
+                    locked.summary = Some(runtime::spawn(async move {
+                        sleep(wait).await;
+                        let (titles, _this) = {
+                            let mut limiter = limiter.lock().unwrap();
+                            // dropping the task cancels it, so it is kept until it is done
+                            (limiter.release(), limiter.summary.take())
+                        };
+                        deliver(&client, &sink, &Notification::HeldBack { titles }).await;
+                    }));
+                }
+                return;
+            }
+        }
+
+        deliver(&self.client, sink, notification).await
+    }
+
+    /// Send the summaries of the held back notifications right away instead of waiting for the
+    /// rate limits, before shutting down
+    pub async fn flush(&self) {
+        join_all(
+            self.sinks
+                .iter()
+                .zip(&self.limiters)
+                .map(|(sink, limiter)| async move {
+                    let titles = {
+                        let mut limiter = limiter.lock().unwrap();
+                        if limiter.held_back.is_empty() {
+                            return;
+                        }
+                        // the scheduled summary is cancelled
+                        limiter.summary = None;
+                        limiter.release()
+                    };
+                    deliver(&self.client, sink, &Notification::HeldBack { titles }).await
+                }),
+        )
+        .await;
+    }
+}
+
+/// Send `notification` to `sink`, retrying with growing delays if it fails or times out
+async fn deliver(client: &Client, sink: &Sink, notification: &Notification) {
+    debug!("Sending {:?} to {}", notification, sink);
+
+    // the same for every attempt, so Matrix drops a retry of a message it already got
+    let transaction = TRANSACTION.fetch_add(1, Ordering::Relaxed);
+    let mut delay = RETRY_DELAY;
+    for attempt in 0..=sink.retries() {
+        let timeout = sink.timeout();
+        let result = send(client, sink, notification, transaction)
+            .or(async {
+                sleep(timeout).await;
+                Err(anyhow!("timed out after {} seconds", timeout.as_secs()))
+            })
+            .await;
+
+        match result {
+            Ok(()) => return,
+            Err(err) if attempt < sink.retries() => {
+                debug!("Could not send notification, retrying: {:#}", err);
+                sleep(delay).await;
+                delay *= 2;
+            }
+            Err(err) => warn!("Could not send notification: {:#}", err),
+        }
+    }
+}
+
+async fn send(
+    client: &Client,
+    sink: &Sink,
+    notification: &Notification,
+    transaction: u64,
+) -> Result<()> {
+    compat(async {
+        match sink {
+            Sink::Webhook { url, .. } => {
+                client
+                    .post(url)
+                    .json(&json!({
+                        "title": notification.title(),
+                        "message": notification.message(),
+                        "event": notification,
+                    }))
+                    .send()
+                    .await?
+                    .error_for_status()?;
+            }
+            Sink::Slack { url, .. } => {
+                client
+                    .post(url)
+                    .json(&slack_message(notification))
+                    .send()
+                    .await?
+                    .error_for_status()?;
+            }
+            Sink::Matrix {
+                homeserver,
+                token,
+                room,
+                ..
+            } => {
+                let mut url = reqwest::Url::parse(homeserver)?;
+                url.path_segments_mut()
+                    .map_err(|_| anyhow!("{} can't be a homeserver", homeserver))?
+                    .pop_if_empty()
+                    .extend(&["_matrix", "client", "v3", "rooms", room])
+                    .extend(&["send", "m.room.message"])
+                    // the id only has to be unique for the access token
+                    .push(&format!("tgf-{}-{}", process::id(), transaction));
+
+                client
+                    .put(url)
+                    .bearer_auth(token)
+                    .json(&json!({
+                        "msgtype": "m.text",
+                        "body": format!("{}\n{}", notification.title(), notification.message()),
+                        "format": "org.matrix.custom.html",
+                        "formatted_body": format!(
+                            "<strong>{}</strong><br>{}",
+                            html_escape(&notification.title()),
+                            html_escape(&notification.message())
+                        ),
+                    }))
+                    .send()
+                    .await?
+                    .error_for_status()?;
+            }
+            Sink::Pushover {
+                token,
+                user,
+                priorities,
+                ..
+            } => {
+                client
+                    .post(PUSHOVER_URL)
+                    .form(&[
+                        ("token", token.as_str()),
+                        ("user", user.as_str()),
+                        ("title", &notification.title()),
+                        ("message", &notification.message()),
+                        // 2 needs to be acknowledged, which the farm can't handle
+                        (
+                            "priority",
+                            &priorities.of(notification).clamp(-2, 1).to_string(),
+                        ),
+                    ])
+                    .send()
+                    .await?
+                    .error_for_status()?;
+            }
+            Sink::Ntfy {
+                server,
+                topic,
+                token,
+                priorities,
+                tags,
+                ..
+            } => {
+                let mut tags: Vec<&str> = tags.iter().map(String::as_str).collect();
+                tags.extend(notification.tags());
+                let mut request = client.post(server).json(&json!({
+                    "topic": topic,
+                    "title": notification.title(),
+                    "message": notification.message(),
+                    "priority": priorities.of(notification).clamp(1, 5),
+                    "tags": tags,
+                }));
+                if let Some(token) = token {
+                    request = request.bearer_auth(token);
+                }
+                request.send().await?.error_for_status()?;
+            }
+            Sink::Gotify {
+                server,
+                token,
+                priorities,
+                ..
+            } => {
+                client
+                    .post(&format!("{}/message", server.trim_end_matches('/')))
+                    .header("X-Gotify-Key", token)
+                    .json(&json!({
+                        "title": notification.title(),
+                        "message": notification.message(),
+                        "priority": priorities.of(notification).clamp(0, 10),
+                    }))
+                    .send()
+                    .await?
+                    .error_for_status()?;
+            }
+        }
+
+        Ok(())
+    })
+    .await
+}
+
+/// A Slack message with the title as header and the details of gifts as fields. `text` is shown
+/// where the blocks can't be, like in push notifications.
+fn slack_message(notification: &Notification) -> serde_json::Value {
+    let mut blocks = vec![
+        json!({
+            "type": "header",
+            "text": { "type": "plain_text", "text": notification.title() },
+        }),
+        json!({
+            "type": "section",
+            "text": { "type": "mrkdwn", "text": slack_escape(&notification.message()) },
+        }),
+    ];
+
+    if let Notification::Gift { gift, value, .. } = notification {
+        let fields: Vec<serde_json::Value> = [
+            ("Account", gift.account.clone()),
+            ("Channel", gift.channel.clone()),
+            ("Gifter", gift.gifter.clone()),
+            ("Tier", gift.tier.as_str().to_string()),
+            ("Months", gift.months.to_string()),
+            ("Value", value.to_string()),
+        ]
+        .iter()
+        .map(|(name, value)| json!({ "type": "mrkdwn", "text": format!("*{}*\n{}", name, slack_escape(value)) }))
+        .collect();
+        blocks.push(json!({ "type": "section", "fields": fields }));
+    }
+
+    json!({
+        "text": format!("{}: {}", notification.title(), notification.message()),
+        "blocks": blocks,
+    })
+}
+
+/// Escape the characters Slack reads as markup in `mrkdwn` text, the same as in HTML
+fn slack_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn html_escape(text: &str) -> String {
+    slack_escape(text).replace('"', "&quot;")
+}
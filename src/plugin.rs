@@ -0,0 +1,185 @@
+use crate::{
+    config::project_dirs,
+    farm::{EventHandler, GiftEvent},
+};
+use anyhow::{anyhow, Context, Result};
+use lazy_static::lazy_static;
+use libloading::{Library, Symbol};
+use log::{debug, error, info};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    env::consts::DLL_EXTENSION,
+    ffi::{c_char, CString},
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// Version of the interface between the farm and its plugins
+pub const ABI_VERSION: u32 = 1;
+
+type AbiVersion = unsafe extern "C" fn() -> u32;
+type Init = unsafe extern "C" fn(config: *const c_char) -> i32;
+type OnEvent = unsafe extern "C" fn(event: *const c_char);
+type Shutdown = unsafe extern "C" fn();
+
+/// What a plugin is told about, serialized to JSON
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Event<'a> {
+    Gift(&'a GiftEvent),
+    Join { account: &'a str, channel: &'a str },
+    Reconnect { account: &'a str },
+    Error { account: &'a str, error: String },
+}
+
+/// A shared library from the `plugins` directory of the config directory.
+///
+/// Plugins talk to the farm through a C interface and exchange JSON as null-terminated UTF-8
+/// strings that are only valid during the call:
+///
+/// - `uint32_t tgf_plugin_abi_version(void)` returns [`ABI_VERSION`]
+/// - `int32_t tgf_plugin_init(const char *config)` gets the `plugins` entry of the config
+///   named after the file of the plugin, `null` if there is none. Anything but `0` is an
+///   error.
+/// - `void tgf_plugin_on_event(const char *event)` gets every gift, join, reconnect and error,
+///   one call at a time
+/// - `void tgf_plugin_shutdown(void)` is optional and called before the plugin is unloaded
+pub struct Plugin {
+    name: String,
+    on_event: Mutex<OnEvent>,
+    shutdown: Option<Shutdown>,
+    // the function pointers are only valid while the library is loaded
+    _library: Library,
+}
+
+impl Plugin {
+    /// Load all plugins in the plugins directory
+    pub fn load_all(config: &HashMap<String, serde_json::Value>) -> Result<Vec<Self>> {
+        let dir = Self::dir();
+        if !dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut paths: Vec<PathBuf> = fs::read_dir(dir)
+            .context("Could not read the plugins directory")?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == DLL_EXTENSION))
+            .collect();
+        paths.sort();
+
+        let plugins = paths
+            .iter()
+            .map(|path| {
+                let name = path
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                let config = config.get(&name).unwrap_or(&serde_json::Value::Null);
+
+                Self::load(path, name, config)
+                    .with_context(|| format!("Could not load plugin {}", path.display()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        if !plugins.is_empty() {
+            info!("Loaded {} plugins", plugins.len());
+        }
+
+        Ok(plugins)
+    }
+
+    fn load(path: &Path, name: String, config: &serde_json::Value) -> Result<Self> {
+        // SAFETY: plugins are trusted like the rest of the config directory, loading one runs
+        // its initializers
+        let library = unsafe { Library::new(path)? };
+
+        // SAFETY: the symbols have the types the interface defines
+        unsafe {
+            let abi_version: Symbol<AbiVersion> = library.get(b"tgf_plugin_abi_version")?;
+            let version = abi_version();
+            if version != ABI_VERSION {
+                return Err(anyhow!(
+                    "Plugin is built for interface version {}, not {}",
+                    version,
+                    ABI_VERSION
+                ));
+            }
+
+            let init: Symbol<Init> = library.get(b"tgf_plugin_init")?;
+            let on_event: OnEvent = *library.get::<OnEvent>(b"tgf_plugin_on_event")?;
+            let shutdown: Option<Shutdown> = library
+                .get::<Shutdown>(b"tgf_plugin_shutdown")
+                .ok()
+                .map(|shutdown| *shutdown);
+
+            let config = CString::new(serde_json::to_string(config)?)?;
+            let status = init(config.as_ptr());
+            if status != 0 {
+                return Err(anyhow!("Plugin failed to start with status {}", status));
+            }
+
+            debug!("Loaded plugin {} from {}", name, path.display());
+
+            Ok(Self {
+                name,
+                on_event: Mutex::new(on_event),
+                shutdown,
+                _library: library,
+            })
+        }
+    }
+
+    fn send(&self, event: &Event<'_>) {
+        let json = match serde_json::to_string(event) {
+            Ok(json) => CString::new(json).expect("JSON escapes null bytes"),
+            Err(err) => {
+                error!("Could not send event to plugin {}: {}", self.name, err);
+                return;
+            }
+        };
+
+        let on_event = self.on_event.lock().unwrap();
+        // SAFETY: the library is still loaded and the string outlives the call
+        unsafe { on_event(json.as_ptr()) };
+    }
+
+    pub fn dir() -> &'static Path {
+        lazy_static! {
+            static ref PATH: PathBuf = project_dirs().config_dir().join("plugins");
+        }
+
+        PATH.as_ref()
+    }
+}
+
+impl EventHandler for Plugin {
+    fn on_gift(&self, event: &GiftEvent) {
+        self.send(&Event::Gift(event));
+    }
+
+    fn on_join(&self, account: &str, channel: &str) {
+        self.send(&Event::Join { account, channel });
+    }
+
+    fn on_reconnect(&self, account: &str) {
+        self.send(&Event::Reconnect { account });
+    }
+
+    fn on_error(&self, account: &str, error: &anyhow::Error) {
+        self.send(&Event::Error {
+            account,
+            error: format!("{:#}", error),
+        });
+    }
+}
+
+impl Drop for Plugin {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown {
+            // SAFETY: the library is unloaded only after this
+            unsafe { shutdown() };
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use crate::runtime::sleep;
+use anyhow::{bail, Context, Result};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use smol::{
+    channel::{self, Receiver, Sender, TrySendError},
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
+use std::time::Duration;
+
+/// Events waiting while Redis is not reachable, newer ones are dropped
+const QUEUE: usize = 256;
+/// Time before connecting again after the connection failed, doubled up to [`MAX_RETRY_DELAY`]
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(5 * 60);
+
+/// The Redis server every gift, upgrade and pay forward is published on as JSON
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Redis {
+    /// Address like `localhost:6379`, connected to without TLS
+    pub address: String,
+    /// For ACL users, only the password is sent if unset
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    /// The pub/sub channel
+    #[serde(default = "default_channel")]
+    pub channel: String,
+}
+
+fn default_channel() -> String {
+    "tgf:gifts".to_string()
+}
+
+/// Publishes to the channel of `config` while [`run`](Self::run) is running
+pub struct Publisher {
+    config: Redis,
+    events: Sender<String>,
+    queue: Receiver<String>,
+}
+
+impl Publisher {
+    pub fn new(config: Redis) -> Self {
+        let (events, queue) = channel::bounded(QUEUE);
+
+        Self {
+            config,
+            events,
+            queue,
+        }
+    }
+
+    /// Queue `event` to be published
+    pub fn publish(&self, event: &impl Serialize) {
+        let json = match serde_json::to_string(event) {
+            Ok(json) => json,
+            Err(err) => return warn!("Could not serialize the event for Redis: {}", err),
+        };
+        if let Err(TrySendError::Full(_)) = self.events.try_send(json) {
+            debug!("Not publishing to Redis, the queue is full");
+        }
+    }
+
+    /// Stop taking events and publish the queued ones, if any
+    pub async fn finish(&self) {
+        self.events.close();
+        if !self.queue.is_empty() {
+            self.run().await;
+        }
+    }
+
+    /// Keep connected to Redis and publish the queued events, connect again if the connection
+    /// fails
+    pub async fn run(&self) {
+        let mut delay = RETRY_DELAY;
+        loop {
+            match self.session(&mut delay).await {
+                Ok(()) => return,
+                Err(err) => warn!(
+                    "Redis connection to {} failed, retrying in {}s: {:#}",
+                    self.config.address,
+                    delay.as_secs(),
+                    err
+                ),
+            }
+            sleep(delay).await;
+            delay = (delay * 2).min(MAX_RETRY_DELAY);
+        }
+    }
+
+    /// Connect once and publish until the connection fails. `delay` is reset once connected.
+    async fn session(&self, delay: &mut Duration) -> Result<()> {
+        let stream = TcpStream::connect(self.config.address.as_str())
+            .await
+            .context("Could not connect")?;
+        let mut writer = stream.clone();
+        let mut reader = BufReader::new(stream);
+
+        if let Some(password) = &self.config.password {
+            let mut auth = vec!["AUTH"];
+            auth.extend(self.config.username.as_deref());
+            auth.push(password);
+            writer.write_all(&command(&auth)).await?;
+            reply(&mut reader).await.context("Could not authenticate")?;
+        }
+        info!("Connected to Redis at {}", self.config.address);
+        *delay = RETRY_DELAY;
+
+        while let Ok(event) = self.queue.recv().await {
+            writer
+                .write_all(&command(&["PUBLISH", &self.config.channel, &event]))
+                .await?;
+            reply(&mut reader).await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// `args` as an array of bulk strings
+fn command(args: &[&str]) -> Vec<u8> {
+    let mut command = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        command.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        command.extend_from_slice(arg.as_bytes());
+        command.extend_from_slice(b"\r\n");
+    }
+    command
+}
+
+/// Read a one line reply like `+OK` or `:1`, errors fail
+async fn reply(reader: &mut BufReader<TcpStream>) -> Result<String> {
+    let mut line = String::new();
+    if reader.read_line(&mut line).await? == 0 {
+        bail!("the connection was closed");
+    }
+
+    let line = line.trim_end();
+    match line.strip_prefix('-') {
+        Some(err) => bail!("{}", err),
+        None => Ok(line.to_string()),
+    }
+}
@@ -0,0 +1,534 @@
+use crate::config::{project_dirs, Config};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use log::debug;
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS channels (
+    name TEXT NOT NULL,
+    -- the account the channel belongs to, empty for the shared channels
+    account TEXT NOT NULL DEFAULT '',
+    source TEXT NOT NULL,
+    added_at TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'active',
+    changed_at TEXT,
+    reason TEXT,
+    gifts INTEGER NOT NULL DEFAULT 0,
+    last_gift TEXT,
+    PRIMARY KEY (name, account)
+);
+CREATE INDEX IF NOT EXISTS channels_by_status ON channels (status, account);
+";
+
+/// Changes to the schema, applied in order. `PRAGMA user_version` counts the applied ones.
+const MIGRATIONS: &[&str] = &[
+    "
+ALTER TABLE channels ADD COLUMN last_live TEXT;
+-- since when the Twitch account of the channel does not exist
+ALTER TABLE channels ADD COLUMN missing_since TEXT;
+",
+    "
+-- since when it is checked whether the channel is live, offline counts from here
+ALTER TABLE channels ADD COLUMN checked_since TEXT;
+UPDATE channels SET checked_since = last_live WHERE last_live IS NOT NULL;
+",
+    "
+-- counts the changes to which channels are farmed, unlike gifts or liveness checks
+CREATE TABLE revision (channels INTEGER NOT NULL);
+INSERT INTO revision VALUES (0);
+CREATE TRIGGER channels_inserted AFTER INSERT ON channels
+BEGIN UPDATE revision SET channels = channels + 1; END;
+CREATE TRIGGER channels_deleted AFTER DELETE ON channels
+BEGIN UPDATE revision SET channels = channels + 1; END;
+CREATE TRIGGER channels_updated AFTER UPDATE OF name, account, status ON channels
+BEGIN UPDATE revision SET channels = channels + 1; END;
+",
+];
+
+/// The empty account, used for the shared channels
+const SHARED: &str = "";
+
+/// How a channel got into the registry
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    /// Added with `channels add`
+    Manual,
+    /// Added by `discover`
+    Discovery,
+    /// Moved over from a config file that still contained channels
+    Config,
+    /// Twitch told us the account is banned from the channel
+    Ban,
+    /// A member of the Twitch team with this name
+    Team(String),
+    /// The account follows the channel
+    Follows,
+    /// Added by `scout` after it saw subs or gifts in the channel
+    Scout,
+    /// Listed in `TGF_CHANNELS_FILE` or at `TGF_CHANNELS_URL`
+    Environment,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Manual => write!(f, "manual"),
+            Self::Discovery => write!(f, "discovery"),
+            Self::Config => write!(f, "config"),
+            Self::Ban => write!(f, "ban"),
+            Self::Team(name) => write!(f, "team:{}", name),
+            Self::Follows => write!(f, "follows"),
+            Self::Scout => write!(f, "scout"),
+            Self::Environment => write!(f, "environment"),
+        }
+    }
+}
+
+/// A channel that was removed by pruning
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Pruned {
+    pub channel: String,
+    /// The account whose channels it was removed from, `None` for the shared channels
+    pub account: Option<String>,
+    pub time: DateTime<Utc>,
+    #[serde(default)]
+    pub reason: String,
+}
+
+/// All known channels, with where they came from and whether they are still joined.
+///
+/// Channels are either shared between all accounts or belong to a single account.
+pub struct Registry {
+    connection: Connection,
+}
+
+impl Registry {
+    pub fn open() -> Result<Self> {
+        let path = Self::path();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).context("Could not create data directory")?;
+        }
+
+        debug!("Opening channel registry at {}", path.display());
+
+        let connection = Connection::open(path).context("Could not open channel registry")?;
+        // the farm and the other commands may use the registry at the same time
+        connection.busy_timeout(Duration::from_secs(10))?;
+        connection
+            .execute_batch(SCHEMA)
+            .context("Could not create channel registry")?;
+
+        let version: i64 = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        for (index, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+            debug!("Migrating channel registry to version {}", index + 1);
+            connection
+                .execute_batch(&format!(
+                    "{}\nPRAGMA user_version = {};",
+                    migration,
+                    index + 1
+                ))
+                .context("Could not migrate channel registry")?;
+        }
+
+        Ok(Self { connection })
+    }
+
+    /// Active channels of the account named `username` or, if no name is given, the shared
+    /// channels
+    pub fn list(&self, username: Option<&str>) -> Result<Vec<String>> {
+        self.names(
+            "SELECT name FROM channels WHERE account = ?1 AND status = 'active' ORDER BY name",
+            username.unwrap_or(SHARED),
+        )
+    }
+
+    /// All channels of the account named `username` or the shared channels, including pruned
+    /// ones
+    pub fn known(&self, username: Option<&str>) -> Result<Vec<String>> {
+        self.names(
+            "SELECT name FROM channels WHERE account = ?1 ORDER BY name",
+            username.unwrap_or(SHARED),
+        )
+    }
+
+    /// Add `channels` to the account named `username` or the shared channels. Returns the number
+    /// of channels that were not known yet.
+    pub fn add<S: AsRef<str>>(
+        &mut self,
+        username: Option<&str>,
+        channels: &[S],
+        source: Source,
+    ) -> Result<usize> {
+        let now = Utc::now().to_rfc3339();
+        let source = source.to_string();
+        let transaction = self.connection.transaction()?;
+
+        let mut added = 0;
+        {
+            let mut insert = transaction.prepare(
+                "INSERT OR IGNORE INTO channels (name, account, source, added_at)
+                 VALUES (?1, ?2, ?3, ?4)",
+            )?;
+            for channel in channels {
+                added += insert.execute(params![
+                    channel.as_ref(),
+                    username.unwrap_or(SHARED),
+                    source,
+                    now
+                ])?;
+            }
+        }
+
+        transaction.commit()?;
+        Ok(added)
+    }
+
+    /// Forget `channels` of the account named `username` or the shared channels. Returns the
+    /// number of removed channels.
+    pub fn remove<S: AsRef<str>>(
+        &mut self,
+        username: Option<&str>,
+        channels: &[S],
+    ) -> Result<usize> {
+        let transaction = self.connection.transaction()?;
+
+        let mut removed = 0;
+        {
+            let mut delete = transaction.prepare(
+                "DELETE FROM channels WHERE name = ?1 AND account = ?2 AND status = 'active'",
+            )?;
+            for channel in channels {
+                removed += delete.execute(params![channel.as_ref(), username.unwrap_or(SHARED)])?;
+            }
+        }
+
+        transaction.commit()?;
+        Ok(removed)
+    }
+
+    /// All channels the account at `index` should join: its own channels and its slice of the
+    /// shared channels, without the channels it is banned from.
+    pub fn channels_for(&self, config: &Config, index: usize) -> Result<Vec<String>> {
+        let accounts = config.accounts.len();
+        let username = config.accounts[index].username.as_ref();
+        let banned = self.banned(username)?;
+
+        let mut channels = self.list(Some(username))?;
+        channels.extend(self.list(None)?.into_iter().filter(|channel| {
+            let first = slot(channel) % accounts;
+            (index + accounts - first) % accounts < config.replicas
+        }));
+
+        channels.retain(|channel| !banned.contains(channel));
+        channels.sort_unstable();
+        channels.dedup();
+        Ok(channels)
+    }
+
+    /// Channels the account named `username` is banned from
+    pub fn banned(&self, username: &str) -> Result<Vec<String>> {
+        self.names(
+            "SELECT name FROM channels WHERE account = ?1 AND status = 'banned' ORDER BY name",
+            username,
+        )
+    }
+
+    /// Never let the account named `username` join `channel` again. Channels that are the
+    /// account's own stay active, returns false for them.
+    pub fn ban(&self, username: &str, channel: &str) -> Result<bool> {
+        let now = Utc::now().to_rfc3339();
+        let changed = self.connection.execute(
+            "INSERT INTO channels (name, account, source, added_at, status, changed_at)
+             VALUES (?1, ?2, ?3, ?4, 'banned', ?4)
+             ON CONFLICT (name, account) DO UPDATE SET status = 'banned', changed_at = ?4
+             WHERE status != 'active'",
+            params![channel, username, Source::Ban.to_string(), now],
+        )?;
+
+        Ok(changed > 0)
+    }
+
+    /// Stop joining `channel` with any account
+    pub fn prune(&self, channel: &str, reason: &str) -> Result<Vec<Pruned>> {
+        let now = Utc::now();
+        let accounts = self.accounts_of(channel, "active")?;
+
+        self.connection.execute(
+            "UPDATE channels SET status = 'pruned', changed_at = ?2, reason = ?3
+             WHERE name = ?1 AND status = 'active'",
+            params![channel, now.to_rfc3339(), reason],
+        )?;
+
+        Ok(accounts
+            .into_iter()
+            .map(|account| Pruned {
+                channel: channel.to_string(),
+                account,
+                time: now,
+                reason: reason.to_string(),
+            })
+            .collect())
+    }
+
+    /// Move `channel` to the scout pool, where `scout --pool` watches it until it sees gifts again.
+    /// Returns the accounts the channel was active for.
+    pub fn demote(&self, channel: &str, reason: &str) -> Result<Vec<Option<String>>> {
+        let accounts = self.accounts_of(channel, "active")?;
+
+        self.connection.execute(
+            "UPDATE channels SET status = 'scouting', changed_at = ?2, reason = ?3
+             WHERE name = ?1 AND status = 'active'",
+            params![channel, Utc::now().to_rfc3339(), reason],
+        )?;
+
+        Ok(accounts)
+    }
+
+    /// Make a channel of the scout pool active again
+    pub fn promote(&self, channel: &str) -> Result<usize> {
+        Ok(self.connection.execute(
+            "UPDATE channels SET status = 'active', changed_at = ?2, reason = NULL
+             WHERE name = ?1 AND status = 'scouting'",
+            params![channel, Utc::now().to_rfc3339()],
+        )?)
+    }
+
+    /// Names of the channels in the scout pool
+    pub fn pool(&self) -> Result<Vec<String>> {
+        let mut statement = self.connection.prepare(
+            "SELECT DISTINCT name FROM channels WHERE status = 'scouting' ORDER BY name",
+        )?;
+        let rows = statement.query_map([], |row| row.get(0))?;
+
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Make a pruned channel active again
+    pub fn restore(&self, channel: &str) -> Result<Vec<Pruned>> {
+        let restored: Vec<Pruned> = self
+            .pruned()?
+            .into_iter()
+            .filter(|pruned| pruned.channel == channel)
+            .collect();
+
+        self.connection.execute(
+            "UPDATE channels SET status = 'active', changed_at = ?2, reason = NULL
+             WHERE name = ?1 AND status = 'pruned'",
+            params![channel, Utc::now().to_rfc3339()],
+        )?;
+
+        Ok(restored)
+    }
+
+    pub fn pruned(&self) -> Result<Vec<Pruned>> {
+        let mut statement = self.connection.prepare(
+            "SELECT name, account, changed_at, reason FROM channels
+             WHERE status = 'pruned' ORDER BY changed_at, name",
+        )?;
+
+        let rows = statement.query_map([], |row| {
+            let account: String = row.get(1)?;
+            let time: Option<String> = row.get(2)?;
+            let reason: Option<String> = row.get(3)?;
+
+            Ok(Pruned {
+                channel: row.get(0)?,
+                account: Some(account).filter(|account| account != SHARED),
+                time: time
+                    .and_then(|time| DateTime::parse_from_rfc3339(&time).ok())
+                    .map_or_else(Utc::now, |time| time.with_timezone(&Utc)),
+                reason: reason.unwrap_or_default(),
+            })
+        })?;
+
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Names of all active channels of all accounts
+    pub fn active(&self) -> Result<Vec<String>> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT DISTINCT name FROM channels WHERE status = 'active' ORDER BY name")?;
+        let rows = statement.query_map([], |row| row.get(0))?;
+
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Remember that `channels` are live right now
+    pub fn live<S: AsRef<str>>(&mut self, channels: &[S]) -> Result<()> {
+        let now = Utc::now().to_rfc3339();
+        let transaction = self.connection.transaction()?;
+        {
+            let mut update = transaction.prepare(
+                "UPDATE channels
+                 SET last_live = ?2, missing_since = NULL, checked_since = COALESCE(checked_since, ?2)
+                 WHERE name = ?1",
+            )?;
+            for channel in channels {
+                update.execute(params![channel.as_ref(), now])?;
+            }
+        }
+
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// Remember that it was checked whether `channels` are live, unless that is already known
+    pub fn checked<S: AsRef<str>>(&mut self, channels: &[S]) -> Result<()> {
+        let now = Utc::now().to_rfc3339();
+        let transaction = self.connection.transaction()?;
+        {
+            let mut update = transaction.prepare(
+                "UPDATE channels SET checked_since = ?2 WHERE name = ?1 AND checked_since IS NULL",
+            )?;
+            for channel in channels {
+                update.execute(params![channel.as_ref(), now])?;
+            }
+        }
+
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// Remember that the Twitch accounts of `channels` don't exist, unless that is already known
+    pub fn missing<S: AsRef<str>>(&mut self, channels: &[S]) -> Result<()> {
+        let now = Utc::now().to_rfc3339();
+        let transaction = self.connection.transaction()?;
+        {
+            let mut update = transaction.prepare(
+                "UPDATE channels SET missing_since = ?2 WHERE name = ?1 AND missing_since IS NULL",
+            )?;
+            for channel in channels {
+                update.execute(params![channel.as_ref(), now])?;
+            }
+        }
+
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// Active channels that were not live or did not exist for `days`, with the reason. Channels
+    /// that were never seen live count from when they were first checked, never from when they
+    /// were added.
+    pub fn stale(&self, days: i64) -> Result<Vec<(String, String)>> {
+        let cutoff = (Utc::now() - chrono::Duration::days(days)).to_rfc3339();
+        let mut statement = self.connection.prepare(
+            "SELECT DISTINCT name, missing_since IS NOT NULL AND missing_since < ?1 FROM channels
+             WHERE status = 'active'
+               AND ((missing_since IS NOT NULL AND missing_since < ?1)
+                    OR COALESCE(last_live, checked_since) < ?1)
+             ORDER BY name",
+        )?;
+
+        let rows = statement.query_map(params![cutoff], |row| {
+            let missing: bool = row.get(1)?;
+            let reason = if missing {
+                format!("deleted for {} days", days)
+            } else {
+                format!("offline for {} days", days)
+            };
+
+            Ok((row.get(0)?, reason))
+        })?;
+
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// A number that changes whenever a channel is added, removed, banned or restored
+    pub fn revision(&self) -> Result<i64> {
+        Ok(self
+            .connection
+            .query_row("SELECT channels FROM revision", [], |row| row.get(0))?)
+    }
+
+    /// Count a gift that landed in `channel`
+    pub fn gift(&self, channel: &str, time: DateTime<Utc>) -> Result<()> {
+        self.connection.execute(
+            "UPDATE channels SET gifts = gifts + 1, last_gift = ?2 WHERE name = ?1",
+            params![channel, time.to_rfc3339()],
+        )?;
+
+        Ok(())
+    }
+
+    /// Move the channels of a config file written before the registry existed
+    pub fn migrate(&mut self, config: &Config) -> Result<usize> {
+        let mut count = self.add(None, &config.channels, Source::Config)?;
+
+        for account in &config.accounts {
+            count += self.add(Some(&account.username), &account.channels, Source::Config)?;
+            for channel in &account.banned {
+                if self.ban(&account.username, channel)? {
+                    count += 1;
+                }
+            }
+        }
+
+        for pruned in &config.pruned {
+            self.add(
+                pruned.account.as_deref(),
+                &[&pruned.channel],
+                Source::Config,
+            )?;
+            self.connection.execute(
+                "UPDATE channels SET status = 'pruned', changed_at = ?3, reason = ?4
+                 WHERE name = ?1 AND account = ?2",
+                params![
+                    pruned.channel,
+                    pruned.account.as_deref().unwrap_or(SHARED),
+                    pruned.time.to_rfc3339(),
+                    pruned.reason
+                ],
+            )?;
+            count += 1;
+        }
+
+        Ok(count)
+    }
+
+    fn accounts_of(&self, channel: &str, status: &str) -> Result<Vec<Option<String>>> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT account FROM channels WHERE name = ?1 AND status = ?2")?;
+        let rows = statement.query_map(params![channel, status], |row| {
+            let account: String = row.get(0)?;
+            Ok(Some(account).filter(|account| account != SHARED))
+        })?;
+
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn names(&self, query: &str, account: &str) -> Result<Vec<String>> {
+        let mut statement = self.connection.prepare_cached(query)?;
+        let rows = statement.query_map(params![account], |row| row.get(0))?;
+
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    pub fn path() -> &'static Path {
+        lazy_static! {
+            static ref PATH: PathBuf = project_dirs().data_dir().join("channels.sqlite");
+        }
+
+        PATH.as_ref()
+    }
+}
+
+/// A stable hash of the channel name so the shared channels keep their account when channels are
+/// added or removed.
+fn slot(channel: &str) -> usize {
+    // FNV-1a
+    channel
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        }) as usize
+}
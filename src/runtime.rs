@@ -0,0 +1,92 @@
+// The async runtime is smol, or tokio with the `tokio` feature. Only the executor, blocking pool and
+// timers change, the sockets of the chat connections, sinks and the health endpoint are always
+// driven by the reactor of smol, which runs on a thread of its own. Channels, locks and future
+// combinators come from smol either way, they work on any runtime.
+
+use futures::future::{AbortHandle, Abortable};
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
+
+/// A spawned task that is cancelled when it is dropped
+#[derive(Debug)]
+pub struct Task(AbortHandle);
+
+impl Drop for Task {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Run `future` in the background until the returned [`Task`] is dropped
+pub fn spawn<F>(future: F) -> Task
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    let (handle, registration) = AbortHandle::new_pair();
+    let future = async move {
+        Abortable::new(future, registration).await.ok();
+    };
+
+    #[cfg(not(feature = "tokio"))]
+    smol::spawn(future).detach();
+    #[cfg(feature = "tokio")]
+    tokio::spawn(future);
+
+    Task(handle)
+}
+
+/// Run `future` to completion on the current thread
+#[cfg(not(feature = "tokio"))]
+pub fn block_on<T>(future: impl Future<Output = T>) -> T {
+    smol::block_on(future)
+}
+
+/// Run `future` to completion on a new runtime
+#[cfg(feature = "tokio")]
+pub fn block_on<T>(future: impl Future<Output = T>) -> T {
+    tokio::runtime::Builder::new()
+        .threaded_scheduler()
+        .enable_all()
+        .build()
+        .expect("Could not start the tokio runtime")
+        .block_on(future)
+}
+
+/// Run the blocking function `f` on a thread pool
+pub async fn unblock<T, F>(f: F) -> T
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    #[cfg(not(feature = "tokio"))]
+    return smol::unblock(f).await;
+    #[cfg(feature = "tokio")]
+    return tokio::task::spawn_blocking(f)
+        .await
+        .expect("blocking task panicked");
+}
+
+pub async fn sleep(duration: Duration) {
+    #[cfg(not(feature = "tokio"))]
+    smol::Timer::after(duration).await;
+    #[cfg(feature = "tokio")]
+    tokio::time::delay_for(duration).await;
+}
+
+pub async fn sleep_until(at: Instant) {
+    #[cfg(not(feature = "tokio"))]
+    smol::Timer::at(at).await;
+    #[cfg(feature = "tokio")]
+    tokio::time::delay_until(tokio::time::Instant::from_std(at)).await;
+}
+
+/// Run a future that needs tokio, like the requests of reqwest. Without the `tokio` feature it
+/// gets a tokio context of its own.
+pub async fn compat<T>(future: impl Future<Output = T>) -> T {
+    #[cfg(not(feature = "tokio"))]
+    return async_compat::Compat::new(future).await;
+    #[cfg(feature = "tokio")]
+    return future.await;
+}
@@ -0,0 +1,140 @@
+use crate::{config::project_dirs, farm::GiftEvent};
+use anyhow::{anyhow, Context, Result};
+use lazy_static::lazy_static;
+use log::{debug, error, info};
+use rhai::{Array, CallFnOptions, Dynamic, Engine, Map, Scope, AST};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// Operations a script may run per event, keeps endless loops from stalling the bot
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+/// Rhai scripts from the `scripts` directory of the config directory.
+///
+/// A script reacts to gifts by defining `fn on_gift(event)`. `event` has the fields `kind`,
+/// `channel`, `gifter`, `recipient`, `tier`, `months` and `tags`. If the function returns a
+/// string it is sent to the chat of the channel the gift was announced in.
+///
+/// Besides the standard library scripts can use `log(text)` and `run(program, args)`, which
+/// starts a program without waiting for it.
+pub struct Scripts {
+    engine: Engine,
+    scripts: Vec<(PathBuf, AST)>,
+}
+
+impl Scripts {
+    /// Compile all `.rhai` files in the scripts directory
+    pub fn load() -> Result<Self> {
+        let engine = engine();
+        let mut scripts = Vec::new();
+
+        let dir = Self::dir();
+        if dir.exists() {
+            let mut paths: Vec<PathBuf> = fs::read_dir(dir)
+                .context("Could not read the scripts directory")?
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.extension().is_some_and(|ext| ext == "rhai"))
+                .collect();
+            paths.sort();
+
+            for path in paths {
+                let ast = engine
+                    .compile_file(path.clone())
+                    .map_err(|err| anyhow!("{}", err))
+                    .with_context(|| format!("Could not compile {}", path.display()))?;
+                debug!("Loaded script {}", path.display());
+                scripts.push((path, ast));
+            }
+        }
+
+        if !scripts.is_empty() {
+            info!("Loaded {} scripts", scripts.len());
+        }
+
+        Ok(Self { engine, scripts })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scripts.is_empty()
+    }
+
+    /// Run `on_gift` of every script and collect the messages they want to send
+    pub fn on_gift(&self, event: &GiftEvent) -> Vec<String> {
+        let mut messages = Vec::new();
+        for (path, ast) in &self.scripts {
+            if !ast.iter_functions().any(|f| f.name == "on_gift") {
+                continue;
+            }
+
+            // only the function runs, not the statements at the top level of the script
+            let result = self.engine.call_fn_with_options::<Dynamic>(
+                CallFnOptions::new().eval_ast(false),
+                &mut Scope::new(),
+                ast,
+                "on_gift",
+                (event_map(event),),
+            );
+            match result {
+                Ok(reply) if reply.is_string() => messages.push(reply.to_string()),
+                Ok(_) => {}
+                Err(err) => error!("Error in {}: {}", path.display(), err),
+            }
+        }
+
+        messages
+    }
+
+    pub fn dir() -> &'static Path {
+        lazy_static! {
+            static ref PATH: PathBuf = project_dirs().config_dir().join("scripts");
+        }
+
+        PATH.as_ref()
+    }
+}
+
+fn engine() -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+
+    engine.on_print(|text| info!("[script] {}", text));
+    engine.on_debug(|text, source, pos| debug!("[script] {:?} {}: {}", source, pos, text));
+    engine.register_fn("log", |text: &str| info!("[script] {}", text));
+    engine.register_fn("run", |program: &str, args: Array| {
+        let args: Vec<String> = args.into_iter().map(|arg| arg.to_string()).collect();
+        match Command::new(program).args(&args).spawn() {
+            Ok(mut child) => {
+                // reap the child once it exits
+                std::thread::spawn(move || child.wait());
+            }
+            Err(err) => error!("[script] Could not run {}: {}", program, err),
+        }
+    });
+
+    engine
+}
+
+fn event_map(event: &GiftEvent) -> Map {
+    let mut map = Map::new();
+    map.insert("kind".into(), event.kind.as_str().into());
+    map.insert("channel".into(), event.channel.clone().into());
+    map.insert("gifter".into(), event.gifter.clone().into());
+    map.insert(
+        "recipient".into(),
+        event.recipient.clone().map_or(Dynamic::UNIT, Dynamic::from),
+    );
+    map.insert("tier".into(), event.tier.as_str().into());
+    map.insert("months".into(), (event.months as i64).into());
+
+    let tags: Map = event
+        .tags
+        .iter()
+        .map(|(key, value)| (key.as_str().into(), value.clone().into()))
+        .collect();
+    map.insert("tags".into(), tags.into());
+
+    map
+}
@@ -0,0 +1,273 @@
+use crate::{config::project_dirs, history::Gift};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use lazy_static::lazy_static;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::{
+    borrow::Borrow,
+    collections::{BTreeMap, HashMap},
+    fs,
+    hash::Hash,
+    path::{Path, PathBuf},
+};
+
+/// Number of days channels are scored over
+pub const WINDOW_DAYS: i64 = 30;
+
+/// Channels with a gift in this many days are joined first
+const RECENT_GIFT_DAYS: i64 = 7;
+
+/// What is known about the channels beyond the config, kept in the data directory
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct ChannelState {
+    pub channels: BTreeMap<String, ChannelStats>,
+    /// When the subs of the accounts are expected to end, by account and channel
+    #[serde(default)]
+    pub subscriptions: BTreeMap<String, BTreeMap<String, DateTime<Utc>>>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct ChannelStats {
+    pub first_joined: Option<DateTime<Utc>>,
+    /// Seconds the channel was joined on each day of the window
+    #[serde(default)]
+    pub joined: BTreeMap<NaiveDate, u64>,
+    /// Seconds the channel was joined since the last gift event in it
+    #[serde(default)]
+    pub since_gift: u64,
+    /// Chat messages seen on each day of the window
+    #[serde(default)]
+    pub messages: BTreeMap<NaiveDate, u64>,
+    /// Seconds the channel was joined since the last chat message in it
+    #[serde(default)]
+    pub since_message: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_gift: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_message: Option<DateTime<Utc>>,
+}
+
+impl ChannelStats {
+    /// Days the channel was joined during the window
+    pub fn days_joined(&self) -> f64 {
+        self.joined.values().sum::<u64>() as f64 / 86400.0
+    }
+}
+
+/// How well a channel did during the window
+#[derive(Debug, Clone)]
+pub struct Score {
+    pub channel: String,
+    pub days_joined: f64,
+    pub gifts: usize,
+    /// Gifts per day joined
+    pub score: f64,
+    pub last_gift: Option<DateTime<Utc>>,
+    /// Chat messages per day joined
+    pub messages: f64,
+}
+
+impl ChannelState {
+    /// Read the state, a missing file is an empty state
+    pub fn load() -> Result<Self> {
+        let path = Self::path();
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        debug!("Loading channel state from {}", path.display());
+
+        let data = fs::read(path).context("Could not read channel state")?;
+        serde_json::from_slice(&data).context("Invalid channel state")
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = Self::path();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).context("Could not create data directory")?;
+        }
+
+        // write to a temporary file first so a crash can't leave a truncated state behind
+        let temporary = path.with_extension("json.tmp");
+        fs::write(&temporary, serde_json::to_vec(self)?)
+            .context("Could not write channel state")?;
+        fs::rename(&temporary, path).context("Could not write channel state")
+    }
+
+    /// Count `elapsed` as time joined today for `channels` and forget days outside the window.
+    /// `messages` are the number of chat messages per channel seen during that time.
+    pub fn track<'a, K>(
+        &mut self,
+        channels: impl IntoIterator<Item = &'a str>,
+        elapsed: Duration,
+        messages: &HashMap<K, u64>,
+    ) where
+        K: Borrow<str> + Eq + Hash,
+    {
+        let now = Utc::now();
+        let today = now.date_naive();
+        let oldest = today - Duration::days(WINDOW_DAYS);
+
+        for channel in channels {
+            let stats = self.channels.entry(channel.to_string()).or_default();
+            stats.first_joined.get_or_insert(now);
+            let elapsed = elapsed.num_seconds().max(0) as u64;
+            *stats.joined.entry(today).or_insert(0) += elapsed;
+            stats.since_gift += elapsed;
+
+            match messages.get(channel).copied().unwrap_or(0) {
+                0 => stats.since_message += elapsed,
+                count => {
+                    *stats.messages.entry(today).or_insert(0) += count;
+                    stats.since_message = 0;
+                    stats.last_message = Some(now);
+                }
+            }
+        }
+
+        for subscriptions in self.subscriptions.values_mut() {
+            subscriptions.retain(|_, until| *until > now);
+        }
+        self.subscriptions
+            .retain(|_, subscriptions| !subscriptions.is_empty());
+
+        for stats in self.channels.values_mut() {
+            stats.joined.retain(|day, _| *day > oldest);
+            stats.messages.retain(|day, _| *day > oldest);
+        }
+    }
+
+    /// Note that gifts are happening in `channel`
+    pub fn gift_event(&mut self, channel: &str) {
+        let stats = self
+            .channels
+            .entry(channel.trim_start_matches('#').to_string())
+            .or_default();
+        stats.since_gift = 0;
+        stats.last_gift = Some(Utc::now());
+    }
+
+    /// When the sub of `account` to `channel` is expected to end, if it did not end yet
+    pub fn subscribed_until(&self, account: &str, channel: &str) -> Option<DateTime<Utc>> {
+        self.subscriptions
+            .get(account)?
+            .get(channel)
+            .copied()
+            .filter(|until| *until > Utc::now())
+    }
+
+    /// Note that `account` is subscribed to `channel` until at least `until`
+    pub fn subscribed(&mut self, account: &str, channel: &str, until: DateTime<Utc>) {
+        let current = self
+            .subscriptions
+            .entry(account.to_string())
+            .or_default()
+            .entry(channel.to_string())
+            .or_insert(until);
+        *current = (*current).max(until);
+    }
+
+    /// Start counting the time without gift events and chat messages of `channel` again
+    pub fn reset(&mut self, channel: &str) {
+        if let Some(stats) = self.channels.get_mut(channel) {
+            stats.since_gift = 0;
+            stats.since_message = 0;
+        }
+    }
+
+    /// Channels that were joined for at least `days` without a gift event
+    pub fn giftless(&self, days: f64) -> Vec<&str> {
+        self.channels
+            .iter()
+            .filter(|(_, stats)| stats.since_gift as f64 >= days * 86400.0)
+            .map(|(channel, _)| channel.as_str())
+            .collect()
+    }
+
+    /// Channels that were joined for at least `days` without a chat message
+    pub fn silent(&self, days: f64) -> Vec<&str> {
+        self.channels
+            .iter()
+            .filter(|(_, stats)| stats.since_message as f64 >= days * 86400.0)
+            .map(|(channel, _)| channel.as_str())
+            .collect()
+    }
+
+    /// Scores of all channels that were joined during the window, best first
+    pub fn scores(&self, history: &[Gift]) -> Vec<Score> {
+        let since = Utc::now() - Duration::days(WINDOW_DAYS);
+
+        let mut gifts = HashMap::new();
+        for gift in history
+            .iter()
+            .filter(|gift| gift.time >= since && gift.kind.is_gift())
+        {
+            *gifts.entry(gift.channel.as_str()).or_insert(0) += 1;
+        }
+
+        let mut scores: Vec<Score> = self
+            .channels
+            .iter()
+            .filter(|(_, stats)| !stats.joined.is_empty())
+            .map(|(channel, stats)| {
+                let days_joined = stats.days_joined();
+                let gifts = gifts.get(channel.as_str()).copied().unwrap_or(0);
+
+                Score {
+                    channel: channel.clone(),
+                    days_joined,
+                    gifts,
+                    // count at least an hour so channels joined for a moment don't top the list
+                    score: gifts as f64 / days_joined.max(1.0 / 24.0),
+                    last_gift: stats.last_gift,
+                    messages: stats.messages.values().sum::<u64>() as f64
+                        / days_joined.max(1.0 / 24.0),
+                }
+            })
+            .collect();
+
+        scores.sort_by(|a, b| {
+            b.score
+                .total_cmp(&a.score)
+                .then_with(|| b.days_joined.total_cmp(&a.days_joined))
+                .then_with(|| a.channel.cmp(&b.channel))
+        });
+
+        scores
+    }
+
+    /// Sort `channels` so channels with recent gifts come first, followed by the channels with
+    /// the best scores. Channels without either keep their order.
+    pub fn prioritize(&self, channels: &mut [impl AsRef<str>], history: &[Gift]) {
+        let recent = Utc::now() - Duration::days(RECENT_GIFT_DAYS);
+        let scores: HashMap<String, f64> = self
+            .scores(history)
+            .into_iter()
+            .map(|score| (score.channel, score.score))
+            .collect();
+
+        let last_gift = |channel: &str| {
+            self.channels
+                .get(channel)
+                .and_then(|stats| stats.last_gift)
+                .filter(|time| *time >= recent)
+        };
+        let score = |channel: &str| scores.get(channel).copied().unwrap_or(0.0);
+
+        channels.sort_by(|a, b| {
+            let (a, b) = (a.as_ref(), b.as_ref());
+            last_gift(b)
+                .cmp(&last_gift(a))
+                .then_with(|| score(b).total_cmp(&score(a)))
+        });
+    }
+
+    pub fn path() -> &'static Path {
+        lazy_static! {
+            static ref PATH: PathBuf = project_dirs().data_dir().join("channels.json");
+        }
+
+        PATH.as_ref()
+    }
+}
@@ -0,0 +1,340 @@
+use crate::{
+    farm::{EventHandler, SessionStats},
+    runtime::sleep,
+};
+use anyhow::{bail, Context, Result};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use smol::{
+    channel::{self, Receiver, Sender, TrySendError},
+    future::FutureExt,
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    lock::Mutex,
+    net::TcpStream,
+};
+use std::{fmt, time::Duration};
+
+/// Events waiting while the server is not reachable, newer ones are dropped
+const QUEUE: usize = 1024;
+/// Time before connecting again after the connection failed, doubled up to [`MAX_RETRY_DELAY`]
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(5 * 60);
+
+/// Where all events of the farm are streamed to for processing elsewhere
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Stream {
+    pub target: Target,
+    #[serde(default)]
+    pub format: Format,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub enum Target {
+    /// Publish every event to `<subject>.<type>`, like `tgf.events.gift`. Add the subjects to a
+    /// JetStream stream to keep the events.
+    Nats {
+        /// Address like `localhost:4222`, connected to without TLS
+        server: String,
+        #[serde(default = "default_subject")]
+        subject: String,
+        #[serde(default)]
+        token: Option<String>,
+        #[serde(default)]
+        user: Option<String>,
+        #[serde(default)]
+        password: Option<String>,
+    },
+    /// Produce every event to a Kafka topic with the type as key
+    #[cfg(feature = "kafka")]
+    Kafka {
+        /// Addresses like `localhost:9092`
+        brokers: Vec<String>,
+        #[serde(default = "default_topic")]
+        topic: String,
+    },
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Nats {
+                server, subject, ..
+            } => write!(f, "NATS {}.* on {}", subject, server),
+            #[cfg(feature = "kafka")]
+            Self::Kafka { brokers, topic } => {
+                write!(f, "Kafka topic {} on {}", topic, brokers.join(", "))
+            }
+        }
+    }
+}
+
+fn default_subject() -> String {
+    "tgf.events".to_string()
+}
+
+#[cfg(feature = "kafka")]
+fn default_topic() -> String {
+    "tgf-events".to_string()
+}
+
+/// How the events are serialized
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize)]
+pub enum Format {
+    #[default]
+    Json,
+    MessagePack,
+}
+
+struct Event {
+    kind: &'static str,
+    payload: Vec<u8>,
+}
+
+/// What the bots do, streamed next to the notifications
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum FarmEvent<'a> {
+    Join { account: &'a str, channel: &'a str },
+    Part { account: &'a str, channel: &'a str },
+    Reconnect { account: &'a str },
+    Error { account: &'a str, error: String },
+    Latency { account: &'a str, latency_ms: u64 },
+    Stats(&'a SessionStats),
+}
+
+impl FarmEvent<'_> {
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::Join { .. } => "join",
+            Self::Part { .. } => "part",
+            Self::Reconnect { .. } => "reconnect",
+            Self::Error { .. } => "error",
+            Self::Latency { .. } => "latency",
+            Self::Stats(_) => "stats",
+        }
+    }
+}
+
+/// Streams the events to the target of `config` while [`run`](Self::run) is running
+pub struct Producer {
+    config: Stream,
+    events: Sender<Event>,
+    queue: Receiver<Event>,
+}
+
+impl Producer {
+    pub fn new(config: Stream) -> Self {
+        let (events, queue) = channel::bounded(QUEUE);
+
+        Self {
+            config,
+            events,
+            queue,
+        }
+    }
+
+    /// Queue `event` of the type `kind` to be streamed
+    pub fn publish(&self, kind: &'static str, event: &impl Serialize) {
+        let payload = match self.config.format {
+            Format::Json => serde_json::to_vec(event).map_err(anyhow::Error::from),
+            Format::MessagePack => rmp_serde::to_vec_named(event).map_err(anyhow::Error::from),
+        };
+        let payload = match payload {
+            Ok(payload) => payload,
+            Err(err) => return warn!("Could not serialize the {} event: {}", kind, err),
+        };
+
+        if let Err(TrySendError::Full(event)) = self.events.try_send(Event { kind, payload }) {
+            debug!("Not streaming the {} event, the queue is full", event.kind);
+        }
+    }
+
+    fn publish_event(&self, event: FarmEvent) {
+        self.publish(event.kind(), &event);
+    }
+
+    /// Stop taking events and stream the queued ones, if any
+    pub async fn finish(&self) {
+        self.events.close();
+        if !self.queue.is_empty() {
+            self.run().await;
+        }
+    }
+
+    /// Keep connected and stream the queued events, connect again if the connection fails
+    pub async fn run(&self) {
+        let mut delay = RETRY_DELAY;
+        loop {
+            let result = match &self.config.target {
+                Target::Nats { .. } => self.nats(&mut delay).await,
+                #[cfg(feature = "kafka")]
+                Target::Kafka { .. } => self.kafka(&mut delay).await,
+            };
+            match result {
+                Ok(()) => return,
+                Err(err) => warn!(
+                    "Streaming events failed, retrying in {}s: {:#}",
+                    delay.as_secs(),
+                    err
+                ),
+            }
+            sleep(delay).await;
+            delay = (delay * 2).min(MAX_RETRY_DELAY);
+        }
+    }
+
+    /// Publish to NATS until the connection fails. `delay` is reset once connected.
+    async fn nats(&self, delay: &mut Duration) -> Result<()> {
+        let (server, subject, token, user, password) = match &self.config.target {
+            Target::Nats {
+                server,
+                subject,
+                token,
+                user,
+                password,
+            } => (server, subject, token, user, password),
+            #[cfg(feature = "kafka")]
+            _ => unreachable!(),
+        };
+
+        let stream = TcpStream::connect(server.as_str())
+            .await
+            .context("Could not connect")?;
+        let writer = Mutex::new(stream.clone());
+        let mut reader = BufReader::new(stream);
+
+        let line = read_line(&mut reader).await?;
+        if !line.starts_with("INFO ") {
+            bail!("expected INFO, got {}", line);
+        }
+        let connect = serde_json::json!({
+            "verbose": false,
+            "pedantic": false,
+            "name": env!("CARGO_PKG_NAME"),
+            "lang": "rust",
+            "version": env!("CARGO_PKG_VERSION"),
+            "auth_token": token,
+            "user": user,
+            "pass": password,
+        });
+        // the PONG confirms the CONNECT was accepted
+        writer
+            .lock()
+            .await
+            .write_all(format!("CONNECT {}\r\nPING\r\n", connect).as_bytes())
+            .await?;
+        loop {
+            match read_line(&mut reader).await?.as_str() {
+                "PONG" => break,
+                line if line.starts_with("-ERR") => bail!("{}", line),
+                _ => {}
+            }
+        }
+        info!("Connected to NATS at {}", server);
+        *delay = RETRY_DELAY;
+
+        let publishing = async {
+            while let Ok(event) = self.queue.recv().await {
+                let mut message =
+                    format!("PUB {}.{} {}\r\n", subject, event.kind, event.payload.len())
+                        .into_bytes();
+                message.extend(event.payload);
+                message.extend_from_slice(b"\r\n");
+                writer.lock().await.write_all(&message).await?;
+            }
+            Ok(())
+        };
+        // the server pings to check the connection and sends errors
+        let reading = async {
+            loop {
+                match read_line(&mut reader).await?.as_str() {
+                    "PING" => writer.lock().await.write_all(b"PONG\r\n").await?,
+                    line if line.starts_with("-ERR") => warn!("NATS: {}", line),
+                    _ => {}
+                }
+            }
+        };
+
+        publishing.or(reading).await
+    }
+
+    /// Produce to Kafka until producing fails. `delay` is reset once connected.
+    #[cfg(feature = "kafka")]
+    async fn kafka(&self, delay: &mut Duration) -> Result<()> {
+        use crate::runtime::unblock;
+        use kafka::producer::{Producer, Record, RequiredAcks};
+
+        let (brokers, topic) = match &self.config.target {
+            Target::Kafka { brokers, topic } => (brokers.clone(), topic.clone()),
+            _ => unreachable!(),
+        };
+
+        let mut producer = unblock(move || {
+            Producer::from_hosts(brokers)
+                .with_required_acks(RequiredAcks::One)
+                .create()
+        })
+        .await
+        .context("Could not connect")?;
+        info!("Connected to Kafka, producing to {}", topic);
+        *delay = RETRY_DELAY;
+
+        while let Ok(event) = self.queue.recv().await {
+            let topic = topic.clone();
+            let result;
+            (producer, result) = unblock(move || {
+                let result = producer.send(&Record::from_key_value(
+                    &topic,
+                    event.kind,
+                    event.payload.as_slice(),
+                ));
+                (producer, result)
+            })
+            .await;
+            result?;
+        }
+
+        Ok(())
+    }
+}
+
+async fn read_line(reader: &mut BufReader<TcpStream>) -> Result<String> {
+    let mut line = String::new();
+    if reader.read_line(&mut line).await? == 0 {
+        bail!("the connection was closed");
+    }
+    Ok(line.trim_end().to_string())
+}
+
+// gifts are streamed as notifications once they are stored, with their value
+impl EventHandler for Producer {
+    fn on_join(&self, account: &str, channel: &str) {
+        self.publish_event(FarmEvent::Join { account, channel });
+    }
+
+    fn on_part(&self, account: &str, channel: &str) {
+        self.publish_event(FarmEvent::Part { account, channel });
+    }
+
+    fn on_reconnect(&self, account: &str) {
+        self.publish_event(FarmEvent::Reconnect { account });
+    }
+
+    fn on_error(&self, account: &str, error: &anyhow::Error) {
+        self.publish_event(FarmEvent::Error {
+            account,
+            error: format!("{:#}", error),
+        });
+    }
+
+    fn on_latency(&self, account: &str, latency: Duration) {
+        self.publish_event(FarmEvent::Latency {
+            account,
+            latency_ms: latency.as_millis() as u64,
+        });
+    }
+
+    fn on_stats(&self, stats: &SessionStats) {
+        self.publish_event(FarmEvent::Stats(stats));
+    }
+}
@@ -0,0 +1,88 @@
+use crate::{
+    history::{Gift, Tier},
+    value::{Prices, Value},
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use std::{collections::BTreeMap, fmt};
+
+/// What happened during a period of farming
+#[derive(Debug, Clone, Serialize)]
+pub struct Summary {
+    pub since: DateTime<Utc>,
+    pub gifts: usize,
+    pub by_tier: BTreeMap<Tier, usize>,
+    pub value: Value,
+    /// Channels with the most gifts, best first
+    pub top_channels: Vec<(String, usize)>,
+    pub new_channels: usize,
+    pub reconnects: u64,
+}
+
+impl Summary {
+    /// Summarize the gifts of the last `period` and the given counters
+    pub fn new(
+        history: &[Gift],
+        prices: &Prices,
+        period: Duration,
+        new_channels: usize,
+        reconnects: u64,
+    ) -> Self {
+        let since = Utc::now() - period;
+        let gifts: Vec<&Gift> = history
+            .iter()
+            .filter(|gift| gift.time >= since && gift.kind.is_gift())
+            .collect();
+
+        let mut by_tier = BTreeMap::new();
+        let mut by_channel = BTreeMap::new();
+        for gift in &gifts {
+            *by_tier.entry(gift.tier).or_insert(0) += 1;
+            *by_channel.entry(gift.channel.clone()).or_insert(0) += 1;
+        }
+
+        let mut top_channels: Vec<_> = by_channel.into_iter().collect();
+        top_channels.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        top_channels.truncate(5);
+
+        Self {
+            since,
+            gifts: gifts.len(),
+            by_tier,
+            value: prices.total(gifts.iter().copied()),
+            top_channels,
+            new_channels,
+            reconnects,
+        }
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} gifts worth {}", self.gifts, self.value)?;
+
+        if !self.by_tier.is_empty() {
+            let tiers: Vec<String> = self
+                .by_tier
+                .iter()
+                .map(|(tier, count)| format!("{} {}", count, tier.as_str()))
+                .collect();
+            write!(f, " ({})", tiers.join(", "))?;
+        }
+
+        if !self.top_channels.is_empty() {
+            let channels: Vec<String> = self
+                .top_channels
+                .iter()
+                .map(|(channel, count)| format!("{} ({})", channel, count))
+                .collect();
+            write!(f, ", top channels: {}", channels.join(", "))?;
+        }
+
+        write!(
+            f,
+            ", {} new channels joined, {} reconnects",
+            self.new_channels, self.reconnects
+        )
+    }
+}
@@ -0,0 +1,28 @@
+/// Replace `{name}` placeholders in `template` with their values.
+///
+/// Unknown placeholders are left as they are.
+pub fn render(template: &str, vars: &[(&str, &str)]) -> String {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let end = match rest.find('}') {
+            Some(end) => end,
+            None => break,
+        };
+
+        let name = &rest[1..end];
+        match vars.iter().find(|(key, _)| *key == name) {
+            Some((_, value)) => output.push_str(value),
+            None => output.push_str(&rest[..=end]),
+        }
+
+        rest = &rest[end + 1..];
+    }
+
+    output.push_str(rest);
+    output
+}
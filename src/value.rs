@@ -0,0 +1,99 @@
+use crate::history::{Gift, Tier};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Prices of a sub in one currency
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Prices {
+    pub currency: String,
+    pub tier1: f64,
+    pub tier2: f64,
+    pub tier3: f64,
+}
+
+/// Which prices to use to estimate the value of gifts
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub enum PriceTable {
+    /// Approximate prices of a region, one of `us`, `eu` or `uk`
+    Region(String),
+    Custom(Prices),
+}
+
+impl Default for PriceTable {
+    fn default() -> Self {
+        Self::Region("us".to_string())
+    }
+}
+
+impl PriceTable {
+    pub fn prices(&self) -> Option<Prices> {
+        let (currency, tier1, tier2, tier3) = match self {
+            Self::Custom(prices) => return Some(prices.clone()),
+            Self::Region(region) => match region.to_lowercase().as_str() {
+                "us" => ("USD", 4.99, 9.99, 24.99),
+                "eu" => ("EUR", 4.99, 9.99, 24.99),
+                "uk" => ("GBP", 3.99, 7.99, 19.99),
+                _ => return None,
+            },
+        };
+
+        Some(Prices {
+            currency: currency.to_string(),
+            tier1,
+            tier2,
+            tier3,
+        })
+    }
+}
+
+/// An amount of money
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Value {
+    pub amount: f64,
+    pub currency: String,
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:.2} {}", self.amount, self.currency)
+    }
+}
+
+impl Prices {
+    pub fn price(&self, tier: Tier) -> f64 {
+        match tier {
+            Tier::Tier1 => self.tier1,
+            Tier::Tier2 => self.tier2,
+            Tier::Tier3 => self.tier3,
+            // gifts can't be prime subs
+            Tier::Prime | Tier::Unknown => 0.0,
+        }
+    }
+
+    /// What the account would have paid for `gift`. Only gifts have a value.
+    pub fn value(&self, gift: &Gift) -> Value {
+        let amount = if gift.kind.is_gift() {
+            self.price(gift.tier) * gift.months as f64
+        } else {
+            0.0
+        };
+
+        self.amount(amount)
+    }
+
+    /// Sum of the value of all `gifts`
+    pub fn total<'a>(&self, gifts: impl IntoIterator<Item = &'a Gift>) -> Value {
+        self.amount(
+            gifts
+                .into_iter()
+                .fold(0.0, |total, gift| total + self.value(gift).amount),
+        )
+    }
+
+    pub fn amount(&self, amount: f64) -> Value {
+        Value {
+            amount,
+            currency: self.currency.clone(),
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use std::{env, fs, process};
+use twitch_gift_farm::{config::project_dirs, registry::Registry, Config};
+
+// the environment is shared by the whole process, so everything is checked in one test
+#[test]
+fn runs_from_the_environment() {
+    let dir = env::temp_dir().join(format!("tgf-env-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let channels = dir.join("channels.txt");
+    fs::write(&channels, "#First\nsecond\n\n  third  \n").unwrap();
+
+    env::remove_var("HOME");
+    env::set_var("TGF_DIR", &dir);
+    env::set_var("TGF_USERNAME", "Container");
+    env::set_var("TGF_TOKEN", "oauth:0123456789abcdefghijklmnopqrst");
+    env::set_var("TGF_CHANNELS_FILE", &channels);
+
+    assert_eq!(project_dirs().data_dir(), dir.join("data"));
+
+    let config = Config::load().unwrap();
+    assert_eq!(config.accounts.len(), 1);
+    assert_eq!(config.accounts[0].username, "container");
+    assert!(!Config::path().exists());
+
+    let mut listed = Registry::open().unwrap().list(Some("container")).unwrap();
+    listed.sort();
+    assert_eq!(listed, ["first", "second", "third"]);
+
+    // the channels are only read once per process, loading again does not add them twice
+    fs::write(&channels, "fourth\n").unwrap();
+    Config::load().unwrap();
+    assert_eq!(
+        Registry::open()
+            .unwrap()
+            .list(Some("container"))
+            .unwrap()
+            .len(),
+        3
+    );
+
+    env::remove_var("TGF_TOKEN");
+    assert!(Config::load().is_err());
+
+    fs::remove_dir_all(&dir).ok();
+}
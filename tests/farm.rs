@@ -0,0 +1,733 @@
+mod support;
+
+use std::{
+    borrow::Cow,
+    io::{BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{mpsc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+use support::{read_command, webhook, MockServer, TIMEOUT, TOKEN};
+use twitch_gift_farm::{
+    config::Friends,
+    connector::{Capture, FakeGift, Simulation},
+    farm::{EventHandler, Farm, FarmBuilder, GiftEvent, SessionStats},
+    history::{ChannelLogs, GiftKind, Tier},
+    notify::{Notification, Sink},
+    redis::Redis,
+    registry::{Registry, Source},
+    runtime,
+    state::ChannelState,
+    Account, Config,
+};
+
+#[derive(Debug)]
+enum Event {
+    Gift(GiftEvent),
+    Join(String),
+    Reconnect,
+    Stats(SessionStats),
+}
+
+/// Passes what the bot does to the test
+struct Recorder(Mutex<mpsc::Sender<Event>>);
+
+impl EventHandler for Recorder {
+    fn on_gift(&self, event: &GiftEvent) {
+        self.0.lock().unwrap().send(Event::Gift(event.clone())).ok();
+    }
+
+    fn on_join(&self, _account: &str, channel: &str) {
+        self.0
+            .lock()
+            .unwrap()
+            .send(Event::Join(channel.to_string()))
+            .ok();
+    }
+
+    fn on_reconnect(&self, _account: &str) {
+        self.0.lock().unwrap().send(Event::Reconnect).ok();
+    }
+
+    fn on_stats(&self, stats: &SessionStats) {
+        self.0
+            .lock()
+            .unwrap()
+            .send(Event::Stats(stats.clone()))
+            .ok();
+    }
+}
+
+/// Farm `channel` as `username` against `server` in the background
+fn farm(server: &MockServer, username: &str, channel: &str) -> mpsc::Receiver<Event> {
+    farm_with(server, username, channel, |builder| builder)
+}
+
+/// Like [`farm`] but lets `configure` change the farm before it starts
+fn farm_with(
+    server: &MockServer,
+    username: &str,
+    channel: &str,
+    configure: impl FnOnce(FarmBuilder) -> FarmBuilder,
+) -> mpsc::Receiver<Event> {
+    farm_config(config(server, username, channel), configure)
+}
+
+/// The config to farm `channel` as `username` against `server`
+fn config(server: &MockServer, username: &str, channel: &str) -> Config<'static> {
+    support::init();
+
+    Registry::open()
+        .unwrap()
+        .add(Some(username), &[channel], Source::Manual)
+        .unwrap();
+
+    Config {
+        accounts: vec![Account {
+            username: Cow::Owned(username.to_string()),
+            token: Cow::Borrowed(TOKEN),
+            ..Account::default()
+        }],
+        irc: server.endpoint(),
+        ..Config::default()
+    }
+}
+
+/// Like [`farm_with`] with a custom `config`
+fn farm_config(
+    config: Config<'static>,
+    configure: impl FnOnce(FarmBuilder) -> FarmBuilder,
+) -> mpsc::Receiver<Event> {
+    let (sender, events) = mpsc::channel();
+    let farm = configure(
+        Farm::builder(config)
+            .force(true)
+            .handler(Recorder(Mutex::new(sender))),
+    )
+    .build()
+    .unwrap();
+    thread::spawn(move || runtime::block_on(farm.run()));
+
+    events
+}
+
+fn next_event(events: &mpsc::Receiver<Event>) -> Event {
+    events.recv_timeout(TIMEOUT).expect("no event from the bot")
+}
+
+#[test]
+fn joins_channels() {
+    let server = MockServer::start();
+    let events = farm(&server, "joiner", "joinchannel");
+
+    let mut client = server.accept();
+    assert_eq!(client.name, "joiner");
+    assert_eq!(client.confirm_join(), ["joinchannel"]);
+
+    match next_event(&events) {
+        Event::Join(channel) => assert_eq!(channel, "joinchannel"),
+        event => panic!("expected a join, got {:?}", event),
+    }
+}
+
+#[test]
+fn publishes_gifts_for_the_account() {
+    let server = MockServer::start();
+    let events = farm(&server, "recipient", "giftchannel");
+
+    let mut client = server.accept();
+    client.confirm_join();
+    assert!(matches!(next_event(&events), Event::Join(_)));
+
+    client.sub_gift("giftchannel", "Someone", "SomeoneElse");
+    client.sub_gift("giftchannel", "Gifter", "Recipient");
+
+    match next_event(&events) {
+        Event::Gift(gift) => {
+            assert_eq!(gift.kind, GiftKind::SubGift);
+            assert_eq!(gift.channel, "giftchannel");
+            assert_eq!(gift.gifter, "Gifter");
+            assert_eq!(gift.recipient.as_deref(), Some("recipient"));
+            assert_eq!(gift.tier, Tier::Tier1);
+            assert_eq!(gift.months, 1);
+            assert_eq!(
+                gift.tags
+                    .get("msg-param-recipient-user-name")
+                    .map(String::as_str),
+                Some("recipient")
+            );
+        }
+        event => panic!("expected a gift, got {:?}", event),
+    }
+}
+
+#[test]
+fn logs_gifts_per_channel() {
+    let server = MockServer::start();
+    let config = Config {
+        channel_logs: true,
+        ..config(&server, "chanlogger", "loggedchannel")
+    };
+    let events = farm_config(config, |builder| builder);
+
+    let mut client = server.accept();
+    client.confirm_join();
+    assert!(matches!(next_event(&events), Event::Join(_)));
+    client.sub_gift("loggedchannel", "Gifter", "ChanLogger");
+    assert!(matches!(next_event(&events), Event::Gift(_)));
+
+    // the gift is stored after it is published
+    let path = ChannelLogs::dir().join("loggedchannel.log");
+    let started = Instant::now();
+    let log = loop {
+        let log = std::fs::read_to_string(&path).unwrap_or_default();
+        if log.ends_with('\n') || started.elapsed() > TIMEOUT {
+            break log;
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+    let fields: Vec<&str> = log.trim_end().split('\t').collect();
+    assert_eq!(
+        fields[1..],
+        ["sub gift", "tier1", "1", "Gifter", "chanlogger"]
+    );
+}
+
+#[test]
+fn highlights_gifts_from_friends() {
+    let server = MockServer::start();
+    let config = Config {
+        friends: Some(Friends {
+            gifters: vec!["gifter".to_string()],
+            notifications: Vec::new(),
+        }),
+        ..config(&server, "befriended", "friendchannel")
+    };
+    let (sender, notifications) = mpsc::channel();
+    let sender = Mutex::new(sender);
+    let events = farm_config(config, |builder| {
+        builder.on_gift(move |notification| {
+            if let Notification::Gift { gift, friend, .. } = notification {
+                sender
+                    .lock()
+                    .unwrap()
+                    .send((gift.gifter.clone(), *friend))
+                    .ok();
+            }
+        })
+    });
+
+    let mut client = server.accept();
+    client.confirm_join();
+    assert!(matches!(next_event(&events), Event::Join(_)));
+    client.sub_gift("friendchannel", "Stranger", "Befriended");
+    client.sub_gift("friendchannel", "Gifter", "Befriended");
+
+    let mut gifts: Vec<_> = (0..2)
+        .map(|_| {
+            notifications
+                .recv_timeout(TIMEOUT)
+                .expect("no notification")
+        })
+        .collect();
+    gifts.sort();
+    assert_eq!(
+        gifts,
+        [
+            ("Gifter".to_string(), true),
+            ("Stranger".to_string(), false)
+        ]
+    );
+}
+
+#[test]
+fn batches_gift_notifications() {
+    let server = MockServer::start();
+    let (url, bodies) = webhook(&[200; 8]);
+    let config = Config {
+        notifications: vec![Sink::Webhook {
+            url,
+            timeout: 10,
+            retries: 0,
+            per_minute: None,
+        }],
+        // long enough that only stopping the farm sends the batch
+        notification_batch: Some(3600),
+        ..config(&server, "batched", "batchchannel")
+    };
+    let (stop, stopped) = smol::channel::bounded::<()>(1);
+    let events = farm_config(config, |builder| {
+        builder.stop_on(async move {
+            stopped.recv().await.ok();
+        })
+    });
+
+    let mut client = server.accept();
+    client.confirm_join();
+    assert!(matches!(next_event(&events), Event::Join(_)));
+    for gifter in ["First", "Second", "Third"] {
+        client.sub_gift("batchchannel", gifter, "Batched");
+        assert!(matches!(next_event(&events), Event::Gift(_)));
+    }
+    stop.try_send(()).unwrap();
+
+    // milestones are not batched
+    let body = loop {
+        let body = bodies.recv_timeout(TIMEOUT).expect("no batch");
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        if body["event"]["type"] != "milestone" {
+            break body;
+        }
+    };
+    assert_eq!(body["title"], "3 gifts");
+    assert_eq!(body["event"]["type"], "gifts");
+    assert_eq!(body["event"]["gifts"].as_array().unwrap().len(), 3);
+}
+
+#[test]
+fn keeps_milestones_in_muted_channels_quiet() {
+    let server = MockServer::start();
+    let (url, bodies) = webhook(&[200; 4]);
+    let config = Config {
+        notifications: vec![Sink::Webhook {
+            url,
+            timeout: 10,
+            retries: 0,
+            per_minute: None,
+        }],
+        muted: vec!["mutedchannel".to_string()],
+        ..config(&server, "muter", "mutedchannel")
+    };
+    let (stop, stopped) = smol::channel::bounded::<()>(1);
+    let events = farm_config(config, |builder| {
+        builder.stop_on(async move {
+            stopped.recv().await.ok();
+        })
+    });
+
+    let mut client = server.accept();
+    client.confirm_join();
+    assert!(matches!(next_event(&events), Event::Join(_)));
+    // the first gift in the channel is a milestone
+    client.sub_gift("mutedchannel", "Gifter", "Muter");
+    assert!(matches!(next_event(&events), Event::Gift(_)));
+
+    stop.try_send(()).unwrap();
+    assert!(matches!(next_event(&events), Event::Stats(_)));
+    assert!(bodies.try_recv().is_err());
+}
+
+#[test]
+fn sums_up_the_session_when_stopped() {
+    let server = MockServer::start();
+    let (stop, stopped) = smol::channel::bounded::<()>(1);
+    let events = farm_with(&server, "stopper", "stopchannel", |builder| {
+        builder.stop_on(async move {
+            stopped.recv().await.ok();
+        })
+    });
+
+    let mut client = server.accept();
+    client.confirm_join();
+    assert!(matches!(next_event(&events), Event::Join(_)));
+    client.sub_gift("stopchannel", "Gifter", "Stopper");
+    assert!(matches!(next_event(&events), Event::Gift(_)));
+
+    stop.try_send(()).unwrap();
+    match next_event(&events) {
+        Event::Stats(stats) => {
+            assert_eq!(stats.gifts, 1);
+            assert_eq!(stats.gifts_by_tier.get(&Tier::Tier1), Some(&1));
+            assert_eq!(stats.active_channels, 1);
+        }
+        event => panic!("expected the session stats, got {:?}", event),
+    }
+
+    // the gift is in the channel state without waiting for the next save
+    assert!(ChannelState::load()
+        .unwrap()
+        .channels
+        .contains_key("stopchannel"));
+}
+
+#[test]
+fn reconnects_and_joins_again() {
+    let server = MockServer::start();
+    let events = farm(&server, "reconnecter", "reconnectchannel");
+
+    let mut client = server.accept();
+    client.confirm_join();
+    assert!(matches!(next_event(&events), Event::Join(_)));
+    drop(client);
+
+    let mut client = server.accept();
+    assert_eq!(client.name, "reconnecter");
+    assert!(matches!(next_event(&events), Event::Reconnect));
+    assert_eq!(client.confirm_join(), ["reconnectchannel"]);
+    assert!(matches!(next_event(&events), Event::Join(_)));
+}
+
+#[test]
+fn captures_raw_lines() {
+    let path = std::env::temp_dir().join(format!("tgf-capture-{}.log", std::process::id()));
+    std::fs::remove_file(&path).ok();
+
+    let server = MockServer::start();
+    let events = farm_with(&server, "capturer", "capturechannel", |builder| {
+        builder.capture(&path)
+    });
+
+    let mut client = server.accept();
+    client.confirm_join();
+    assert!(matches!(next_event(&events), Event::Join(_)));
+    client.sub_gift("capturechannel", "Gifter", "Capturer");
+    assert!(matches!(next_event(&events), Event::Gift(_)));
+
+    let captured = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).ok();
+
+    let notice = captured
+        .lines()
+        .find(|line| line.contains("USERNOTICE #capturechannel"))
+        .expect("the gift was not captured");
+    assert_ne!(Capture::strip_time(notice), notice);
+    assert!(Capture::strip_time(notice).starts_with("@"));
+}
+
+#[test]
+fn drops_notices_repeated_after_a_reconnect() {
+    let server = MockServer::start();
+    let events = farm(&server, "deduplicated", "dedupchannel");
+
+    let mut client = server.accept();
+    client.confirm_join();
+    assert!(matches!(next_event(&events), Event::Join(_)));
+    client.sub_gift_with_id("dedupchannel", "Gifter", "Deduplicated", "repeated");
+    assert!(matches!(next_event(&events), Event::Gift(_)));
+    drop(client);
+
+    let mut client = server.accept();
+    assert!(matches!(next_event(&events), Event::Reconnect));
+    // the channel is not joined again while the gifted sub runs
+    client.sub_gift_with_id("dedupchannel", "Gifter", "Deduplicated", "repeated");
+    client.sub_gift_with_id("dedupchannel", "Gifter", "Deduplicated", "new");
+
+    match next_event(&events) {
+        Event::Gift(gift) => assert_eq!(
+            gift.tags.get("id").map(String::as_str),
+            Some("new"),
+            "the repeated gift was published again"
+        ),
+        event => panic!("expected a gift, got {:?}", event),
+    }
+}
+
+/// Passes the latencies the bot measures to the test
+struct Latencies(Mutex<mpsc::Sender<Duration>>);
+
+impl EventHandler for Latencies {
+    fn on_latency(&self, _account: &str, latency: Duration) {
+        self.0.lock().unwrap().send(latency).ok();
+    }
+}
+
+#[test]
+fn measures_the_latency() {
+    let server = MockServer::start();
+    let (sender, latencies) = mpsc::channel();
+    let _events = farm_with(&server, "pinger", "pingchannel", |builder| {
+        builder.handler(Latencies(Mutex::new(sender)))
+    });
+
+    let mut client = server.accept();
+    let ping = client.expect("PING ");
+    client.confirm_join();
+    thread::sleep(Duration::from_millis(50));
+    client.send(&format!(
+        ":tmi.twitch.tv PONG tmi.twitch.tv {}",
+        &ping["PING ".len()..]
+    ));
+
+    let latency = latencies
+        .recv_timeout(TIMEOUT)
+        .expect("no latency measured");
+    assert!(latency >= Duration::from_millis(50));
+}
+
+/// Send `GET path` to `port` and return the status line and the body
+fn get(port: u16, path: &str) -> (String, String) {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).expect("connect to the endpoint");
+    write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .expect("no end of the headers");
+    (head.lines().next().unwrap().to_string(), body.to_string())
+}
+
+#[test]
+fn serves_the_health_endpoint() {
+    let server = MockServer::start();
+    // a free port for the endpoint
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let config = Config {
+        health_endpoint: Some(format!("127.0.0.1:{}", port)),
+        ..config(&server, "healthy", "healthchannel")
+    };
+    let events = farm_config(config, |builder| builder);
+
+    let mut client = server.accept();
+    client.confirm_join();
+    assert!(matches!(next_event(&events), Event::Join(_)));
+
+    // a client that never sends its request does not hold up the others
+    let _idle = TcpStream::connect(("127.0.0.1", port)).unwrap();
+
+    let (status, body) = get(port, "/healthz");
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    let health: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(health["joined"], 1);
+    assert_eq!(health["configured"], 1);
+    assert_eq!(health["accounts"][0]["username"], "healthy");
+    assert_eq!(health["accounts"][0]["connected"], true);
+
+    let (status, _) = get(port, "/readyz");
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    let (status, _) = get(port, "/metrics");
+    assert_eq!(status, "HTTP/1.1 404 Not Found");
+}
+
+#[test]
+fn replays_recorded_lines() {
+    support::init();
+
+    let path = std::env::temp_dir().join(format!("tgf-replay-{}.log", std::process::id()));
+    std::fs::write(
+        &path,
+        "@display-name=Gifter;login=gifter;msg-id=subgift;msg-param-recipient-user-name=replayer;\
+         msg-param-sub-plan=2000;msg-param-gift-months=3;user-id=4 \
+         :tmi.twitch.tv USERNOTICE #replaychannel\n\
+         2024-01-02T03:04:05.678Z\t@display-name=Other;login=other;msg-id=subgift;\
+         msg-param-recipient-user-name=replayer;msg-param-sub-plan=1000;user-id=5 \
+         :tmi.twitch.tv USERNOTICE #capturedchannel\n",
+    )
+    .unwrap();
+
+    let config = Config {
+        accounts: vec![Account {
+            username: Cow::Borrowed("replayer"),
+            token: Cow::Borrowed(TOKEN),
+            ..Account::default()
+        }],
+        ..Config::default()
+    };
+    let (sender, events) = mpsc::channel();
+    let farm = Farm::builder(config)
+        .replay(&path)
+        .handler(Recorder(Mutex::new(sender)))
+        .build()
+        .unwrap();
+
+    runtime::block_on(farm.run()).unwrap();
+    std::fs::remove_file(&path).ok();
+
+    match next_event(&events) {
+        Event::Gift(gift) => {
+            assert_eq!(gift.channel, "replaychannel");
+            assert_eq!(gift.tier, Tier::Tier2);
+            assert_eq!(gift.months, 3);
+        }
+        event => panic!("expected a gift, got {:?}", event),
+    }
+    // lines written by --capture start with the time they arrived
+    match next_event(&events) {
+        Event::Gift(gift) => assert_eq!(gift.channel, "capturedchannel"),
+        event => panic!("expected a gift, got {:?}", event),
+    }
+    match next_event(&events) {
+        Event::Stats(stats) => {
+            assert_eq!(stats.user_notices, 2);
+            assert_eq!(stats.gifts, 2);
+            assert_eq!(stats.reconnects, 0);
+            assert_eq!(
+                stats.gifts_by_tier.into_iter().collect::<Vec<_>>(),
+                [(Tier::Tier1, 1), (Tier::Tier2, 1)]
+            );
+            assert_eq!(stats.active_channels, 2);
+        }
+        event => panic!("expected the session stats, got {:?}", event),
+    }
+}
+
+#[test]
+fn simulates_gifts() {
+    support::init();
+
+    let config = Config {
+        accounts: vec![Account {
+            username: Cow::Borrowed("simulated"),
+            token: Cow::Borrowed(TOKEN),
+            ..Account::default()
+        }],
+        ..Config::default()
+    };
+    let (sender, events) = mpsc::channel();
+    let farm = Farm::builder(config)
+        .simulate(Simulation {
+            rate: 600.0,
+            channels: 2,
+        })
+        .handler(Recorder(Mutex::new(sender)))
+        .build()
+        .unwrap();
+    thread::spawn(move || runtime::block_on(farm.run()));
+
+    let mut channels = Vec::new();
+    for _ in 0..2 {
+        match next_event(&events) {
+            Event::Gift(gift) => {
+                assert_eq!(gift.recipient.as_deref(), Some("simulated"));
+                channels.push(gift.channel);
+            }
+            event => panic!("expected a gift, got {:?}", event),
+        }
+    }
+    assert_eq!(channels, ["simulated1", "simulated2"]);
+}
+
+#[test]
+fn simulates_no_gifts_at_a_tiny_rate() {
+    support::init();
+
+    let config = Config {
+        accounts: vec![Account {
+            username: Cow::Borrowed("slow"),
+            token: Cow::Borrowed(TOKEN),
+            ..Account::default()
+        }],
+        ..Config::default()
+    };
+    let (sender, events) = mpsc::channel();
+    let farm = Farm::builder(config)
+        .simulate(Simulation {
+            // the gap between the gifts does not fit in a `Duration`
+            rate: 1e-30,
+            channels: 1,
+        })
+        .handler(Recorder(Mutex::new(sender)))
+        .stop_on(async { runtime::sleep(Duration::from_millis(500)).await })
+        .build()
+        .unwrap();
+
+    runtime::block_on(farm.run()).unwrap();
+    assert!(!events
+        .try_iter()
+        .any(|event| matches!(event, Event::Gift(_))));
+}
+
+#[test]
+fn reaches_no_milestones_with_injected_gifts() {
+    support::init();
+
+    let (url, bodies) = webhook(&[200; 4]);
+    let config = Config {
+        accounts: vec![Account {
+            username: Cow::Borrowed("milestoneless"),
+            token: Cow::Borrowed(TOKEN),
+            ..Account::default()
+        }],
+        notifications: vec![Sink::Webhook {
+            url,
+            timeout: 10,
+            retries: 0,
+            per_minute: None,
+        }],
+        ..Config::default()
+    };
+    // the first gift in a channel and the first tier 3 gift are milestones
+    let line = FakeGift {
+        gifter: Some("Gifter"),
+        recipient: "Milestoneless",
+        channel: "#MilestoneChannel",
+        plan: "3000",
+        months: 1,
+        id: "milestoneless-1",
+    }
+    .line();
+    let farm = Farm::builder(config).inject(line).build().unwrap();
+
+    runtime::block_on(farm.run()).unwrap();
+
+    let body = bodies.recv_timeout(TIMEOUT).expect("no notification");
+    let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(body["event"]["type"], "gift");
+    assert!(bodies.try_recv().is_err());
+}
+
+#[test]
+fn injects_fake_gifts() {
+    support::init();
+
+    // injected gifts are published like real ones
+    let redis = TcpListener::bind("127.0.0.1:0").unwrap();
+    let config = Config {
+        accounts: vec![Account {
+            username: Cow::Borrowed("injected"),
+            token: Cow::Borrowed(TOKEN),
+            ..Account::default()
+        }],
+        redis: Some(Redis {
+            address: redis.local_addr().unwrap().to_string(),
+            username: None,
+            password: None,
+            channel: "gifts".to_string(),
+        }),
+        ..Config::default()
+    };
+    let (published, publishes) = mpsc::channel();
+    thread::spawn(move || {
+        let (stream, _) = redis.accept().unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        let mut reader = BufReader::new(stream);
+        published.send(read_command(&mut reader)).unwrap();
+        writer.write_all(b":1\r\n").unwrap();
+    });
+    let line = FakeGift {
+        gifter: Some("Gifter"),
+        recipient: "Injected",
+        channel: "#InjectChannel",
+        plan: "3000",
+        months: 6,
+        id: "injected-1",
+    }
+    .line();
+    let (sender, events) = mpsc::channel();
+    let farm = Farm::builder(config)
+        .inject(line)
+        .handler(Recorder(Mutex::new(sender)))
+        .build()
+        .unwrap();
+
+    runtime::block_on(farm.run()).unwrap();
+
+    match next_event(&events) {
+        Event::Gift(gift) => {
+            assert_eq!(gift.channel, "injectchannel");
+            assert_eq!(gift.gifter, "Gifter");
+            assert_eq!(gift.tier, Tier::Tier3);
+            assert_eq!(gift.months, 6);
+        }
+        event => panic!("expected a gift, got {:?}", event),
+    }
+
+    let publish = publishes.recv_timeout(TIMEOUT).unwrap();
+    assert_eq!(publish[..2], ["PUBLISH", "gifts"]);
+    assert!(publish[2].contains("\"gifter\":\"Gifter\""));
+}
@@ -0,0 +1,93 @@
+use std::{
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    thread,
+};
+use twitch_gift_farm::{
+    history::{Gift, GiftKind, Tier},
+    mqtt::{Mqtt, Publisher},
+    runtime,
+    value::Value,
+};
+
+/// The first byte and the body of the next packet
+fn read_packet(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+    let mut byte = [0];
+    stream.read_exact(&mut byte).unwrap();
+    let kind = byte[0];
+
+    let (mut length, mut shift) = (0, 0);
+    loop {
+        stream.read_exact(&mut byte).unwrap();
+        length |= ((byte[0] & 0x7f) as usize) << shift;
+        shift += 7;
+        if byte[0] & 0x80 == 0 {
+            break;
+        }
+    }
+
+    let mut body = vec![0; length];
+    stream.read_exact(&mut body).unwrap();
+    (kind, body)
+}
+
+/// The topic and payload of a PUBLISH
+fn publish(stream: &mut TcpStream) -> (String, String) {
+    let (kind, body) = read_packet(stream);
+    assert_eq!(kind & 0xf0, 0x30, "expected a PUBLISH");
+
+    let length = u16::from_be_bytes([body[0], body[1]]) as usize;
+    let topic = String::from_utf8(body[2..2 + length].to_vec()).unwrap();
+    let payload = String::from_utf8(body[2 + length..].to_vec()).unwrap();
+    (topic, payload)
+}
+
+#[test]
+fn publishes_discovery_and_gifts() {
+    let broker = TcpListener::bind("127.0.0.1:0").unwrap();
+    let publisher = Publisher::new(Mqtt {
+        broker: broker.local_addr().unwrap().to_string(),
+        username: Some("user".to_string()),
+        password: Some("secret".to_string()),
+        client_id: "farm".to_string(),
+        topic: "gifts".to_string(),
+        discovery_prefix: Some("homeassistant".to_string()),
+    });
+    let gift = Gift {
+        tier: Tier::Tier3,
+        ..Gift::new("account", "#channel", "Gifter", GiftKind::SubGift)
+    };
+    publisher.gift(
+        &gift,
+        &Value {
+            amount: 24.99,
+            currency: "USD".to_string(),
+        },
+    );
+    thread::spawn(move || runtime::block_on(publisher.run()));
+
+    let (mut stream, _) = broker.accept().unwrap();
+    let (kind, connect) = read_packet(&mut stream);
+    assert_eq!(kind, 0x10, "expected a CONNECT");
+    assert!(connect.ends_with(b"\0\x04user\0\x06secret"));
+    stream.write_all(&[0x20, 2, 0, 0]).unwrap();
+
+    assert_eq!(
+        publish(&mut stream),
+        ("gifts/status".to_string(), "online".to_string())
+    );
+    let mut topics = Vec::new();
+    let event = loop {
+        let (topic, payload) = publish(&mut stream);
+        if topic == "gifts/gift" {
+            break payload;
+        }
+        topics.push(topic);
+    };
+    assert!(topics.contains(&"homeassistant/event/farm/gift/config".to_string()));
+    assert!(topics.contains(&"homeassistant/binary_sensor/farm/live/config".to_string()));
+
+    let event: serde_json::Value = serde_json::from_str(&event).unwrap();
+    assert_eq!(event["event_type"], "tier3");
+    assert_eq!(event["gifter"], "Gifter");
+}
@@ -0,0 +1,264 @@
+mod support;
+
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+use support::{http_server, webhook, TIMEOUT};
+use twitch_gift_farm::{
+    history::{Gift, GiftKind, Tier},
+    notify::{Notification, Notifier, Priorities, Sink},
+    runtime,
+    value::Value,
+};
+
+fn banned() -> Notification {
+    Notification::Banned {
+        account: "account".to_string(),
+        channel: "channel".to_string(),
+    }
+}
+
+#[test]
+fn retries_failed_webhooks() {
+    let (url, bodies) = webhook(&[500, 200]);
+    let notifier = Notifier::new(vec![Sink::Webhook {
+        url,
+        timeout: 5,
+        retries: 1,
+        per_minute: None,
+    }])
+    .unwrap();
+
+    runtime::block_on(notifier.notify(&banned()));
+
+    let bodies: Vec<String> = bodies.try_iter().collect();
+    assert_eq!(bodies.len(), 2);
+    assert!(bodies[1].contains("Banned from channel"));
+}
+
+#[test]
+fn slow_webhooks_do_not_hold_up_the_others() {
+    let (slow, _) = webhook(&[0]);
+    let (fast, bodies) = webhook(&[200]);
+    let notifier = Notifier::new(vec![
+        Sink::Webhook {
+            url: slow,
+            timeout: 2,
+            retries: 0,
+            per_minute: None,
+        },
+        Sink::Webhook {
+            url: fast,
+            timeout: 2,
+            retries: 0,
+            per_minute: None,
+        },
+    ])
+    .unwrap();
+
+    let start = Instant::now();
+    let notifying = thread::spawn(move || runtime::block_on(notifier.notify(&banned())));
+
+    assert!(
+        bodies.recv_timeout(Duration::from_secs(1)).is_ok(),
+        "the second webhook waited for the first"
+    );
+    notifying.join().unwrap();
+    assert!(
+        start.elapsed() < Duration::from_secs(4),
+        "the timeout did not apply"
+    );
+}
+
+#[test]
+fn holds_back_notifications_over_the_rate_limit() {
+    let (url, bodies) = webhook(&[200; 3]);
+    let notifier = Notifier::new(vec![Sink::Webhook {
+        url,
+        timeout: 2,
+        retries: 0,
+        per_minute: Some(1),
+    }])
+    .unwrap()
+    .rate_window(Duration::from_secs(1));
+
+    runtime::block_on(async {
+        let start = Instant::now();
+        for _ in 0..3 {
+            notifier.notify(&banned()).await;
+        }
+        assert!(
+            start.elapsed() < Duration::from_millis(500),
+            "waited for the rate limit"
+        );
+
+        // the summary is sent in the background once the window is over
+        runtime::sleep(Duration::from_secs(2)).await;
+    });
+
+    let first: serde_json::Value = serde_json::from_str(&bodies.recv().unwrap()).unwrap();
+    assert_eq!(first["event"]["type"], "banned");
+    let summary: serde_json::Value =
+        serde_json::from_str(&bodies.recv_timeout(TIMEOUT).unwrap()).unwrap();
+    assert_eq!(summary["event"]["type"], "held_back");
+    assert_eq!(summary["event"]["titles"].as_array().unwrap().len(), 2);
+}
+
+#[test]
+fn flushes_held_back_notifications() {
+    let (url, bodies) = webhook(&[200; 2]);
+    let notifier = Notifier::new(vec![Sink::Webhook {
+        url,
+        timeout: 2,
+        retries: 0,
+        per_minute: Some(1),
+    }])
+    .unwrap();
+
+    runtime::block_on(async {
+        notifier.notify(&banned()).await;
+        notifier.notify(&banned()).await;
+        notifier.flush().await;
+    });
+
+    bodies.recv().unwrap();
+    let summary: serde_json::Value = serde_json::from_str(&bodies.recv().unwrap()).unwrap();
+    assert_eq!(summary["event"]["type"], "held_back");
+}
+
+#[test]
+fn publishes_to_ntfy() {
+    let (server, bodies) = webhook(&[200]);
+    let notifier = Notifier::new(vec![Sink::Ntfy {
+        server,
+        topic: "gifts".to_string(),
+        token: None,
+        priorities: Priorities {
+            tier1: 3,
+            tier2: 3,
+            tier3: 4,
+            other: 2,
+        },
+        tags: vec!["farm".to_string()],
+        timeout: 5,
+        retries: 0,
+        per_minute: None,
+    }])
+    .unwrap();
+
+    runtime::block_on(notifier.notify(&banned()));
+
+    let body: serde_json::Value = serde_json::from_str(&bodies.recv().unwrap()).unwrap();
+    assert_eq!(body["topic"], "gifts");
+    assert_eq!(body["title"], "Banned from channel");
+    assert_eq!(body["priority"], 2);
+    assert_eq!(body["tags"], serde_json::json!(["farm", "warning"]));
+}
+
+#[test]
+fn sends_to_gotify() {
+    let (server, bodies) = webhook(&[200]);
+    let notifier = Notifier::new(vec![Sink::Gotify {
+        server,
+        token: "token".to_string(),
+        priorities: Priorities {
+            tier1: 5,
+            tier2: 5,
+            tier3: 8,
+            other: 12,
+        },
+        timeout: 5,
+        retries: 0,
+        per_minute: None,
+    }])
+    .unwrap();
+
+    runtime::block_on(notifier.notify(&banned()));
+
+    let body: serde_json::Value = serde_json::from_str(&bodies.recv().unwrap()).unwrap();
+    assert_eq!(body["title"], "Banned from channel");
+    assert_eq!(body["priority"], 10);
+}
+
+#[test]
+fn posts_gifts_to_slack_with_blocks() {
+    let (url, bodies) = webhook(&[200]);
+    let notifier = Notifier::new(vec![Sink::Slack {
+        url,
+        timeout: 5,
+        retries: 0,
+        per_minute: None,
+    }])
+    .unwrap();
+    let gift = Gift {
+        tier: Tier::Tier2,
+        ..Gift::new("account", "#channel", "Gifter", GiftKind::SubGift)
+    };
+
+    runtime::block_on(notifier.notify(&Notification::Gift {
+        gift,
+        value: Value {
+            amount: 9.99,
+            currency: "USD".to_string(),
+        },
+        friend: false,
+    }));
+
+    let body: serde_json::Value = serde_json::from_str(&bodies.recv().unwrap()).unwrap();
+    let blocks = body["blocks"].as_array().unwrap();
+    assert_eq!(blocks[0]["text"]["text"], "tier2 gift in channel");
+    assert_eq!(blocks[2]["fields"][2]["text"], "*Gifter*\nGifter");
+    assert_eq!(blocks[2]["fields"][5]["text"], "*Value*\n9.99 USD");
+}
+
+#[test]
+fn posts_to_a_matrix_room() {
+    let (homeserver, bodies) = webhook(&[200]);
+    let notifier = Notifier::new(vec![Sink::Matrix {
+        homeserver,
+        token: "token".to_string(),
+        room: "!room:example.org".to_string(),
+        timeout: 5,
+        retries: 0,
+        per_minute: None,
+    }])
+    .unwrap();
+
+    runtime::block_on(notifier.notify(&Notification::Banned {
+        account: "account".to_string(),
+        channel: "<channel>".to_string(),
+    }));
+
+    let body: serde_json::Value = serde_json::from_str(&bodies.recv().unwrap()).unwrap();
+    assert_eq!(body["msgtype"], "m.text");
+    assert_eq!(
+        body["formatted_body"],
+        "<strong>Banned from &lt;channel&gt;</strong><br>account is banned from &lt;channel&gt; \
+         and won't join it again"
+    );
+}
+
+#[test]
+fn retries_matrix_messages_with_the_same_transaction() {
+    let (homeserver, requests) = http_server(&[500, 200]);
+    let notifier = Notifier::new(vec![Sink::Matrix {
+        homeserver,
+        token: "token".to_string(),
+        room: "!room:example.org".to_string(),
+        timeout: 5,
+        retries: 1,
+        per_minute: None,
+    }])
+    .unwrap();
+
+    runtime::block_on(notifier.notify(&Notification::Banned {
+        account: "account".to_string(),
+        channel: "channel".to_string(),
+    }));
+
+    let (first, _) = requests.recv().unwrap();
+    let (retry, _) = requests.recv().unwrap();
+    assert!(first.starts_with("PUT /_matrix/client/v3/rooms/"));
+    assert_eq!(first, retry);
+}
@@ -0,0 +1,46 @@
+mod support;
+
+use std::{
+    io::{BufReader, Write},
+    net::TcpListener,
+    thread,
+};
+use support::read_command;
+use twitch_gift_farm::{
+    history::{Gift, GiftKind},
+    notify::Notification,
+    redis::{Publisher, Redis},
+    runtime,
+};
+
+#[test]
+fn publishes_gift_events() {
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let publisher = Publisher::new(Redis {
+        address: server.local_addr().unwrap().to_string(),
+        username: None,
+        password: Some("secret".to_string()),
+        channel: "gifts".to_string(),
+    });
+    publisher.publish(&Notification::Upgrade(Gift::new(
+        "account",
+        "#channel",
+        "Gifter",
+        GiftKind::GiftPaidUpgrade,
+    )));
+    thread::spawn(move || runtime::block_on(publisher.run()));
+
+    let (stream, _) = server.accept().unwrap();
+    let mut writer = stream.try_clone().unwrap();
+    let mut reader = BufReader::new(stream);
+
+    assert_eq!(read_command(&mut reader), ["AUTH", "secret"]);
+    writer.write_all(b"+OK\r\n").unwrap();
+
+    let publish = read_command(&mut reader);
+    assert_eq!(publish[..2], ["PUBLISH", "gifts"]);
+    let event: serde_json::Value = serde_json::from_str(&publish[2]).unwrap();
+    assert_eq!(event["type"], "upgrade");
+    assert_eq!(event["gifter"], "Gifter");
+    writer.write_all(b":1\r\n").unwrap();
+}
@@ -0,0 +1,110 @@
+use chrono::Utc;
+use std::{borrow::Cow, collections::BTreeMap, env, process, sync::Once};
+use twitch_gift_farm::{
+    registry::{Registry, Source},
+    Account, Config,
+};
+
+/// Keep the registry of the tests away from the real one, every test of the binary shares it
+fn init() {
+    static INIT: Once = Once::new();
+
+    INIT.call_once(|| {
+        env::remove_var("HOME");
+        env::set_var(
+            "TGF_DIR",
+            env::temp_dir().join(format!("tgf-registry-{}", process::id())),
+        );
+    });
+}
+
+#[test]
+fn tracks_liveness_and_bans() {
+    init();
+
+    let mut registry = Registry::open().unwrap();
+    registry
+        .add(
+            None,
+            &["added".to_string(), "checked".to_string()],
+            Source::Manual,
+        )
+        .unwrap();
+
+    // nothing is known about channels that were only added
+    assert!(registry.stale(0).unwrap().is_empty());
+
+    registry.checked(&["checked"]).unwrap();
+    let stale = registry.stale(0).unwrap();
+    assert_eq!(
+        stale,
+        [("checked".to_string(), "offline for 0 days".to_string())]
+    );
+
+    let revision = registry.revision().unwrap();
+    registry.live(&["checked"]).unwrap();
+    assert!(registry.stale(1).unwrap().is_empty());
+    registry.gift("checked", Utc::now()).unwrap();
+    assert_eq!(registry.revision().unwrap(), revision);
+
+    // bans don't take the own channels of an account away
+    registry
+        .add(Some("account"), &["own"], Source::Manual)
+        .unwrap();
+    assert!(!registry.ban("account", "own").unwrap());
+    assert!(registry.ban("account", "added").unwrap());
+    assert_eq!(registry.list(Some("account")).unwrap(), ["own"]);
+    assert_eq!(registry.banned("account").unwrap(), ["added"]);
+    assert!(registry.revision().unwrap() > revision);
+}
+
+#[test]
+fn assigns_shared_channels_to_replicas() {
+    init();
+
+    let config = Config {
+        accounts: ["first", "second", "third", "fourth"]
+            .iter()
+            .map(|username| Account {
+                username: Cow::Borrowed(*username),
+                ..Account::default()
+            })
+            .collect(),
+        replicas: 2,
+        ..Config::default()
+    };
+    // the other tests add shared channels of their own
+    let ours = |name: &String| name.starts_with("replicated");
+    let assignment = |registry: &Registry| {
+        let mut accounts: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+        for index in 0..config.accounts.len() {
+            for channel in registry.channels_for(&config, index).unwrap() {
+                if ours(&channel) {
+                    accounts.entry(channel).or_default().push(index);
+                }
+            }
+        }
+        accounts
+    };
+
+    let mut registry = Registry::open().unwrap();
+    let channels: Vec<String> = (0..50).map(|n| format!("replicated{}", n)).collect();
+    registry.add(None, &channels, Source::Manual).unwrap();
+
+    let before = assignment(&registry);
+    assert_eq!(before.len(), channels.len());
+    for (channel, accounts) in &before {
+        assert_eq!(accounts.len(), config.replicas, "{}", channel);
+    }
+
+    // adding a channel does not move the others
+    registry
+        .add(None, &["replicatednew"], Source::Manual)
+        .unwrap();
+    let mut after = assignment(&registry);
+    assert_eq!(
+        after.remove("replicatednew").unwrap().len(),
+        config.replicas
+    );
+    assert_eq!(after, before);
+}
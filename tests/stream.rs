@@ -0,0 +1,84 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::TcpListener,
+    thread,
+};
+use twitch_gift_farm::{
+    farm::EventHandler,
+    history::{Gift, GiftKind},
+    notify::Notification,
+    runtime,
+    stream::{Format, Producer, Stream, Target},
+};
+
+fn read_line(reader: &mut impl BufRead) -> String {
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    line.trim_end().to_string()
+}
+
+#[test]
+fn publishes_events_to_nats() {
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let producer = Producer::new(Stream {
+        target: Target::Nats {
+            server: server.local_addr().unwrap().to_string(),
+            subject: "farm".to_string(),
+            token: Some("token".to_string()),
+            user: None,
+            password: None,
+        },
+        format: Format::MessagePack,
+    });
+    let notification = Notification::PayForward(Gift::new(
+        "account",
+        "#channel",
+        "Gifter",
+        GiftKind::StandardPayForward,
+    ));
+    producer.publish(notification.kind(), &notification);
+    producer.on_join("account", "channel");
+    thread::spawn(move || runtime::block_on(producer.run()));
+
+    let (stream, _) = server.accept().unwrap();
+    let mut writer = stream.try_clone().unwrap();
+    let mut reader = BufReader::new(stream);
+    writer
+        .write_all(b"INFO {\"server_id\":\"test\"}\r\n")
+        .unwrap();
+
+    let connect: serde_json::Value =
+        serde_json::from_str(read_line(&mut reader).strip_prefix("CONNECT ").unwrap()).unwrap();
+    assert_eq!(connect["auth_token"], "token");
+    assert_eq!(read_line(&mut reader), "PING");
+    writer.write_all(b"PONG\r\n").unwrap();
+
+    let (subject, event) = read_publish(&mut reader);
+    assert_eq!(subject, "farm.pay_forward");
+    assert_eq!(event["type"], "pay_forward");
+    assert_eq!(event["gifter"], "Gifter");
+
+    // what the bots do is streamed as well
+    let (subject, event) = read_publish(&mut reader);
+    assert_eq!(subject, "farm.join");
+    assert_eq!(event["account"], "account");
+    assert_eq!(event["channel"], "channel");
+}
+
+/// Read a PUB with a MessagePack payload
+fn read_publish(reader: &mut impl BufRead) -> (String, serde_json::Value) {
+    let publish = read_line(reader);
+    let (subject, length) = publish
+        .strip_prefix("PUB ")
+        .unwrap()
+        .split_once(' ')
+        .unwrap();
+    let mut payload = vec![0; length.parse::<usize>().unwrap() + 2];
+    reader.read_exact(&mut payload).unwrap();
+    payload.truncate(payload.len() - 2);
+
+    (
+        subject.to_string(),
+        rmp_serde::from_slice(&payload).unwrap(),
+    )
+}
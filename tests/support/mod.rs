@@ -0,0 +1,297 @@
+// A chat server speaking just enough of the Twitch IRC protocol to run the bots against it, a
+// webhook to send notifications to and a reader for Redis commands. Not every test binary uses all
+// of it.
+#![allow(dead_code)]
+
+use std::{
+    env,
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc, Once,
+    },
+    thread,
+    time::Duration,
+};
+use twitch_gift_farm::connector::Endpoint;
+
+/// How long to wait for the bot before failing the test
+pub const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A token the farm accepts as well formed
+pub const TOKEN: &str = "oauth:0123456789abcdefghijklmnopqrst";
+
+/// Keep the config and data of the tests away from the real ones.
+///
+/// The paths are read once per process, so every test of a binary shares them.
+pub fn init() {
+    static INIT: Once = Once::new();
+
+    INIT.call_once(|| {
+        let dir = env::temp_dir().join(format!("tgf-test-{}", std::process::id()));
+        env::set_var("HOME", &dir);
+        env::set_var("XDG_CONFIG_HOME", dir.join("config"));
+        env::set_var("XDG_DATA_HOME", dir.join("data"));
+        env::set_var("XDG_CACHE_HOME", dir.join("cache"));
+    });
+}
+
+pub struct MockServer {
+    port: u16,
+    connections: mpsc::Receiver<TcpStream>,
+}
+
+impl MockServer {
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind mock server");
+        let port = listener.local_addr().unwrap().port();
+
+        let (sender, connections) = mpsc::channel();
+        thread::spawn(move || {
+            // stops once the server is dropped
+            for stream in listener.incoming().flatten() {
+                if sender.send(stream).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Self { port, connections }
+    }
+
+    /// The endpoint to put in the config of the farm
+    pub fn endpoint(&self) -> Endpoint {
+        Endpoint {
+            host: "127.0.0.1".to_string(),
+            port: Some(self.port),
+            tls: false,
+        }
+    }
+
+    /// Wait for the next connection and log it in
+    pub fn accept(&self) -> Client {
+        let stream = self
+            .connections
+            .recv_timeout(TIMEOUT)
+            .expect("no connection to the mock server");
+        stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+
+        let mut client = Client {
+            name: String::new(),
+            reader: BufReader::new(stream.try_clone().unwrap()),
+            writer: stream,
+        };
+        client.handshake();
+        client
+    }
+}
+
+/// A connection of a bot to the [`MockServer`]
+pub struct Client {
+    pub name: String,
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Client {
+    pub fn read_line(&mut self) -> String {
+        let mut line = String::new();
+        let read = self
+            .reader
+            .read_line(&mut line)
+            .expect("the bot did not send anything");
+        assert!(read > 0, "the bot closed the connection");
+
+        line.trim_end().to_string()
+    }
+
+    /// Read lines until one starts with `prefix`, answering pings on the way
+    pub fn expect(&mut self, prefix: &str) -> String {
+        loop {
+            let line = self.read_line();
+            if line.starts_with(prefix) {
+                return line;
+            }
+            if let Some(token) = line.strip_prefix("PING ") {
+                self.send(&format!(":tmi.twitch.tv PONG tmi.twitch.tv {}", token));
+            }
+        }
+    }
+
+    pub fn send(&mut self, line: &str) {
+        write!(self.writer, "{}\r\n", line).expect("send to the bot");
+    }
+
+    fn handshake(&mut self) {
+        let nick = self.expect("NICK ");
+        self.name = nick["NICK ".len()..].to_string();
+
+        let name = self.name.clone();
+        for cap in &["twitch.tv/tags", "twitch.tv/commands"] {
+            self.send(&format!(":tmi.twitch.tv CAP * ACK :{}", cap));
+        }
+        self.send(&format!(":tmi.twitch.tv 001 {} :Welcome, GLHF!", name));
+        self.send(&format!(":tmi.twitch.tv 376 {} :>", name));
+        self.send(&format!(
+            "@badge-info=;badges=;color=;display-name={};emote-sets=0;user-id=1;user-type= \
+             :tmi.twitch.tv GLOBALUSERSTATE",
+            name
+        ));
+    }
+
+    /// Wait for the bot to join channels and confirm the joins
+    pub fn confirm_join(&mut self) -> Vec<String> {
+        let line = self.expect("JOIN ");
+        let channels: Vec<String> = line["JOIN ".len()..]
+            .split(',')
+            .map(|channel| channel.trim_start_matches('#').to_string())
+            .collect();
+
+        let name = self.name.clone();
+        for channel in &channels {
+            self.send(&format!(
+                ":{name}!{name}@{name}.tmi.twitch.tv JOIN #{channel}",
+                name = name,
+                channel = channel
+            ));
+        }
+
+        channels
+    }
+
+    /// Announce an event in `channel` like Twitch does
+    pub fn user_notice(&mut self, channel: &str, tags: &[(&str, &str)]) {
+        let tags: Vec<String> = tags
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+        self.send(&format!(
+            "@{} :tmi.twitch.tv USERNOTICE #{}",
+            tags.join(";"),
+            channel
+        ));
+    }
+
+    /// Announce a sub that `gifter` gifted to `recipient` in `channel`
+    pub fn sub_gift(&mut self, channel: &str, gifter: &str, recipient: &str) {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+        let id = format!(
+            "00000000-0000-0000-0000-{:012}",
+            NEXT_ID.fetch_add(1, Ordering::Relaxed)
+        );
+        self.sub_gift_with_id(channel, gifter, recipient, &id);
+    }
+
+    /// Like [`sub_gift`](Self::sub_gift) with the message id `id`
+    pub fn sub_gift_with_id(&mut self, channel: &str, gifter: &str, recipient: &str, id: &str) {
+        self.user_notice(
+            channel,
+            &[
+                ("badge-info", ""),
+                ("badges", ""),
+                ("display-name", gifter),
+                ("id", id),
+                ("login", &gifter.to_lowercase()),
+                ("msg-id", "subgift"),
+                ("msg-param-gift-months", "1"),
+                ("msg-param-months", "1"),
+                ("msg-param-recipient-display-name", recipient),
+                ("msg-param-recipient-id", "2"),
+                ("msg-param-recipient-user-name", &recipient.to_lowercase()),
+                ("msg-param-sub-plan", "1000"),
+                ("msg-param-sub-plan-name", "Channel\\sSub"),
+                ("room-id", "3"),
+                ("system-msg", "a\\sgift"),
+                ("tmi-sent-ts", "0"),
+                ("user-id", "4"),
+                ("user-type", ""),
+            ],
+        );
+    }
+}
+
+/// Answer every request to the returned URL with the next of `statuses` and pass the bodies to
+/// the receiver. A status of 0 never answers.
+pub fn webhook(statuses: &'static [u16]) -> (String, mpsc::Receiver<String>) {
+    serve_http(statuses, |_, body| body)
+}
+
+/// Like [`webhook`] but passes the request line, like `PUT /path HTTP/1.1`, with the body
+pub fn http_server(statuses: &'static [u16]) -> (String, mpsc::Receiver<(String, String)>) {
+    serve_http(statuses, |request, body| (request, body))
+}
+
+fn serve_http<T: Send + 'static>(
+    statuses: &'static [u16],
+    received: fn(String, String) -> T,
+) -> (String, mpsc::Receiver<T>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    let (sender, requests) = mpsc::channel();
+
+    thread::spawn(move || {
+        let mut open = Vec::new();
+        for (stream, &status) in listener.incoming().flatten().zip(statuses) {
+            let mut stream = BufReader::new(stream);
+            let mut request = String::new();
+            stream.read_line(&mut request).unwrap();
+            let body = read_body(&mut stream);
+            sender
+                .send(received(request.trim_end().to_string(), body))
+                .ok();
+
+            let mut stream = stream.into_inner();
+            if status == 0 {
+                open.push(stream);
+                continue;
+            }
+            write!(
+                stream,
+                "HTTP/1.1 {} Whatever\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                status
+            )
+            .unwrap();
+        }
+    });
+
+    (url, requests)
+}
+
+fn read_body(stream: &mut BufReader<TcpStream>) -> String {
+    let mut length = 0;
+    loop {
+        let mut line = String::new();
+        stream.read_line(&mut line).unwrap();
+        if line == "\r\n" {
+            break;
+        }
+        if let Some(value) = line.to_lowercase().strip_prefix("content-length:") {
+            length = value.trim().parse().unwrap();
+        }
+    }
+
+    let mut body = vec![0; length];
+    stream.read_exact(&mut body).unwrap();
+    String::from_utf8(body).unwrap()
+}
+
+/// The arguments of the next command
+pub fn read_command(reader: &mut impl BufRead) -> Vec<String> {
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    let count: usize = line.trim_end().strip_prefix('*').unwrap().parse().unwrap();
+
+    (0..count)
+        .map(|_| {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            let length: usize = line.trim_end().strip_prefix('$').unwrap().parse().unwrap();
+            let mut arg = vec![0; length + 2];
+            reader.read_exact(&mut arg).unwrap();
+            arg.truncate(length);
+            String::from_utf8(arg).unwrap()
+        })
+        .collect()
+}
@@ -0,0 +1,32 @@
+#![cfg(unix)]
+
+use std::{env, os::unix::net::UnixDatagram, process, time::Duration};
+use twitch_gift_farm::systemd;
+
+// the environment is shared by the whole process, so everything is checked in one test
+#[test]
+fn notifies_systemd() {
+    assert!(!systemd::enabled());
+    systemd::notify("READY=1").unwrap();
+
+    let path = env::temp_dir().join(format!("tgf-notify-{}.sock", process::id()));
+    let socket = UnixDatagram::bind(&path).unwrap();
+    socket
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    env::set_var("NOTIFY_SOCKET", &path);
+
+    assert!(systemd::enabled());
+    systemd::notify("READY=1").unwrap();
+    let mut buffer = [0; 64];
+    let read = socket.recv(&mut buffer).unwrap();
+    assert_eq!(&buffer[..read], b"READY=1");
+
+    assert_eq!(systemd::watchdog(), None);
+    env::set_var("WATCHDOG_USEC", "30000000");
+    assert_eq!(systemd::watchdog(), Some(Duration::from_secs(30)));
+    env::set_var("WATCHDOG_PID", "1");
+    assert_eq!(systemd::watchdog(), None);
+
+    std::fs::remove_file(&path).ok();
+}